@@ -0,0 +1,394 @@
+use std::{env, fmt, fs, str::FromStr};
+
+use anyhow::{anyhow, Context, Error};
+use serde::{Deserialize, Serialize};
+
+// file picked up by OrbitConfig::load, every field can also be set through ORBIT_* variables
+pub const CONFIG_PATH_VAR: &str = "ORBIT_CONFIG";
+const ENV_PREFIX: &str = "ORBIT_";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OrbitEnvironment {
+    #[default]
+    Mainnet,
+    Testnet,
+}
+
+impl FromStr for OrbitEnvironment {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mainnet" => Ok(OrbitEnvironment::Mainnet),
+            "testnet" => Ok(OrbitEnvironment::Testnet),
+            other => Err(anyhow!(
+                "unknown environment {other}, expected mainnet or testnet"
+            )),
+        }
+    }
+}
+
+// deribit book channels come either aggregated every 100ms or as every single change, raw
+// tickers and trades are only streamed to authorized connections
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrbitBookInterval {
+    #[serde(rename = "raw")]
+    Raw,
+    #[default]
+    #[serde(rename = "100ms")]
+    Ms100,
+}
+
+impl OrbitBookInterval {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrbitBookInterval::Raw => "raw",
+            OrbitBookInterval::Ms100 => "100ms",
+        }
+    }
+}
+
+impl fmt::Display for OrbitBookInterval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for OrbitBookInterval {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(OrbitBookInterval::Raw),
+            "100ms" => Ok(OrbitBookInterval::Ms100),
+            other => Err(anyhow!(
+                "unknown book interval {other}, expected raw or 100ms"
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeltaConfig {
+    pub rest_url: String,
+    pub ws_url: String,
+    // seconds between server heartbeats, the stream reconnects when one is late
+    pub heartbeat_interval: u64,
+    // l2_orderbook refuses subscriptions with more than 20 symbols
+    pub chunk_size: usize,
+}
+
+impl DeltaConfig {
+    pub fn new(environment: OrbitEnvironment) -> Self {
+        let (rest_url, ws_url) = match environment {
+            OrbitEnvironment::Mainnet => {
+                ("https://api.delta.exchange", "wss://socket.delta.exchange")
+            }
+            OrbitEnvironment::Testnet => (
+                "https://testnet-api.delta.exchange",
+                "wss://testnet-socket.delta.exchange",
+            ),
+        };
+        Self {
+            rest_url: rest_url.to_string(),
+            ws_url: ws_url.to_string(),
+            heartbeat_interval: 30,
+            chunk_size: 20,
+        }
+    }
+}
+
+impl Default for DeltaConfig {
+    fn default() -> Self {
+        Self::new(OrbitEnvironment::default())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeribitConfig {
+    pub rest_url: String,
+    pub ws_url: String,
+    // seconds, sent with public/set_heartbeat
+    pub heartbeat_interval: u64,
+    pub book_interval: OrbitBookInterval,
+    // instruments per websocket connection, each one subscribes its book, ticker and trades
+    pub chunk_size: usize,
+    // api key the connections authorize with, tickers and trades stay at 100ms without one
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+}
+
+impl DeribitConfig {
+    pub fn new(environment: OrbitEnvironment) -> Self {
+        let host = match environment {
+            OrbitEnvironment::Mainnet => "www.deribit.com",
+            OrbitEnvironment::Testnet => "test.deribit.com",
+        };
+        Self {
+            rest_url: format!("https://{host}/api/v2"),
+            ws_url: format!("wss://{host}/ws/api/v2"),
+            heartbeat_interval: 30,
+            book_interval: OrbitBookInterval::default(),
+            chunk_size: 250,
+            client_id: None,
+            client_secret: None,
+        }
+    }
+
+    pub fn credentials(&self) -> Option<(&str, &str)> {
+        self.client_id.as_deref().zip(self.client_secret.as_deref())
+    }
+
+    pub fn book_channel(&self, instrument_name: &str) -> String {
+        format!("book.{}.{}", instrument_name, self.book_interval)
+    }
+
+    // tickers and trades come at the interval of the books once the connection is
+    // authorized, raw ones aren't public
+    fn public_interval(&self) -> OrbitBookInterval {
+        match self.credentials() {
+            Some(_) => self.book_interval,
+            None => OrbitBookInterval::Ms100,
+        }
+    }
+
+    pub fn ticker_channel(&self, instrument_name: &str) -> String {
+        format!("ticker.{}.{}", instrument_name, self.public_interval())
+    }
+
+    pub fn trades_channel(&self, instrument_name: &str) -> String {
+        format!("trades.{}.{}", instrument_name, self.public_interval())
+    }
+
+    // e.g. btc_usd, the price_index of the instruments
+    pub fn index_channel(&self, index_name: &str) -> String {
+        format!("deribit_price_index.{}", index_name)
+    }
+}
+
+impl Default for DeribitConfig {
+    fn default() -> Self {
+        Self::new(OrbitEnvironment::default())
+    }
+}
+
+// seconds of slack on top of the heartbeat interval before a connection is considered dead
+pub const HEARTBEAT_GRACE: u64 = 5;
+
+// ceiling of the exponential reconnect backoff, in ms
+pub const MAX_BACKOFF_MS: u64 = 30_000;
+
+// a deribit connection subscribes a book, a ticker and a trades channel per instrument in a
+// single request, which is kept to this many channels
+pub const DERIBIT_MAX_CHANNELS: usize = 1000;
+pub const DERIBIT_CHANNELS_PER_INSTRUMENT: usize = 3;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrbitConfig {
+    pub environment: OrbitEnvironment,
+    pub delta: DeltaConfig,
+    pub deribit: DeribitConfig,
+}
+
+impl OrbitConfig {
+    pub fn new(environment: OrbitEnvironment) -> Self {
+        Self {
+            environment,
+            delta: DeltaConfig::new(environment),
+            deribit: DeribitConfig::new(environment),
+        }
+    }
+
+    // ORBIT_CONFIG (if set) and then the ORBIT_* variables of the process
+    pub fn load() -> Result<Self, Error> {
+        let toml = match env::var(CONFIG_PATH_VAR) {
+            Ok(path) => {
+                Some(fs::read_to_string(&path).with_context(|| format!("reading config {path}"))?)
+            }
+            Err(_) => None,
+        };
+        Self::from_sources(toml.as_deref(), env::vars())
+    }
+
+    pub fn from_toml(toml: &str) -> Result<Self, Error> {
+        Self::from_sources(Some(toml), std::iter::empty())
+    }
+
+    // the environment picks the defaults, then the toml and finally the variables override
+    // them field by field, so a testnet file only needs `environment = "testnet"`
+    pub fn from_sources(
+        toml: Option<&str>,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, Error> {
+        let file = match toml {
+            Some(toml) => toml::from_str::<PartialConfig>(toml).context("parsing config")?,
+            None => PartialConfig::default(),
+        };
+        let vars = PartialConfig::from_vars(vars)?;
+        let config = vars.or(file).resolve();
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.delta.chunk_size == 0 || self.deribit.chunk_size == 0 {
+            return Err(anyhow!("chunk_size must be at least 1"));
+        }
+        if self.delta.chunk_size > 20 {
+            return Err(anyhow!(
+                "delta allows at most 20 symbols per l2_orderbook subscription"
+            ));
+        }
+        if self.deribit.chunk_size * DERIBIT_CHANNELS_PER_INSTRUMENT > DERIBIT_MAX_CHANNELS {
+            return Err(anyhow!(
+                "deribit chunk_size {} subscribes more than {DERIBIT_MAX_CHANNELS} channels per connection",
+                self.deribit.chunk_size
+            ));
+        }
+        if self.deribit.client_id.is_some() != self.deribit.client_secret.is_some() {
+            return Err(anyhow!(
+                "deribit client_id and client_secret must be set together"
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PartialConfig {
+    environment: Option<OrbitEnvironment>,
+    delta: PartialDeltaConfig,
+    deribit: PartialDeribitConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PartialDeltaConfig {
+    rest_url: Option<String>,
+    ws_url: Option<String>,
+    heartbeat_interval: Option<u64>,
+    chunk_size: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PartialDeribitConfig {
+    rest_url: Option<String>,
+    ws_url: Option<String>,
+    heartbeat_interval: Option<u64>,
+    book_interval: Option<OrbitBookInterval>,
+    chunk_size: Option<usize>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+fn parse_var<T>(name: &str, value: &str) -> Result<Option<T>, Error>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .parse()
+        .map(Some)
+        .map_err(|err| anyhow!("invalid {ENV_PREFIX}{name}={value}: {err}"))
+}
+
+impl PartialConfig {
+    fn from_vars(vars: impl IntoIterator<Item = (String, String)>) -> Result<Self, Error> {
+        let mut config = Self::default();
+        for (key, value) in vars {
+            let Some(name) = key.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            match name {
+                "ENVIRONMENT" => config.environment = parse_var(name, &value)?,
+                "DELTA_REST_URL" => config.delta.rest_url = Some(value),
+                "DELTA_WS_URL" => config.delta.ws_url = Some(value),
+                "DELTA_HEARTBEAT_INTERVAL" => {
+                    config.delta.heartbeat_interval = parse_var(name, &value)?
+                }
+                "DELTA_CHUNK_SIZE" => config.delta.chunk_size = parse_var(name, &value)?,
+                "DERIBIT_REST_URL" => config.deribit.rest_url = Some(value),
+                "DERIBIT_WS_URL" => config.deribit.ws_url = Some(value),
+                "DERIBIT_HEARTBEAT_INTERVAL" => {
+                    config.deribit.heartbeat_interval = parse_var(name, &value)?
+                }
+                "DERIBIT_BOOK_INTERVAL" => config.deribit.book_interval = parse_var(name, &value)?,
+                "DERIBIT_CHUNK_SIZE" => config.deribit.chunk_size = parse_var(name, &value)?,
+                "DERIBIT_CLIENT_ID" => config.deribit.client_id = Some(value),
+                "DERIBIT_CLIENT_SECRET" => config.deribit.client_secret = Some(value),
+                _ => {}
+            }
+        }
+        Ok(config)
+    }
+
+    fn or(self, other: Self) -> Self {
+        Self {
+            environment: self.environment.or(other.environment),
+            delta: PartialDeltaConfig {
+                rest_url: self.delta.rest_url.or(other.delta.rest_url),
+                ws_url: self.delta.ws_url.or(other.delta.ws_url),
+                heartbeat_interval: self
+                    .delta
+                    .heartbeat_interval
+                    .or(other.delta.heartbeat_interval),
+                chunk_size: self.delta.chunk_size.or(other.delta.chunk_size),
+            },
+            deribit: PartialDeribitConfig {
+                rest_url: self.deribit.rest_url.or(other.deribit.rest_url),
+                ws_url: self.deribit.ws_url.or(other.deribit.ws_url),
+                heartbeat_interval: self
+                    .deribit
+                    .heartbeat_interval
+                    .or(other.deribit.heartbeat_interval),
+                book_interval: self.deribit.book_interval.or(other.deribit.book_interval),
+                chunk_size: self.deribit.chunk_size.or(other.deribit.chunk_size),
+                client_id: self.deribit.client_id.or(other.deribit.client_id),
+                client_secret: self.deribit.client_secret.or(other.deribit.client_secret),
+            },
+        }
+    }
+
+    fn resolve(self) -> OrbitConfig {
+        let mut config = OrbitConfig::new(self.environment.unwrap_or_default());
+        let delta = &mut config.delta;
+        if let Some(rest_url) = self.delta.rest_url {
+            delta.rest_url = rest_url;
+        }
+        if let Some(ws_url) = self.delta.ws_url {
+            delta.ws_url = ws_url;
+        }
+        if let Some(heartbeat_interval) = self.delta.heartbeat_interval {
+            delta.heartbeat_interval = heartbeat_interval;
+        }
+        if let Some(chunk_size) = self.delta.chunk_size {
+            delta.chunk_size = chunk_size;
+        }
+        let deribit = &mut config.deribit;
+        if let Some(rest_url) = self.deribit.rest_url {
+            deribit.rest_url = rest_url;
+        }
+        if let Some(ws_url) = self.deribit.ws_url {
+            deribit.ws_url = ws_url;
+        }
+        if let Some(heartbeat_interval) = self.deribit.heartbeat_interval {
+            deribit.heartbeat_interval = heartbeat_interval;
+        }
+        if let Some(book_interval) = self.deribit.book_interval {
+            deribit.book_interval = book_interval;
+        }
+        if let Some(chunk_size) = self.deribit.chunk_size {
+            deribit.chunk_size = chunk_size;
+        }
+        if let Some(client_id) = self.deribit.client_id {
+            deribit.client_id = Some(client_id);
+        }
+        if let Some(client_secret) = self.deribit.client_secret {
+            deribit.client_secret = Some(client_secret);
+        }
+        config
+    }
+}
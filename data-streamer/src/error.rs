@@ -0,0 +1,125 @@
+use std::fmt::Debug;
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use thiserror::Error;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_tungstenite::tungstenite;
+
+use crate::OrbitExchange;
+
+// everything that can go wrong inside a spawned stream, none of it is fatal: the stream
+// logs it and either drops the frame or reconnects
+#[derive(Debug, Error)]
+pub enum OrbitStreamError {
+    #[error("{exchange:?} connect to {url} failed: {source}")]
+    Connect {
+        exchange: OrbitExchange,
+        url: String,
+        #[source]
+        source: Box<tungstenite::Error>,
+    },
+    #[error("{exchange:?} subscribe failed: {source}")]
+    Subscribe {
+        exchange: OrbitExchange,
+        #[source]
+        source: Box<tungstenite::Error>,
+    },
+    #[error("{exchange:?} unparseable frame: {source}")]
+    Parse {
+        exchange: OrbitExchange,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("{exchange:?} protocol error: {message}")]
+    Protocol {
+        exchange: OrbitExchange,
+        message: String,
+    },
+    #[error("{exchange:?} sequence gap on {symbol}: prev_change_id {prev_change_id:?} change_id {change_id}")]
+    Sequence {
+        exchange: OrbitExchange,
+        symbol: String,
+        prev_change_id: Option<i64>,
+        change_id: i64,
+    },
+}
+
+impl OrbitStreamError {
+    pub fn connect(exchange: OrbitExchange, url: &str, source: tungstenite::Error) -> Self {
+        OrbitStreamError::Connect {
+            exchange,
+            url: url.to_string(),
+            source: Box::new(source),
+        }
+    }
+
+    pub fn subscribe(exchange: OrbitExchange, source: tungstenite::Error) -> Self {
+        OrbitStreamError::Subscribe {
+            exchange,
+            source: Box::new(source),
+        }
+    }
+
+    pub fn parse(
+        exchange: OrbitExchange,
+        source: impl std::error::Error + Send + Sync + 'static,
+    ) -> Self {
+        OrbitStreamError::Parse {
+            exchange,
+            source: Box::new(source),
+        }
+    }
+
+    pub fn protocol(exchange: OrbitExchange, message: impl Into<String>) -> Self {
+        OrbitStreamError::Protocol {
+            exchange,
+            message: message.into(),
+        }
+    }
+}
+
+// a frame the stream could not use, kept verbatim so it can be looked at later
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OrbitDeadLetter {
+    pub exchange: OrbitExchange,
+    pub received_at: DateTime<Utc>,
+    pub error: String,
+    pub raw: String,
+}
+
+impl OrbitDeadLetter {
+    pub fn new(exchange: OrbitExchange, error: &OrbitStreamError, raw: String) -> Self {
+        Self {
+            exchange,
+            received_at: Utc::now(),
+            error: error.to_string(),
+            raw,
+        }
+    }
+}
+
+pub trait OrbitDeadLetterSink: Debug + Send + Sync {
+    fn push(&self, letter: OrbitDeadLetter);
+}
+
+// default sink, the frame only ends up in the log
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LogDeadLetterSink;
+
+impl OrbitDeadLetterSink for LogDeadLetterSink {
+    fn push(&self, letter: OrbitDeadLetter) {
+        warn!(
+            "dead letter from {:?}: {}, raw {}",
+            letter.exchange, letter.error, letter.raw
+        );
+    }
+}
+
+impl OrbitDeadLetterSink for UnboundedSender<OrbitDeadLetter> {
+    fn push(&self, letter: OrbitDeadLetter) {
+        if let Err(err) = self.send(letter) {
+            LogDeadLetterSink.push(err.0);
+        }
+    }
+}
@@ -1,28 +1,43 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
+    num::ParseFloatError,
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
     time::{Duration, Instant},
 };
 
 use crate::{
-    OrbitContractType, OrbitCurrency, OrbitEvent, OrbitEventPayload, OrbitExchange,
-    OrbitInstrument, OrderbookUpdate, OrderbookUpdateLevel, OrderbookUpdateType,
+    config::{DeltaConfig, HEARTBEAT_GRACE, MAX_BACKOFF_MS},
+    error::{LogDeadLetterSink, OrbitDeadLetter, OrbitDeadLetterSink, OrbitStreamError},
+    expiration_key,
+    lifecycle::{OrbitStreamSet, OrbitSubscriptionChange},
+    price_indices,
+    recorder::OrbitRecorder,
+    OrbitConnectorHealth, OrbitContractSpecs, OrbitContractType, OrbitCurrency, OrbitEvent,
+    OrbitEventPayload, OrbitExchange, OrbitExchangeConnector, OrbitFunding, OrbitIndexPrice,
+    OrbitInstrument, OrbitSettlement, OrbitSizeUnit, OrbitTrade, OrbitTradeSide, OrderbookUpdate,
+    OrderbookUpdateLevel, OrderbookUpdateType,
 };
-use anyhow::Error;
-use chrono::{DateTime, NaiveDateTime, NaiveTime, Utc};
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
 use futures::{SinkExt, StreamExt};
 use log::*;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use tokio::sync::broadcast::{self, Receiver, Sender};
+use tokio::sync::{broadcast::Sender, mpsc::UnboundedReceiver};
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 use uuid::Uuid;
 
 #[derive(Debug)]
 pub struct DeltaClient {
     id: Uuid,
-    heartbeat_timeout: u64,
-    sender: Sender<OrbitEvent>,
-    receiver: Receiver<OrbitEvent>,
+    config: DeltaConfig,
+    health: Arc<RwLock<OrbitConnectorHealth>>,
+    dead_letters: Arc<dyn OrbitDeadLetterSink>,
+    recorder: Option<OrbitRecorder>,
+    streams: Arc<Mutex<OrbitStreamSet>>,
 }
 
 impl Default for DeltaClient {
@@ -33,178 +48,589 @@ impl Default for DeltaClient {
 
 impl DeltaClient {
     pub fn new() -> Self {
-        let (sender, receiver) = broadcast::channel::<OrbitEvent>(10_000);
+        Self::from_config(DeltaConfig::default())
+    }
+
+    pub fn from_config(config: DeltaConfig) -> Self {
         Self {
             id: Uuid::new_v4(),
-            heartbeat_timeout: 35,
-            sender,
-            receiver,
+            config,
+            health: Arc::new(RwLock::new(OrbitConnectorHealth::default())),
+            dead_letters: Arc::new(LogDeadLetterSink),
+            recorder: None,
+            streams: Arc::new(Mutex::new(OrbitStreamSet::default())),
         }
     }
 
-    pub async fn get_products(&self) -> Result<DeltaProductWrapper, Error> {
-        let url = "https://api.delta.exchange/v2/products"; //TODO!
-        let response = reqwest::get(url).await?;
-        let resp_text = response.text().await?;
-        let resp_json =
-            serde_json::from_str::<DeltaProductWrapper>(&resp_text).expect("Error parsing Delta");
-        Ok(resp_json)
+    pub fn config(&self) -> &DeltaConfig {
+        &self.config
+    }
+
+    // points the streams somewhere else, e.g. a local mock exchange in tests
+    pub fn with_ws_url(mut self, ws_url: &str) -> Self {
+        self.config.ws_url = ws_url.to_string();
+        self
     }
 
-    pub async fn consume(
+    pub fn with_rest_url(mut self, rest_url: &str) -> Self {
+        self.config.rest_url = rest_url.to_string();
+        self
+    }
+
+    // where frames that can't be parsed end up, the log by default
+    pub fn with_dead_letter_sink(mut self, dead_letters: Arc<dyn OrbitDeadLetterSink>) -> Self {
+        self.dead_letters = dead_letters;
+        self
+    }
+
+    // keeps a copy of every raw frame the streams receive
+    pub fn with_recorder(mut self, recorder: OrbitRecorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    // running streams with room take what they can, the rest gets new streams
+    fn spawn_streams(
         &self,
-        sender: Sender<OrbitEvent>,
-        products: Vec<OrbitInstrument>,
+        streams: &mut OrbitStreamSet,
+        instruments: Vec<OrbitInstrument>,
     ) -> Result<(), Error> {
+        let sender = streams
+            .sender()
+            .ok_or_else(|| anyhow!("delta client {} isn't consuming yet", self.id))?;
         // subscription forbidden on this channel with more than 20 symbols\",\"name\":\"l2_orderbook\"
-        for chunk in products.chunks(20) {
-            // let symbols: Vec<String> = chunk.iter().map(|p| p.symbol.clone()).collect();
+        for (chunk, control) in streams.assign(instruments, self.config.chunk_size) {
+            OrbitConnectorHealth::on_spawn(&self.health);
             tokio::spawn(Self::_stream_websockets_delta(
+                self.config.clone(),
                 sender.clone(),
-                chunk.to_owned(),
+                chunk,
+                control,
+                self.health.clone(),
+                self.dead_letters.clone(),
+                self.recorder.clone(),
             ));
         }
         Ok(())
     }
 
+    pub async fn get_products(&self) -> Result<DeltaProductWrapper, Error> {
+        let url = format!("{}/v2/products", self.config.rest_url);
+        let response = reqwest::get(url).await?;
+        let resp_text = response.text().await?;
+        let resp_json = serde_json::from_str::<DeltaProductWrapper>(&resp_text)?;
+        Ok(resp_json)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn _stream_websockets_delta(
+        config: DeltaConfig,
         sender: Sender<OrbitEvent>,
         symbols: Vec<OrbitInstrument>,
+        control: UnboundedReceiver<OrbitSubscriptionChange>,
+        health: Arc<RwLock<OrbitConnectorHealth>>,
+        dead_letters: Arc<dyn OrbitDeadLetterSink>,
+        recorder: Option<OrbitRecorder>,
     ) {
-        let mut symbol_details_map: HashMap<String, OrbitInstrument> = HashMap::new();
-        let mut delta_symbols = vec![];
-        for x in symbols.iter() {
-            symbol_details_map.insert(x.symbol.clone(), x.clone());
-            delta_symbols.push(x.symbol.clone());
-        }
+        let mut stream = DeltaStream {
+            config,
+            sender,
+            symbol_details_map: HashMap::new(),
+            price_indices: BTreeMap::new(),
+            delta_symbols: vec![],
+            control,
+            stopped: false,
+            health,
+            dead_letters,
+            recorder,
+        };
+        stream.apply(OrbitSubscriptionChange::Subscribe(symbols));
         let mut sleep = 100; //ms
         loop {
-            let (mut stream, _response) = connect_async("wss://socket.delta.exchange")
+            if let Err(err) = stream.connect(&mut sleep).await {
+                error!("{err}");
+                OrbitConnectorHealth::on_error(&stream.health);
+            }
+            if stream.stopped {
+                debug!("delta stream has no instruments left, stopping");
+                return;
+            }
+            // Exponential backoff
+            warn!("Delta stream disconnected, re-connecting. Sleep:{}", sleep);
+            tokio::time::sleep(Duration::from_millis(sleep)).await;
+            sleep = (sleep * 2).min(MAX_BACKOFF_MS);
+        }
+    }
+}
+
+// move options and spot pairs aren't booked, so they aren't subscribed either
+fn is_streamed(contract_type: &OrbitContractType) -> bool {
+    matches!(
+        contract_type,
+        OrbitContractType::Future
+            | OrbitContractType::PutOption
+            | OrbitContractType::CallOption
+            | OrbitContractType::PerpetualFuture
+    )
+}
+
+// one spawned stream, i.e. one chunk of symbols on its own connection
+struct DeltaStream {
+    config: DeltaConfig,
+    sender: Sender<OrbitEvent>,
+    symbol_details_map: HashMap<String, OrbitInstrument>,
+    // spot indices of the chunk, followed through v2/spot_price
+    price_indices: BTreeMap<String, OrbitCurrency>,
+    // in subscription order
+    delta_symbols: Vec<String>,
+    // instruments listed or delisted while the stream runs
+    control: UnboundedReceiver<OrbitSubscriptionChange>,
+    // set once the connector took the last instrument away
+    stopped: bool,
+    health: Arc<RwLock<OrbitConnectorHealth>>,
+    dead_letters: Arc<dyn OrbitDeadLetterSink>,
+    recorder: Option<OrbitRecorder>,
+}
+
+impl DeltaStream {
+    // books and trades of every instrument, funding of the perpetuals and the spot indices
+    fn channels(instruments: &[&OrbitInstrument], indices: &[&String]) -> Vec<Value> {
+        let symbols: Vec<&String> = instruments.iter().map(|x| &x.symbol).collect();
+        let perpetual_symbols: Vec<&String> = instruments
+            .iter()
+            .filter(|x| x.contract_type == OrbitContractType::PerpetualFuture)
+            .map(|x| &x.symbol)
+            .collect();
+        let mut channels = vec![];
+        if !symbols.is_empty() {
+            channels.push(json!({
+                "name": "l2_orderbook",
+                "symbols": symbols
+            }));
+            channels.push(json!({
+                "name": "all_trades",
+                "symbols": symbols
+            }));
+        }
+        if !perpetual_symbols.is_empty() {
+            channels.push(json!({
+                "name": "funding_rate",
+                "symbols": perpetual_symbols
+            }));
+        }
+        if !indices.is_empty() {
+            channels.push(json!({
+                "name": "v2/spot_price",
+                "symbols": indices
+            }));
+        }
+        channels
+    }
+
+    // takes the change on and returns the message making the live connection follow it,
+    // indices come and go with the last instrument priced off them
+    fn apply(&mut self, change: OrbitSubscriptionChange) -> Option<Value> {
+        let (kind, instruments) = match change {
+            OrbitSubscriptionChange::Subscribe(instruments) => {
+                let mut added = vec![];
+                for x in instruments {
+                    if is_streamed(&x.contract_type)
+                        && !self.symbol_details_map.contains_key(&x.symbol)
+                    {
+                        self.delta_symbols.push(x.symbol.clone());
+                        self.symbol_details_map.insert(x.symbol.clone(), x.clone());
+                        added.push(x);
+                    }
+                }
+                ("subscribe", added)
+            }
+            OrbitSubscriptionChange::Unsubscribe(instruments) => {
+                let removed: Vec<OrbitInstrument> = instruments
+                    .into_iter()
+                    .filter_map(|x| self.symbol_details_map.remove(&x.symbol))
+                    .collect();
+                let details = &self.symbol_details_map;
+                self.delta_symbols
+                    .retain(|symbol| details.contains_key(symbol));
+                ("unsubscribe", removed)
+            }
+        };
+        let remaining: Vec<OrbitInstrument> = self.symbol_details_map.values().cloned().collect();
+        let price_indices = price_indices(&remaining);
+        let indices: Vec<&String> = match kind {
+            "subscribe" => price_indices
+                .keys()
+                .filter(|name| !self.price_indices.contains_key(*name))
+                .collect(),
+            _ => self
+                .price_indices
+                .keys()
+                .filter(|name| !price_indices.contains_key(*name))
+                .collect(),
+        };
+        let channels = Self::channels(&instruments.iter().collect::<Vec<_>>(), &indices);
+        let message = (!channels.is_empty()).then(|| {
+            json!({
+                "type": kind,
+                "payload": {
+                    "channels": channels
+                }
+            })
+        });
+        self.price_indices = price_indices;
+        message
+    }
+
+    // runs a single connection until it drops, Err is anything worth a reconnect
+    async fn connect(&mut self, sleep: &mut u64) -> Result<(), OrbitStreamError> {
+        let ws_url = self.config.ws_url.as_str();
+        let (mut stream, _response) = connect_async(ws_url)
+            .await
+            .map_err(|err| OrbitStreamError::connect(OrbitExchange::Delta, ws_url, err))?;
+        // debug!("initialized delta stream");
+        let instruments: Vec<&OrbitInstrument> = self
+            .delta_symbols
+            .iter()
+            .filter_map(|symbol| self.symbol_details_map.get(symbol))
+            .collect();
+        let channels = Self::channels(&instruments, &self.price_indices.keys().collect::<Vec<_>>());
+        let subscribe = json!({
+            "type": "subscribe",
+            "payload": {
+                "channels": channels
+            }
+        });
+        // heartbeat is recommended, the subscription required
+        for message in [subscribe, json!({ "type": "enable_heartbeat" })] {
+            stream
+                .send(Message::Text(message.to_string()))
                 .await
-                .expect("Expected connection with Delta to work");
-            // debug!("initialized delta stream");
-            let _result = stream
-                .send(Message::Text(
-                    json!({
-                        "type": "subscribe",
-                        "payload": {
-                            "channels": [
+                .map_err(|err| OrbitStreamError::subscribe(OrbitExchange::Delta, err))?;
+        }
+        //TODO what happens when the channel gets clogged with messages??!!!!
+        // right now, no logic and after 100k messages, the broadcast channel
+        // length max was 365 messages
+        let heartbeat_timeout = self.config.heartbeat_interval + HEARTBEAT_GRACE;
+        let mut hearbeat_timer: Instant = Instant::now();
+        let connection_id = Uuid::new_v4();
+        OrbitConnectorHealth::on_connect(&self.health);
+        let mut result = Ok(());
+        loop {
+            let event = tokio::select! {
+                event = stream.next() => event,
+                change = self.control.recv() => {
+                    let Some(change) = change else {
+                        self.stopped = true;
+                        break;
+                    };
+                    if let Some(message) = self.apply(change) {
+                        if let Err(err) = stream.send(Message::Text(message.to_string())).await {
+                            result = Err(OrbitStreamError::subscribe(OrbitExchange::Delta, err));
+                            break;
+                        }
+                    }
+                    continue;
+                }
+            };
+            let Some(event) = event else {
+                break;
+            };
+            match event {
+                Ok(msg) => {
+                    if let Message::Text(text) = msg {
+                        OrbitConnectorHealth::on_message(&self.health);
+                        if let Some(recorder) = &self.recorder {
+                            recorder.record(OrbitExchange::Delta, connection_id, &text);
+                        }
+                        match DeltaFrame::parse(&text) {
+                            Ok(DeltaFrame::Orderbook(symbol, update)) => {
+                                self.send(symbol, OrbitEventPayload::OrderbookUpdate(update));
+                            }
+                            Ok(DeltaFrame::Trades(symbol, trades)) => {
+                                for trade in trades {
+                                    self.send(symbol.clone(), OrbitEventPayload::Trade(trade));
+                                }
+                            }
+                            Ok(DeltaFrame::Funding(symbol, funding)) => {
+                                self.send(symbol, OrbitEventPayload::Funding(funding));
+                            }
+                            Ok(DeltaFrame::SpotPrice(index_name, index)) => {
+                                if let Some(orbit_event) =
+                                    index_event(&self.price_indices, index_name, index)
                                 {
-                                    "name": "l2_orderbook",
-                                    "symbols": delta_symbols
+                                    let _ = self
+                                        .sender
+                                        .send(orbit_event)
+                                        .map_err(|err| error!("Error: {}", err));
+                                }
+                            }
+                            Ok(DeltaFrame::Subscriptions) => {}
+                            Ok(DeltaFrame::Heartbeat) => {
+                                if hearbeat_timer.elapsed().as_secs() > heartbeat_timeout {
+                                    warn!("connection died, reconnecting...");
+                                    break;
                                 }
-                            ]
+                                hearbeat_timer = Instant::now();
+                            }
+                            Ok(DeltaFrame::Unexpected(kind)) => {
+                                let err = OrbitStreamError::protocol(
+                                    OrbitExchange::Delta,
+                                    format!("unexpected message type {kind}"),
+                                );
+                                warn!("{err}");
+                                self.dead_letter(&err, text);
+                            }
+                            Err(err) => {
+                                warn!("{err}");
+                                self.dead_letter(&err, text);
+                            }
                         }
-                    })
-                    .to_string(),
-                ))
-                .await;
+                    };
+                    *sleep = 100;
+                }
+                Err(error) => {
+                    error!("Error: {}", error);
+                    break;
+                }
+            }
+        }
+        if self.stopped {
+            OrbitConnectorHealth::on_stop(&self.health);
+        } else {
+            OrbitConnectorHealth::on_disconnect(&self.health);
+        }
+        result
+    }
+
+    fn send(&self, symbol: String, payload: OrbitEventPayload) {
+        let orbit_event = OrbitEvent::for_instrument(
+            OrbitExchange::Delta,
+            symbol.clone(),
+            self.symbol_details_map.get(&symbol),
+            payload,
+        );
+        let _ = self
+            .sender
+            .send(orbit_event)
+            .map_err(|err| error!("Error: {}", err));
+    }
+
+    fn dead_letter(&self, err: &OrbitStreamError, raw: String) {
+        OrbitConnectorHealth::on_error(&self.health);
+        self.dead_letters
+            .push(OrbitDeadLetter::new(OrbitExchange::Delta, err, raw));
+    }
+}
+
+// turns recorded frames into events the same way the live stream does, see replay
+#[derive(Debug, Default)]
+pub struct DeltaFrameDecoder {
+    symbol_details_map: HashMap<String, OrbitInstrument>,
+    price_indices: BTreeMap<String, OrbitCurrency>,
+}
+
+impl DeltaFrameDecoder {
+    pub fn new(instruments: &[OrbitInstrument]) -> Self {
+        let instruments: Vec<OrbitInstrument> = instruments
+            .iter()
+            .filter(|x| x.exchange == OrbitExchange::Delta)
+            .cloned()
+            .collect();
+        Self {
+            price_indices: price_indices(&instruments),
+            symbol_details_map: instruments
+                .into_iter()
+                .map(|x| (x.symbol.clone(), x))
+                .collect(),
+        }
+    }
+
+    // l2_orderbook snapshots stand on their own, the connection makes no difference
+    pub fn decode(
+        &mut self,
+        _connection_id: Uuid,
+        raw: &str,
+    ) -> Result<Vec<OrbitEvent>, OrbitStreamError> {
+        let (symbol, payloads) = match DeltaFrame::parse(raw)? {
+            DeltaFrame::Orderbook(symbol, update) => {
+                (symbol, vec![OrbitEventPayload::OrderbookUpdate(update)])
+            }
+            DeltaFrame::Trades(symbol, trades) => (
+                symbol,
+                trades.into_iter().map(OrbitEventPayload::Trade).collect(),
+            ),
+            DeltaFrame::Funding(symbol, funding) => {
+                (symbol, vec![OrbitEventPayload::Funding(funding)])
+            }
+            DeltaFrame::SpotPrice(index_name, index) => {
+                return Ok(index_event(&self.price_indices, index_name, index)
+                    .into_iter()
+                    .collect())
+            }
+            _ => return Ok(vec![]),
+        };
+        let details = self.symbol_details_map.get(&symbol);
+        Ok(payloads
+            .into_iter()
+            .map(|payload| {
+                OrbitEvent::for_instrument(OrbitExchange::Delta, symbol.clone(), details, payload)
+            })
+            .collect())
+    }
+}
+
+// None for an index none of the instruments is priced off
+fn index_event(
+    price_indices: &BTreeMap<String, OrbitCurrency>,
+    index_name: String,
+    index: OrbitIndexPrice,
+) -> Option<OrbitEvent> {
+    let currency = price_indices.get(&index_name)?.clone();
+    Some(OrbitEvent::index_price(
+        OrbitExchange::Delta,
+        index_name,
+        currency,
+        index,
+    ))
+}
 
-            // debug!("sent sub message to delta (required): {:?}", result);
+enum DeltaFrame {
+    Orderbook(String, OrderbookUpdate),
+    SpotPrice(String, OrbitIndexPrice),
+    // a single print or, right after subscribing, the latest ones oldest first
+    Trades(String, Vec<OrbitTrade>),
+    Funding(String, OrbitFunding),
+    Subscriptions,
+    Heartbeat,
+    Unexpected(String),
+}
 
-            let _result = stream
-                .send(Message::Text(
-                    json!({
-                        "type": "enable_heartbeat"
-                    })
-                    .to_string(),
+impl DeltaFrame {
+    fn parse(text: &str) -> Result<Self, OrbitStreamError> {
+        let parse_error = |err| OrbitStreamError::parse(OrbitExchange::Delta, err);
+        let resp = serde_json::from_str::<HashMap<String, Value>>(text).map_err(parse_error)?;
+        let Some(Value::String(kind)) = resp.get("type") else {
+            return Err(OrbitStreamError::protocol(
+                OrbitExchange::Delta,
+                "message without a type",
+            ));
+        };
+        match kind.as_str() {
+            "l2_orderbook" => {
+                let ob: DeltaOrderbook = serde_json::from_str(text).map_err(parse_error)?;
+                let symbol = ob.symbol.clone();
+                let update = OrderbookUpdate::try_from(ob)
+                    .map_err(|err| OrbitStreamError::parse(OrbitExchange::Delta, err))?;
+                Ok(DeltaFrame::Orderbook(symbol, update))
+            }
+            "all_trades" => {
+                let trade: DeltaTrade = serde_json::from_str(text).map_err(parse_error)?;
+                let symbol = trade.symbol.clone();
+                let trade = OrbitTrade::try_from(trade)
+                    .map_err(|err| OrbitStreamError::parse(OrbitExchange::Delta, err))?;
+                Ok(DeltaFrame::Trades(symbol, vec![trade]))
+            }
+            "all_trades_snapshot" => {
+                let snapshot: DeltaTradesSnapshot =
+                    serde_json::from_str(text).map_err(parse_error)?;
+                let mut trades = snapshot
+                    .trades
+                    .into_iter()
+                    .map(OrbitTrade::try_from)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|err| OrbitStreamError::parse(OrbitExchange::Delta, err))?;
+                trades.sort_by_key(|trade| trade.timestamp);
+                Ok(DeltaFrame::Trades(snapshot.symbol, trades))
+            }
+            "funding_rate" => {
+                let funding: DeltaFundingRate = serde_json::from_str(text).map_err(parse_error)?;
+                let symbol = funding.symbol.clone();
+                let funding = OrbitFunding::try_from(funding)
+                    .map_err(|err| OrbitStreamError::parse(OrbitExchange::Delta, err))?;
+                Ok(DeltaFrame::Funding(symbol, funding))
+            }
+            "v2/spot_price" => {
+                let spot: DeltaSpotPrice = serde_json::from_str(text).map_err(parse_error)?;
+                let price = spot
+                    .price
+                    .to_decimal()
+                    .map_err(|err| OrbitStreamError::parse(OrbitExchange::Delta, err))?;
+                Ok(DeltaFrame::SpotPrice(
+                    spot.symbol,
+                    OrbitIndexPrice {
+                        price,
+                        timestamp: None,
+                    },
                 ))
-                .await;
-            // debug!(
-            //     "sent heartbeat sub message to delta (recommended): {:?}",
-            //     result
-            // );
-            //TODO what happens when the channel gets clogged with messages??!!!!
-            // right now, no logic and after 100k messages, the broadcast channel
-            // length max was 365 messages
-            let mut hearbeat_timer: Instant = Instant::now();
-            while let Some(event) = stream.next().await {
-                match event {
-                    Ok(msg) => {
-                        match msg {
-                            Message::Text(text) => {
-                                let resp = serde_json::from_str::<HashMap<String, Value>>(&text)
-                                    .expect("Error parsing Delta");
-                                match resp.get("type").expect("Coundt find type in response") {
-                                    Value::String(kind) => match kind.as_str() {
-                                        "l2_orderbook" => {
-                                            let ob: DeltaOrderbook =
-                                                serde_json::from_str(&text).expect("Can't parse");
-                                            // debug!("sent {:?}", ob);
-                                            // if sender.send(DeltaMarketEvent::OrderbookSnapshot(ob)).is_err() {
-                                            //     error!("error sending event");
-                                            // };
-                                            let norm_ob = OrbitEventPayload::OrderbookUpdate(
-                                                OrderbookUpdate::from(ob.clone()),
-                                            );
-
-                                            let orbit_event: OrbitEvent = OrbitEvent {
-                                                exchange: OrbitExchange::Delta,
-                                                symbol: ob.symbol.clone(),
-                                                currency: symbol_details_map
-                                                    .get(&ob.symbol)
-                                                    .map(|x| x.base.clone()),
-                                                contract_type: symbol_details_map
-                                                    .get(&ob.symbol)
-                                                    .map(|x| x.contract_type.clone()),
-                                                strike: symbol_details_map
-                                                    .get(&ob.symbol)
-                                                    .and_then(|x| x.strike),
-                                                payload: Some(norm_ob),
-                                                expiration: symbol_details_map
-                                                    .get(&ob.symbol)
-                                                    .and_then(|x| x.expiration_date),
-                                            };
-
-                                            let _ = sender
-                                                .send(orbit_event)
-                                                .map_err(|err| error!("Error: {}", err));
-                                        }
-                                        "subscriptions" => {
-                                            // let ds: DeltaSubscription =
-                                            //     serde_json::from_str(&text).expect("Can't parse");
-                                            // let _ = sender
-                                            //     .send(DeltaMarketEvent::Subscription(ds))
-                                            //     .map_err(|err| error!("Error: {}", err));
-                                        }
-                                        "heartbeat" => {
-                                            if hearbeat_timer.elapsed().as_secs() > 35 {
-                                                warn!("connection died, reconnecting...");
-                                                break;
-                                            }
-                                            hearbeat_timer = Instant::now();
-                                            // let hb: DeltaHeartbeat =
-                                            //     serde_json::from_str(&text).expect("Can't parse");
-                                            // let _ = sender.send(DeltaMarketEvent::Heartbeat(hb));
-                                            // let latency = data.ts_publish - data.ts_origin;
-                                            // debug!("heartbeat latency (ns) {:?}", latency);
-                                        }
-                                        _ => {
-                                            error!("unexpected message");
-                                            break;
-                                        }
-                                    },
-                                    _ => {}
-                                }
-                            }
-                            _ => {}
-                        };
-                        sleep = 100;
-                    }
-                    Err(error) => {
-                        error!("Error: {}", error);
-                        break;
-                    }
-                }
             }
-            // Exponential backoff
-            warn!("Delta stream disconnected, re-connecting. Sleep:{}", sleep);
-            tokio::time::sleep(Duration::from_millis(sleep)).await;
-            sleep *= 2;
+            "subscriptions" => Ok(DeltaFrame::Subscriptions),
+            "heartbeat" => Ok(DeltaFrame::Heartbeat),
+            _ => Ok(DeltaFrame::Unexpected(kind.clone())),
         }
     }
 }
 
+#[async_trait]
+impl OrbitExchangeConnector for DeltaClient {
+    fn exchange(&self) -> OrbitExchange {
+        OrbitExchange::Delta
+    }
+
+    async fn get_instruments(&self) -> Result<Vec<OrbitInstrument>, Error> {
+        let data = self.get_products().await?;
+        Ok(data.result.iter().map(OrbitInstrument::from).collect())
+    }
+
+    async fn consume(
+        &self,
+        sender: Sender<OrbitEvent>,
+        instruments: Vec<OrbitInstrument>,
+    ) -> Result<(), Error> {
+        debug!(
+            "delta client {} consuming {} instruments",
+            self.id,
+            instruments.len()
+        );
+        let mut streams = self
+            .streams
+            .lock()
+            .map_err(|_| anyhow!("delta streams poisoned"))?;
+        streams.set_sender(sender);
+        self.spawn_streams(&mut streams, instruments)
+    }
+
+    async fn subscribe(&self, instruments: Vec<OrbitInstrument>) -> Result<(), Error> {
+        debug!(
+            "delta client {} subscribing {} instruments",
+            self.id,
+            instruments.len()
+        );
+        let mut streams = self
+            .streams
+            .lock()
+            .map_err(|_| anyhow!("delta streams poisoned"))?;
+        self.spawn_streams(&mut streams, instruments)
+    }
+
+    async fn unsubscribe(&self, instruments: Vec<OrbitInstrument>) -> Result<(), Error> {
+        debug!(
+            "delta client {} unsubscribing {} instruments",
+            self.id,
+            instruments.len()
+        );
+        let mut streams = self
+            .streams
+            .lock()
+            .map_err(|_| anyhow!("delta streams poisoned"))?;
+        streams.remove(&instruments);
+        Ok(())
+    }
+
+    fn health(&self) -> OrbitConnectorHealth {
+        self.health.read().map(|h| h.clone()).unwrap_or_default()
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum DeltaMarketEvent {
     OrderbookSnapshot(DeltaOrderbook),
@@ -230,6 +656,18 @@ pub struct DeltaProduct {
     pub launch_time: Option<String>,
     pub underlying_asset: DeltaProductUnderlyingAsset,
     pub quoting_asset: DeltaProductQuotingAsset,
+    pub settling_asset: Option<DeltaProductSettlingAsset>,
+    // what one contract is worth, in the underlying or in USD for inverse contracts
+    pub contract_value: Option<DeltaNumber>,
+    pub tick_size: Option<DeltaNumber>,
+    pub maker_commission_rate: Option<DeltaNumber>,
+    pub taker_commission_rate: Option<DeltaNumber>,
+    pub spot_index: Option<DeltaProductSpotIndex>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DeltaProductSpotIndex {
+    pub symbol: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -253,6 +691,11 @@ pub struct DeltaProductQuotingAsset {
     pub symbol: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct DeltaProductSettlingAsset {
+    pub symbol: String,
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct DeltaHeartbeat {
     pub ts_origin: u64,
@@ -281,6 +724,79 @@ pub struct DeltaOrderbookLevel {
     pub size: u64,
 }
 
+// all_trades message, the symbol is only set on live prints and not on the ones of a
+// snapshot. The aggressor is whichever side was the taker.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DeltaTrade {
+    #[serde(default)]
+    pub symbol: String,
+    pub price: String,
+    pub size: u64,
+    pub buyer_role: DeltaTradeRole,
+    pub seller_role: DeltaTradeRole,
+    pub timestamp: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeltaTradeRole {
+    Maker,
+    Taker,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DeltaTradesSnapshot {
+    pub symbol: String,
+    pub trades: Vec<DeltaTrade>,
+}
+
+// v2/spot_price message, e.g. {"s": ".DEXBTUSD", "p": "16850.2", "type": "v2/spot_price"}
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DeltaSpotPrice {
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "p")]
+    pub price: DeltaNumber,
+}
+
+// funding_rate message, rates are in percent and timestamps in us, e.g.
+// {"type": "funding_rate", "symbol": "BTCUSD", "funding_rate": 0.0041, "funding_rate_8h": 0.0041,
+//  "next_funding_realization": 1683734400000000, "timestamp": 1683729000000000}
+// funding_rate is per funding interval, the 8h one is preferred when it's there
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DeltaFundingRate {
+    pub symbol: String,
+    pub funding_rate: DeltaNumber,
+    pub funding_rate_8h: Option<DeltaNumber>,
+    pub next_funding_realization: Option<i64>,
+    pub timestamp: u64,
+}
+
+// delta sends most numbers as strings but not all of them
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum DeltaNumber {
+    Number(f64),
+    String(String),
+}
+
+impl DeltaNumber {
+    pub fn to_f64(&self) -> Result<f64, ParseFloatError> {
+        match self {
+            DeltaNumber::Number(number) => Ok(*number),
+            DeltaNumber::String(number) => number.parse::<f64>(),
+        }
+    }
+
+    // prices and sizes, taken from the digits delta sent rather than through a float
+    pub fn to_decimal(&self) -> Result<Decimal, rust_decimal::Error> {
+        match self {
+            DeltaNumber::Number(number) => Decimal::from_str(&number.to_string()),
+            DeltaNumber::String(number) => Decimal::from_str(number),
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct DeltaSubscription {
     pub channels: Vec<DeltaSubscriptionChannel>,
@@ -294,55 +810,106 @@ pub struct DeltaSubscriptionChannel {
     pub symbols: Vec<String>,
 }
 
-impl From<&DeltaOrderbookLevel> for OrderbookUpdateLevel {
-    fn from(delta_orderbook_level: &DeltaOrderbookLevel) -> Self {
-        // orderbookupdatetype is new becuause delta does snapshots so OB is always new
-        Self(
+impl TryFrom<&DeltaOrderbookLevel> for OrderbookUpdateLevel {
+    type Error = rust_decimal::Error;
+
+    fn try_from(delta_orderbook_level: &DeltaOrderbookLevel) -> Result<Self, Self::Error> {
+        // orderbookupdatetype is new becuause delta does snapshots so OB is always new,
+        // the update carrying these levels is flagged as a snapshot
+        Ok(Self(
             OrderbookUpdateType::New,
-            delta_orderbook_level.limit_price.parse::<f64>().unwrap(),
-            delta_orderbook_level.size as f64,
-        )
+            Decimal::from_str(&delta_orderbook_level.limit_price)?,
+            Decimal::from(delta_orderbook_level.size),
+        ))
     }
 }
 
-impl From<DeltaOrderbook> for OrderbookUpdate {
-    fn from(delta_orderbook: DeltaOrderbook) -> Self {
-        Self {
+impl TryFrom<DeltaOrderbook> for OrderbookUpdate {
+    type Error = rust_decimal::Error;
+
+    fn try_from(delta_orderbook: DeltaOrderbook) -> Result<Self, Self::Error> {
+        // l2_orderbook messages are always full snapshots
+        Ok(Self {
+            is_snapshot: true,
             timestamp: delta_orderbook.timestamp,
             bids: delta_orderbook
                 .buy
                 .iter()
-                .map(OrderbookUpdateLevel::from)
-                .collect(),
+                .map(OrderbookUpdateLevel::try_from)
+                .collect::<Result<_, _>>()?,
             asks: delta_orderbook
                 .sell
                 .iter()
-                .map(OrderbookUpdateLevel::from)
-                .collect(),
-        }
+                .map(OrderbookUpdateLevel::try_from)
+                .collect::<Result<_, _>>()?,
+        })
     }
 }
 
+impl TryFrom<DeltaTrade> for OrbitTrade {
+    type Error = rust_decimal::Error;
+
+    fn try_from(delta_trade: DeltaTrade) -> Result<Self, Self::Error> {
+        Ok(Self {
+            trade_id: None,
+            timestamp: delta_trade.timestamp,
+            price: Decimal::from_str(&delta_trade.price)?,
+            size: Decimal::from(delta_trade.size),
+            side: match delta_trade.buyer_role {
+                DeltaTradeRole::Taker => OrbitTradeSide::Buy,
+                DeltaTradeRole::Maker => OrbitTradeSide::Sell,
+            },
+            iv: None,
+        })
+    }
+}
+
+impl TryFrom<DeltaFundingRate> for OrbitFunding {
+    type Error = ParseFloatError;
+
+    fn try_from(delta_funding: DeltaFundingRate) -> Result<Self, Self::Error> {
+        let percent = match &delta_funding.funding_rate_8h {
+            Some(rate_8h) => rate_8h.to_f64()?,
+            None => delta_funding.funding_rate.to_f64()?,
+        };
+        Ok(Self {
+            timestamp: delta_funding.timestamp,
+            rate_8h: percent / 100.0,
+            next_funding_at: delta_funding
+                .next_funding_realization
+                .map(|us| Utc.timestamp_nanos(us * 1000)),
+        })
+    }
+}
+
+// option fees are capped at 10% of the premium, the products don't say so, the fee
+// schedule does
+const DELTA_OPTION_FEE_CAP: f64 = 0.1;
+
 impl From<&DeltaProduct> for OrbitInstrument {
     fn from(delta_product: &DeltaProduct) -> Self {
-        let expiration_datetime = delta_product.settlement_time.clone().map(|time| {
-            let a = DateTime::from(DateTime::parse_from_rfc3339(time.as_str()).unwrap()); 
-            a
-        });
+        // a listing we can't read is left without expiry or strike, which keeps it unbooked
+        let symbol = &delta_product.symbol;
+        let expiration_datetime: Option<DateTime<Utc>> =
+            delta_product.settlement_time.as_ref().and_then(|time| {
+                DateTime::parse_from_rfc3339(time)
+                    .map(DateTime::from)
+                    .map_err(|err| warn!("bad settlement time {time} on {symbol}: {err}"))
+                    .ok()
+            });
 
-        let expiration_date = delta_product.settlement_time.clone().map(|time| {
-            let nd = DateTime::parse_from_rfc3339(&time).unwrap().date_naive();
-            let t = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
-            let a = NaiveDateTime::new(nd, t);
-            let d: DateTime<Utc> = DateTime::from_local(a, Utc);
-            // d.timestamp_millis()
-            d
-        });
+        let expiration_date = expiration_datetime.map(expiration_key);
 
+        // fractional strikes (22.5 on SOL) are kept whole, 22.50 and 22.5 are the same strike
         let strike = delta_product
             .strike
-            .clone()
-            .map(|strike| strike.parse::<u64>().unwrap());
+            .as_ref()
+            .and_then(|strike| {
+                Decimal::from_str(strike)
+                    .map_err(|err| warn!("bad strike {strike} on {symbol}: {err}"))
+                    .ok()
+            })
+            .map(|strike| strike.normalize());
 
         let contract_type = match delta_product.contract_type {
             DeltaContractType::Futures => OrbitContractType::Future,
@@ -353,16 +920,54 @@ impl From<&DeltaProduct> for OrbitInstrument {
             DeltaContractType::Spot => OrbitContractType::Spot,
         };
 
+        // the coin margined futures settle in their underlying, everything else in USDT
+        let settlement = match &delta_product.settling_asset {
+            Some(asset) if asset.symbol == delta_product.underlying_asset.symbol => {
+                OrbitSettlement::Inverse
+            }
+            _ => OrbitSettlement::Linear,
+        };
+        // sizes count whole contracts
+        let number = |x: &Option<DeltaNumber>| x.as_ref().and_then(|x| x.to_f64().ok());
+        let decimal = |x: &Option<DeltaNumber>| x.as_ref().and_then(|x| x.to_decimal().ok());
+        let contract_size = decimal(&delta_product.contract_value);
+        let size_unit = match (contract_size, settlement) {
+            (Some(value), OrbitSettlement::Linear) => OrbitSizeUnit::Contracts(value),
+            (Some(value), OrbitSettlement::Inverse) => OrbitSizeUnit::UsdContracts(value),
+            (None, _) => OrbitSizeUnit::Underlying,
+        };
+
+        let premium_fee_cap = match contract_type {
+            OrbitContractType::CallOption | OrbitContractType::PutOption => {
+                Some(DELTA_OPTION_FEE_CAP)
+            }
+            _ => None,
+        };
+
         // debug!("delta timestamp transformed {:?}, settlement_time {:?}", expiration, delta_product.settlement_time);
         Self {
             symbol: delta_product.symbol.clone(),
             base: OrbitCurrency::from(&delta_product.underlying_asset.symbol),
             quote: OrbitCurrency::from(&delta_product.quoting_asset.symbol),
+            settlement,
             strike,
             expiration_datetime,
             expiration_date,
             contract_type,
             exchange: OrbitExchange::Delta,
+            price_index: delta_product
+                .spot_index
+                .as_ref()
+                .map(|spot_index| spot_index.symbol.clone()),
+            specs: OrbitContractSpecs {
+                size_unit,
+                contract_size,
+                tick_size: decimal(&delta_product.tick_size),
+                min_size: Some(Decimal::ONE),
+                maker_fee: number(&delta_product.maker_commission_rate),
+                taker_fee: number(&delta_product.taker_commission_rate),
+                premium_fee_cap,
+            },
         }
     }
 }
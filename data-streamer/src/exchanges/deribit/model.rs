@@ -1,27 +1,41 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex, RwLock},
     time::{Duration, Instant},
 };
 
 use crate::{
-    OrbitContractType, OrbitCurrency, OrbitEvent, OrbitEventPayload, OrbitExchange,
-    OrbitInstrument, OrderbookUpdate, OrderbookUpdateLevel, OrderbookUpdateType,
+    config::{DeribitConfig, HEARTBEAT_GRACE, MAX_BACKOFF_MS},
+    error::{LogDeadLetterSink, OrbitDeadLetter, OrbitDeadLetterSink, OrbitStreamError},
+    expiration_key,
+    lifecycle::{OrbitStreamSet, OrbitSubscriptionChange},
+    price_indices,
+    recorder::OrbitRecorder,
+    OrbitConnectorHealth, OrbitContractSpecs, OrbitContractType, OrbitCurrency, OrbitEvent,
+    OrbitEventPayload, OrbitExchange, OrbitExchangeConnector, OrbitFunding, OrbitGreeks,
+    OrbitIndexPrice, OrbitInstrument, OrbitSettlement, OrbitSizeUnit, OrbitTicker, OrbitTrade,
+    OrbitTradeSide, OrderbookUpdate, OrderbookUpdateLevel, OrderbookUpdateType,
 };
-use anyhow::Error;
-use chrono::{DateTime, NaiveDateTime, NaiveTime, Utc};
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use futures::{SinkExt, StreamExt};
 use log::*;
+use rust_decimal::Decimal;
 use serde::Deserialize;
 use serde_json::{json, Value};
-use tokio::sync::broadcast::{self, Receiver, Sender};
+use tokio::sync::{broadcast::Sender, mpsc::UnboundedReceiver};
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 use uuid::Uuid;
 
 #[derive(Debug)]
 pub struct DeribitClient {
     id: Uuid,
-    sender: Sender<OrbitEvent>,
-    receiver: Receiver<OrbitEvent>,
+    config: DeribitConfig,
+    health: Arc<RwLock<OrbitConnectorHealth>>,
+    dead_letters: Arc<dyn OrbitDeadLetterSink>,
+    recorder: Option<OrbitRecorder>,
+    streams: Arc<Mutex<OrbitStreamSet>>,
 }
 
 impl Default for DeribitClient {
@@ -32,20 +46,76 @@ impl Default for DeribitClient {
 
 impl DeribitClient {
     pub fn new() -> Self {
-        let (sender, receiver) = broadcast::channel::<OrbitEvent>(10_000);
+        Self::from_config(DeribitConfig::default())
+    }
+
+    pub fn from_config(config: DeribitConfig) -> Self {
         Self {
             id: Uuid::new_v4(),
-            sender,
-            receiver,
+            config,
+            health: Arc::new(RwLock::new(OrbitConnectorHealth::default())),
+            dead_letters: Arc::new(LogDeadLetterSink),
+            recorder: None,
+            streams: Arc::new(Mutex::new(OrbitStreamSet::default())),
         }
     }
 
+    pub fn config(&self) -> &DeribitConfig {
+        &self.config
+    }
+
+    // points the stream somewhere else, e.g. a local mock exchange in tests
+    pub fn with_ws_url(mut self, ws_url: &str) -> Self {
+        self.config.ws_url = ws_url.to_string();
+        self
+    }
+
+    pub fn with_rest_url(mut self, rest_url: &str) -> Self {
+        self.config.rest_url = rest_url.to_string();
+        self
+    }
+
+    // where frames that can't be parsed end up, the log by default
+    pub fn with_dead_letter_sink(mut self, dead_letters: Arc<dyn OrbitDeadLetterSink>) -> Self {
+        self.dead_letters = dead_letters;
+        self
+    }
+
+    // keeps a copy of every raw frame the streams receive
+    pub fn with_recorder(mut self, recorder: OrbitRecorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    // running streams with room take what they can, the rest gets new streams
+    fn spawn_streams(
+        &self,
+        streams: &mut OrbitStreamSet,
+        instruments: Vec<OrbitInstrument>,
+    ) -> Result<(), Error> {
+        let sender = streams
+            .sender()
+            .ok_or_else(|| anyhow!("deribit client {} isn't consuming yet", self.id))?;
+        for (chunk, control) in streams.assign(instruments, self.config.chunk_size) {
+            OrbitConnectorHealth::on_spawn(&self.health);
+            tokio::spawn(Self::_stream_websocket_deribit(
+                self.config.clone(),
+                sender.clone(),
+                chunk,
+                control,
+                self.health.clone(),
+                self.dead_letters.clone(),
+                self.recorder.clone(),
+            ));
+        }
+        Ok(())
+    }
+
     pub async fn get_currencies(&self) -> Result<DeribitCurrencyWrapper, Error> {
-        let url = "https://test.deribit.com/api/v2/public/get_currencies";
+        let url = format!("{}/public/get_currencies", self.config.rest_url);
         let response = reqwest::get(url).await?;
         let resp_text = response.text().await?;
-        let resp_json = serde_json::from_str::<DeribitCurrencyWrapper>(&resp_text)
-            .expect("Error parsing Deribit");
+        let resp_json = serde_json::from_str::<DeribitCurrencyWrapper>(&resp_text)?;
         Ok(resp_json)
     }
     // returns a vector because have to send a request per currency
@@ -54,173 +124,656 @@ impl DeribitClient {
         let mut result = Vec::with_capacity(currencies.result.capacity());
         for c in currencies.result.iter() {
             let url = format!(
-                "https://deribit.com/api/v2/public/get_instruments?currency={}&expired=false",
-                c.currency
+                "{}/public/get_instruments?currency={}&expired=false",
+                self.config.rest_url, c.currency
             );
             let response = reqwest::get(url).await?;
             let resp_text = response.text().await?;
-            let resp_json = serde_json::from_str::<DeribitInstrumentsWrapper>(&resp_text)
-                .expect("Error parsing Deribit");
+            let resp_json = serde_json::from_str::<DeribitInstrumentsWrapper>(&resp_text)?;
             result.push(resp_json);
         }
         Ok(result)
     }
 
-    pub async fn consume(
-        &self,
-        sender: Sender<OrbitEvent>,
-        orbit_instruments: Vec<OrbitInstrument>,
-    ) -> Result<(), Error> {
-        tokio::spawn(Self::_stream_websocket_deribit(sender, orbit_instruments));
-        Ok(())
+    pub async fn get_order_book(
+        rest_url: &str,
+        instrument_name: &str,
+    ) -> Result<DeribitOrderbook, Error> {
+        let url = format!(
+            "{}/public/get_order_book?instrument_name={}&depth=10000",
+            rest_url, instrument_name
+        );
+        let response = reqwest::get(url).await?;
+        let resp_text = response.text().await?;
+        let resp_json = serde_json::from_str::<DeribitOrderbookSnapshotWrapper>(&resp_text)?;
+        Ok(DeribitOrderbook::from(resp_json.result))
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn _stream_websocket_deribit(
+        config: DeribitConfig,
         sender: Sender<OrbitEvent>,
         orbit_instruments: Vec<OrbitInstrument>,
+        control: UnboundedReceiver<OrbitSubscriptionChange>,
+        health: Arc<RwLock<OrbitConnectorHealth>>,
+        dead_letters: Arc<dyn OrbitDeadLetterSink>,
+        recorder: Option<OrbitRecorder>,
     ) {
-        let mut deribit_symbols = vec![];
-        let mut symbol_details_map: HashMap<String, OrbitInstrument> = HashMap::new();
-        for x in orbit_instruments.iter() {
-            if x.contract_type == OrbitContractType::Future
-                || x.contract_type == OrbitContractType::PutOption
-                || x.contract_type == OrbitContractType::CallOption
-                || x.contract_type == OrbitContractType::PerpetualFuture
-                || x.contract_type == OrbitContractType::Spot
-            {
-                deribit_symbols.push(format!("book.{}.100ms", x.symbol)); //raw or 100ms
-                symbol_details_map.insert(x.symbol.clone(), x.clone());
-            }
-        }
+        let mut stream = DeribitStream {
+            config,
+            sender,
+            symbol_details_map: HashMap::new(),
+            price_indices: BTreeMap::new(),
+            instrument_names: vec![],
+            control,
+            stopped: false,
+            health,
+            dead_letters,
+            recorder,
+        };
+        stream.apply(OrbitSubscriptionChange::Subscribe(orbit_instruments));
 
         let mut sleep = 100; //ms
         loop {
-            // debug!("{:#?}",deribit_symbols);
-            debug!("consuming deribit");
-            let (mut stream, _response) = connect_async("wss://www.deribit.com/ws/api/v2")
+            if let Err(err) = stream.connect(&mut sleep).await {
+                error!("{err}");
+                OrbitConnectorHealth::on_error(&stream.health);
+            }
+            if stream.stopped {
+                debug!("deribit stream has no instruments left, stopping");
+                return;
+            }
+            // Exponential backoff
+            warn!(
+                "Deribit stream disconnected, re-connecting. Sleep:{}",
+                sleep
+            );
+            tokio::time::sleep(Duration::from_millis(sleep)).await;
+            sleep = (sleep * 2).min(MAX_BACKOFF_MS);
+        }
+    }
+}
+
+// one spawned stream, i.e. one chunk of book, ticker and trades channels (plus the
+// indices of the chunk) on its own connection
+struct DeribitStream {
+    config: DeribitConfig,
+    sender: Sender<OrbitEvent>,
+    symbol_details_map: HashMap<String, OrbitInstrument>,
+    // every chunk follows the indices of its own instruments, a currency spread over
+    // several chunks gets its index from each of them
+    price_indices: BTreeMap<String, OrbitCurrency>,
+    // in subscription order
+    instrument_names: Vec<String>,
+    // instruments listed or delisted while the stream runs
+    control: UnboundedReceiver<OrbitSubscriptionChange>,
+    // set once the connector took the last instrument away
+    stopped: bool,
+    health: Arc<RwLock<OrbitConnectorHealth>>,
+    dead_letters: Arc<dyn OrbitDeadLetterSink>,
+    recorder: Option<OrbitRecorder>,
+}
+
+impl DeribitStream {
+    fn instrument_channels(&self, instrument_name: &str) -> [String; 3] {
+        [
+            self.config.book_channel(instrument_name),
+            self.config.ticker_channel(instrument_name),
+            self.config.trades_channel(instrument_name),
+        ]
+    }
+
+    // everything the stream follows, subscribed on every (re)connect
+    fn channels(&self) -> Vec<String> {
+        let mut channels: Vec<String> = self
+            .instrument_names
+            .iter()
+            .flat_map(|name| self.instrument_channels(name))
+            .collect();
+        channels.extend(
+            self.price_indices
+                .keys()
+                .map(|name| self.config.index_channel(name)),
+        );
+        channels
+    }
+
+    // takes the change on and returns the request making the live connection follow it,
+    // indices come and go with the last instrument priced off them
+    fn apply(&mut self, change: OrbitSubscriptionChange) -> Option<(&'static str, Vec<String>)> {
+        let (method, mut channels) = match change {
+            OrbitSubscriptionChange::Subscribe(instruments) => {
+                let mut channels = vec![];
+                for x in instruments {
+                    if !is_streamed(&x.contract_type)
+                        || self.symbol_details_map.contains_key(&x.symbol)
+                    {
+                        continue;
+                    }
+                    channels.extend(self.instrument_channels(&x.symbol));
+                    self.instrument_names.push(x.symbol.clone());
+                    self.symbol_details_map.insert(x.symbol.clone(), x);
+                }
+                ("public/subscribe", channels)
+            }
+            OrbitSubscriptionChange::Unsubscribe(instruments) => {
+                let mut channels = vec![];
+                for x in instruments {
+                    if self.symbol_details_map.remove(&x.symbol).is_some() {
+                        channels.extend(self.instrument_channels(&x.symbol));
+                    }
+                }
+                let details = &self.symbol_details_map;
+                self.instrument_names
+                    .retain(|name| details.contains_key(name));
+                ("public/unsubscribe", channels)
+            }
+        };
+        let instruments: Vec<OrbitInstrument> = self.symbol_details_map.values().cloned().collect();
+        let price_indices = price_indices(&instruments);
+        let (before, after) = match method {
+            "public/subscribe" => (&self.price_indices, &price_indices),
+            _ => (&price_indices, &self.price_indices),
+        };
+        channels.extend(
+            after
+                .keys()
+                .filter(|name| !before.contains_key(*name))
+                .map(|name| self.config.index_channel(name)),
+        );
+        self.price_indices = price_indices;
+        (!channels.is_empty()).then_some((method, channels))
+    }
+
+    // runs a single connection until it drops, Err is anything worth a reconnect
+    async fn connect(&mut self, sleep: &mut u64) -> Result<(), OrbitStreamError> {
+        // every (re)connection starts from fresh subscription snapshots
+        let mut sequencer = DeribitBookSequencer::default();
+        // debug!("{:#?}",deribit_symbols);
+        debug!("consuming deribit");
+        let ws_url = self.config.ws_url.as_str();
+        let (mut stream, _response) = connect_async(ws_url)
+            .await
+            .map_err(|err| OrbitStreamError::connect(OrbitExchange::Deribit, ws_url, err))?;
+
+        debug!("initialized deribit stream");
+        let subscribe = json!({
+            "jsonrpc": "2.0",
+            "method": "public/subscribe",
+            "id": 42,
+            "params": {
+            "channels": self.channels()}
+        });
+        let heartbeat = json!({
+            "jsonrpc" : "2.0",
+            "id" : 1003,
+            "method" : "public/set_heartbeat",
+            "params" : {
+            "interval" : self.config.heartbeat_interval
+            }
+        });
+        // heartbeat is recommended, the subscription required, and raw tickers and trades
+        // need the connection authorized before it
+        let mut messages = vec![subscribe, heartbeat];
+        if let Some((client_id, client_secret)) = self.config.credentials() {
+            let auth = json!({
+                "jsonrpc": "2.0",
+                "id": 9929,
+                "method": "public/auth",
+                "params": {
+                "grant_type": "client_credentials",
+                "client_id": client_id,
+                "client_secret": client_secret}
+            });
+            messages.insert(0, auth);
+        }
+        for message in messages {
+            stream
+                .send(Message::Text(message.to_string()))
                 .await
-                .expect("Expected connection with Deribit to work");
-
-            debug!("initialized deribit stream");
-            let result = stream
-                .send(Message::Text(
-                    json!({
-                        "jsonrpc": "2.0",
-                        "method": "public/subscribe",
-                        "id": 42,
-                        "params": {
-                        "channels": deribit_symbols}
-                    })
-                    .to_string(),
-                ))
-                .await;
-
-            debug!("sent sub message to deribit (required): {:?}", result);
-
-            let result = stream
-                .send(Message::Text(
-                    json!({
-                        "jsonrpc" : "2.0",
-                        "id" : 1003,
-                        "method" : "public/set_heartbeat",
-                        "params" : {
-                        "interval" : 30
+                .map_err(|err| OrbitStreamError::subscribe(OrbitExchange::Deribit, err))?;
+        }
+
+        let heartbeat_timeout = self.config.heartbeat_interval + HEARTBEAT_GRACE;
+        let mut hearbeat_timer: Instant = Instant::now();
+        let connection_id = Uuid::new_v4();
+        OrbitConnectorHealth::on_connect(&self.health);
+        let mut result = Ok(());
+        loop {
+            let event = tokio::select! {
+                event = stream.next() => event,
+                change = self.control.recv() => {
+                    let Some(change) = change else {
+                        self.stopped = true;
+                        break;
+                    };
+                    if let Some((method, channels)) = self.apply(change) {
+                        let request = json!({
+                            "jsonrpc": "2.0",
+                            "method": method,
+                            "id": 44,
+                            "params": {
+                            "channels": channels}
+                        });
+                        if let Err(err) = stream.send(Message::Text(request.to_string())).await {
+                            result = Err(OrbitStreamError::subscribe(OrbitExchange::Deribit, err));
+                            break;
                         }
-                    })
-                    .to_string(),
-                ))
-                .await;
-            debug!(
-                "sent heartbeat sub message to deribit (recommended): {:?}",
-                result
-            );
+                        debug!("sent {}", method);
+                    }
+                    continue;
+                }
+            };
+            let Some(event) = event else {
+                break;
+            };
+            let text = match event {
+                Ok(Message::Text(text)) => text,
+                Ok(_) => continue,
+                Err(error) => {
+                    error!("Error: {}", error);
+                    break;
+                }
+            };
+            OrbitConnectorHealth::on_message(&self.health);
+            if let Some(recorder) = &self.recorder {
+                recorder.record(OrbitExchange::Deribit, connection_id, &text);
+            }
+            *sleep = 100;
+            let frame = match DeribitFrame::parse(&text) {
+                Ok(frame) => frame,
+                Err(err) => {
+                    warn!("{err}");
+                    OrbitConnectorHealth::on_error(&self.health);
+                    self.dead_letters.push(OrbitDeadLetter::new(
+                        OrbitExchange::Deribit,
+                        &err,
+                        text,
+                    ));
+                    continue;
+                }
+            };
+            match frame {
+                DeribitFrame::Book(ob) => match sequencer.check(&ob) {
+                    DeribitSequenceCheck::Apply => {
+                        self.send(
+                            ob.instrument_name.clone(),
+                            OrbitEventPayload::OrderbookUpdate(OrderbookUpdate::from(ob)),
+                        );
+                    }
+                    DeribitSequenceCheck::Stale => {
+                        debug!(
+                            "dropping stale book update {} change_id {}",
+                            ob.instrument_name, ob.change_id
+                        );
+                    }
+                    DeribitSequenceCheck::Gap => {
+                        let err = OrbitStreamError::Sequence {
+                            exchange: OrbitExchange::Deribit,
+                            symbol: ob.instrument_name.clone(),
+                            prev_change_id: ob.prev_change_id,
+                            change_id: ob.change_id,
+                        };
+                        warn!("{err}, resyncing");
+                        // storage drops the book before anything else lands on it
+                        self.send(
+                            ob.instrument_name.clone(),
+                            OrbitEventPayload::OrderbookResync,
+                        );
 
-            let mut hearbeat_timer: Instant = Instant::now();
-            while let Some(event) = stream.next().await {
-                match event {
-                    Ok(msg) => {
-                        if let Message::Text(text) = msg {
-                            let resp = serde_json::from_str::<HashMap<String, Value>>(&text)
-                                .expect("Error parsing Deribit");
-                            if let Some(method) = resp.get("method") {
-                                if let Value::String(method) = method {
-                                    match method.as_str() {
-                                        "subscription" => {
-                                            let ob: DeribitOrderbookDataWrapper =
-                                                serde_json::from_str(&text).expect("Can't parse");
-
-                                            let norm_ob: OrbitEventPayload =
-                                                OrbitEventPayload::OrderbookUpdate(
-                                                    OrderbookUpdate::from(ob.params.data.clone()),
-                                                );
-
-                                            let contract_type = symbol_details_map
-                                                .get(&ob.params.data.instrument_name)
-                                                .map(|x| x.contract_type.clone());
-
-                                            let currency = symbol_details_map
-                                                .get(&ob.params.data.instrument_name)
-                                                .map(|x| x.base.clone());
-
-                                            let expiration = symbol_details_map
-                                                .get(&ob.params.data.instrument_name)
-                                                .and_then(|x| x.expiration_datetime);
-
-                                            let strike = symbol_details_map
-                                                .get(&ob.params.data.instrument_name)
-                                                .and_then(|x| x.strike);
-
-                                            let orbit_event = OrbitEvent::new(
-                                                OrbitExchange::Deribit,
-                                                ob.params.data.instrument_name,
-                                                currency,
-                                                contract_type,
-                                                expiration,
-                                                strike,
-                                                Some(norm_ob),
-                                            );
-                                            // debug!("-- {:?}", ob);
-                                            let _ = sender
-                                                .send(orbit_event)
-                                                .map_err(|err| error!("Error: {}", err));
-                                        }
-                                        "heartbeat" => {
-                                            if hearbeat_timer.elapsed().as_secs() > 35 {
-                                                warn!("connection died, reconnecting...");
-                                                break;
-                                            }
-                                            hearbeat_timer = Instant::now();
-                                            debug!("received heatbeat pong {:?}", text);
-                                            let result = stream
-                                                .send(Message::Text(
-                                                    json!({
-                                                        "jsonrpc" : "2.0",
-                                                        "id" : 8212,
-                                                        "method" : "public/test",
-                                                        "params" : {}
-                                                    })
-                                                    .to_string(),
-                                                ))
-                                                .await;
-                                            debug!("sent heatbeat ping");
-                                        }
-                                        _ => {}
+                        match DeribitClient::get_order_book(
+                            &self.config.rest_url,
+                            &ob.instrument_name,
+                        )
+                        .await
+                        {
+                            Ok(snapshot) => {
+                                sequencer.reset(&snapshot);
+                                self.send(
+                                    snapshot.instrument_name.clone(),
+                                    OrbitEventPayload::OrderbookUpdate(OrderbookUpdate::from(
+                                        snapshot,
+                                    )),
+                                );
+                            }
+                            Err(err) => {
+                                // fall back to resubscribing, deribit sends a
+                                // fresh snapshot as the first message of a subscription
+                                error!(
+                                    "snapshot for {} failed: {}, resubscribing",
+                                    ob.instrument_name, err
+                                );
+                                sequencer.invalidate(&ob.instrument_name);
+                                let channel = vec![self.config.book_channel(&ob.instrument_name)];
+                                for method in ["public/unsubscribe", "public/subscribe"] {
+                                    if let Err(err) = stream
+                                        .send(Message::Text(
+                                            json!({
+                                                "jsonrpc": "2.0",
+                                                "method": method,
+                                                "id": 43,
+                                                "params": {
+                                                "channels": channel}
+                                            })
+                                            .to_string(),
+                                        ))
+                                        .await
+                                    {
+                                        result = Err(OrbitStreamError::subscribe(
+                                            OrbitExchange::Deribit,
+                                            err,
+                                        ));
+                                        break;
                                     }
+                                    debug!("sent {}", method);
+                                }
+                                if result.is_err() {
+                                    break;
                                 }
                             }
                         }
                     }
-                    Err(error) => {
-                        error!("Error: {}", error);
+                },
+                DeribitFrame::Ticker(ticker) => {
+                    let instrument_name = ticker.instrument_name.clone();
+                    for payload in ticker_payloads(ticker) {
+                        self.send(instrument_name.clone(), payload);
+                    }
+                }
+                DeribitFrame::Trades(trades) => {
+                    for trade in trades {
+                        self.send(
+                            trade.instrument_name.clone(),
+                            OrbitEventPayload::Trade(OrbitTrade::from(trade)),
+                        );
+                    }
+                }
+                DeribitFrame::Index(index) => {
+                    if let Some(orbit_event) = index_event(&self.price_indices, index) {
+                        let _ = self
+                            .sender
+                            .send(orbit_event)
+                            .map_err(|err| error!("Error: {}", err));
+                    }
+                }
+                DeribitFrame::Heartbeat => {
+                    if hearbeat_timer.elapsed().as_secs() > heartbeat_timeout {
+                        warn!("connection died, reconnecting...");
                         break;
                     }
+                    hearbeat_timer = Instant::now();
+                    debug!("received heatbeat pong {:?}", text);
+                    let result = stream
+                        .send(Message::Text(
+                            json!({
+                                "jsonrpc" : "2.0",
+                                "id" : 8212,
+                                "method" : "public/test",
+                                "params" : {}
+                            })
+                            .to_string(),
+                        ))
+                        .await;
+                    debug!("sent heatbeat ping {:?}", result);
                 }
+                DeribitFrame::Other => {}
             }
-            // Exponential backoff
-            warn!("Delta stream disconnected, re-connecting. Sleep:{}", sleep);
-            tokio::time::sleep(Duration::from_millis(sleep)).await;
-            sleep *= 2;
         }
+        if self.stopped {
+            OrbitConnectorHealth::on_stop(&self.health);
+        } else {
+            OrbitConnectorHealth::on_disconnect(&self.health);
+        }
+        result
+    }
+
+    fn send(&self, instrument_name: String, payload: OrbitEventPayload) {
+        let details = self.symbol_details_map.get(&instrument_name);
+        let orbit_event =
+            OrbitEvent::for_instrument(OrbitExchange::Deribit, instrument_name, details, payload);
+        let _ = self
+            .sender
+            .send(orbit_event)
+            .map_err(|err| error!("Error: {}", err));
+    }
+}
+
+// turns recorded frames into events the same way the live stream does, see replay
+#[derive(Debug, Default)]
+pub struct DeribitFrameDecoder {
+    symbol_details_map: HashMap<String, OrbitInstrument>,
+    price_indices: BTreeMap<String, OrbitCurrency>,
+    // each connection sequences its own books, recordings can interleave connections
+    sequencers: HashMap<Uuid, DeribitBookSequencer>,
+}
+
+impl DeribitFrameDecoder {
+    pub fn new(instruments: &[OrbitInstrument]) -> Self {
+        let instruments: Vec<OrbitInstrument> = instruments
+            .iter()
+            .filter(|x| x.exchange == OrbitExchange::Deribit)
+            .cloned()
+            .collect();
+        Self {
+            price_indices: price_indices(&instruments),
+            symbol_details_map: instruments
+                .into_iter()
+                .map(|x| (x.symbol.clone(), x))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    // a gap can't be healed from a recording, the rest snapshot the live stream fetched
+    // isn't in it, so the book stays empty until the next subscription snapshot
+    pub fn decode(
+        &mut self,
+        connection_id: Uuid,
+        raw: &str,
+    ) -> Result<Vec<OrbitEvent>, OrbitStreamError> {
+        let (instrument_name, payload) = match DeribitFrame::parse(raw)? {
+            DeribitFrame::Book(ob) => {
+                let instrument_name = ob.instrument_name.clone();
+                // sequencing starts over with every connection, like the live stream
+                let sequencer = self.sequencers.entry(connection_id).or_default();
+                let payload = match sequencer.check(&ob) {
+                    DeribitSequenceCheck::Apply => {
+                        OrbitEventPayload::OrderbookUpdate(OrderbookUpdate::from(ob))
+                    }
+                    DeribitSequenceCheck::Stale => return Ok(vec![]),
+                    DeribitSequenceCheck::Gap => {
+                        sequencer.invalidate(&instrument_name);
+                        OrbitEventPayload::OrderbookResync
+                    }
+                };
+                (instrument_name, payload)
+            }
+            DeribitFrame::Ticker(ticker) => {
+                let instrument_name = ticker.instrument_name.clone();
+                let details = self.symbol_details_map.get(&instrument_name);
+                return Ok(ticker_payloads(ticker)
+                    .into_iter()
+                    .map(|payload| {
+                        OrbitEvent::for_instrument(
+                            OrbitExchange::Deribit,
+                            instrument_name.clone(),
+                            details,
+                            payload,
+                        )
+                    })
+                    .collect());
+            }
+            DeribitFrame::Trades(trades) => {
+                return Ok(trades
+                    .into_iter()
+                    .map(|trade| {
+                        let instrument_name = trade.instrument_name.clone();
+                        OrbitEvent::for_instrument(
+                            OrbitExchange::Deribit,
+                            instrument_name.clone(),
+                            self.symbol_details_map.get(&instrument_name),
+                            OrbitEventPayload::Trade(OrbitTrade::from(trade)),
+                        )
+                    })
+                    .collect())
+            }
+            DeribitFrame::Index(index) => {
+                return Ok(index_event(&self.price_indices, index)
+                    .into_iter()
+                    .collect())
+            }
+            DeribitFrame::Heartbeat | DeribitFrame::Other => return Ok(vec![]),
+        };
+        let details = self.symbol_details_map.get(&instrument_name);
+        Ok(vec![OrbitEvent::for_instrument(
+            OrbitExchange::Deribit,
+            instrument_name,
+            details,
+            payload,
+        )])
+    }
+}
+
+fn is_streamed(contract_type: &OrbitContractType) -> bool {
+    matches!(
+        contract_type,
+        OrbitContractType::Future
+            | OrbitContractType::PutOption
+            | OrbitContractType::CallOption
+            | OrbitContractType::PerpetualFuture
+            | OrbitContractType::Spot
+    )
+}
+
+// the ticker, followed by the funding when it's a perpetual's
+fn ticker_payloads(ticker: DeribitTicker) -> Vec<OrbitEventPayload> {
+    let funding = ticker.funding();
+    let mut payloads = vec![OrbitEventPayload::Ticker(OrbitTicker::from(ticker))];
+    payloads.extend(funding.map(OrbitEventPayload::Funding));
+    payloads
+}
+
+// None for an index none of the instruments is priced off
+fn index_event(
+    price_indices: &BTreeMap<String, OrbitCurrency>,
+    index: DeribitIndexPrice,
+) -> Option<OrbitEvent> {
+    let currency = price_indices.get(&index.index_name)?.clone();
+    Some(OrbitEvent::index_price(
+        OrbitExchange::Deribit,
+        index.index_name,
+        currency,
+        OrbitIndexPrice {
+            price: index.price,
+            timestamp: Some(index.timestamp),
+        },
+    ))
+}
+
+enum DeribitFrame {
+    Book(DeribitOrderbook),
+    Index(DeribitIndexPrice),
+    Ticker(DeribitTicker),
+    // a trades notification carries every print of the interval
+    Trades(Vec<DeribitTrade>),
+    Heartbeat,
+    // subscription acks, test responses and whatever else carries no method we handle
+    Other,
+}
+
+impl DeribitFrame {
+    fn parse(text: &str) -> Result<Self, OrbitStreamError> {
+        let parse_error = |err| OrbitStreamError::parse(OrbitExchange::Deribit, err);
+        let mut resp = serde_json::from_str::<HashMap<String, Value>>(text).map_err(parse_error)?;
+        match resp.get("method") {
+            Some(Value::String(method)) if method == "subscription" => {
+                let params: DeribitSubscriptionParams =
+                    serde_json::from_value(resp.remove("params").unwrap_or_default())
+                        .map_err(parse_error)?;
+                // the channel name says what data holds
+                match params.channel.split('.').next() {
+                    Some("book") => Ok(DeribitFrame::Book(
+                        serde_json::from_value(params.data).map_err(parse_error)?,
+                    )),
+                    Some("ticker") => Ok(DeribitFrame::Ticker(
+                        serde_json::from_value(params.data).map_err(parse_error)?,
+                    )),
+                    Some("trades") => Ok(DeribitFrame::Trades(
+                        serde_json::from_value(params.data).map_err(parse_error)?,
+                    )),
+                    Some("deribit_price_index") => Ok(DeribitFrame::Index(
+                        serde_json::from_value(params.data).map_err(parse_error)?,
+                    )),
+                    _ => Ok(DeribitFrame::Other),
+                }
+            }
+            Some(Value::String(method)) if method == "heartbeat" => Ok(DeribitFrame::Heartbeat),
+            _ => Ok(DeribitFrame::Other),
+        }
+    }
+}
+
+#[async_trait]
+impl OrbitExchangeConnector for DeribitClient {
+    fn exchange(&self) -> OrbitExchange {
+        OrbitExchange::Deribit
+    }
+
+    async fn get_instruments(&self) -> Result<Vec<OrbitInstrument>, Error> {
+        let data = DeribitClient::get_instruments(self).await?;
+        Ok(data
+            .iter()
+            .flat_map(|currency| currency.result.iter().map(OrbitInstrument::from))
+            .collect())
+    }
+
+    async fn consume(
+        &self,
+        sender: Sender<OrbitEvent>,
+        instruments: Vec<OrbitInstrument>,
+    ) -> Result<(), Error> {
+        debug!(
+            "deribit client {} consuming {} instruments",
+            self.id,
+            instruments.len()
+        );
+        let mut streams = self
+            .streams
+            .lock()
+            .map_err(|_| anyhow!("deribit streams poisoned"))?;
+        streams.set_sender(sender);
+        self.spawn_streams(&mut streams, instruments)
+    }
+
+    async fn subscribe(&self, instruments: Vec<OrbitInstrument>) -> Result<(), Error> {
+        debug!(
+            "deribit client {} subscribing {} instruments",
+            self.id,
+            instruments.len()
+        );
+        let mut streams = self
+            .streams
+            .lock()
+            .map_err(|_| anyhow!("deribit streams poisoned"))?;
+        self.spawn_streams(&mut streams, instruments)
+    }
+
+    async fn unsubscribe(&self, instruments: Vec<OrbitInstrument>) -> Result<(), Error> {
+        debug!(
+            "deribit client {} unsubscribing {} instruments",
+            self.id,
+            instruments.len()
+        );
+        let mut streams = self
+            .streams
+            .lock()
+            .map_err(|_| anyhow!("deribit streams poisoned"))?;
+        streams.remove(&instruments);
+        Ok(())
+    }
+
+    fn health(&self) -> OrbitConnectorHealth {
+        self.health.read().map(|h| h.clone()).unwrap_or_default()
     }
 }
 
@@ -250,7 +803,8 @@ pub struct DeribitInstrumentsWrapper {
 pub struct DeribitInstrument {
     pub base_currency: String,
     // pub block_trade_commission: f64,
-    // pub contract_size: u64,
+    // in the base currency for options and linear futures, in USD for inverse ones
+    pub contract_size: Option<Decimal>,
     pub counter_currency: String,
     pub creation_timestamp: u64,
     pub expiration_timestamp: i64, //deribit for perps uses expiration year 3000
@@ -260,15 +814,15 @@ pub struct DeribitInstrument {
     pub is_active: bool,
     pub kind: DeribitInstrumentKind,
     // pub leverage: u64,
-    // pub maker_commission: f64,
-    // pub min_trade_amount: f64,
+    pub maker_commission: Option<f64>,
+    pub min_trade_amount: Option<Decimal>,
     pub option_type: Option<DeribitOptionType>,
     pub price_index: String,
     pub quote_currency: String, //todo i think its actually counter currency in deribit api, implement USD/USDT for Orbit Currencies
     pub settlement_period: DeribitSettlementPeriod,
-    pub strike: Option<f64>,
-    // pub taker_commision: f64,
-    // pub tick_size: f64,
+    pub strike: Option<Decimal>,
+    pub taker_commission: Option<f64>,
+    pub tick_size: Option<Decimal>,
 }
 
 #[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -305,7 +859,7 @@ pub enum DeribitOrderbookAction {
 }
 
 #[derive(Deserialize, Debug, Clone)]
-pub struct DeribitOrderbookUpdate(pub DeribitOrderbookAction, pub f64, pub f64);
+pub struct DeribitOrderbookUpdate(pub DeribitOrderbookAction, pub Decimal, pub Decimal);
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "lowercase")]
@@ -337,6 +891,169 @@ pub struct DeribitOrderbook {
     pub kind: DeribitOrderbookUpdateType,
 }
 
+// any subscription notification, data is decoded once the channel is known
+#[derive(Deserialize, Debug, Clone)]
+pub struct DeribitSubscriptionParams {
+    pub channel: String,
+    pub data: Value,
+}
+
+// ticker.{instrument}.{interval} data, ivs are in percent. Deribit sends 0 for the bid
+// or ask iv of an empty side and leaves greeks and ivs out for futures. Only perpetuals
+// carry the funding fields, both are fractions.
+#[derive(Deserialize, Debug, Clone)]
+pub struct DeribitTicker {
+    pub instrument_name: String,
+    pub timestamp: u64,
+    pub mark_price: Decimal,
+    pub mark_iv: Option<f64>,
+    pub bid_iv: Option<f64>,
+    pub ask_iv: Option<f64>,
+    pub greeks: Option<DeribitGreeks>,
+    pub open_interest: Option<Decimal>,
+    pub underlying_price: Option<Decimal>,
+    pub index_price: Option<Decimal>,
+    pub current_funding: Option<f64>,
+    pub funding_8h: Option<f64>,
+}
+
+impl DeribitTicker {
+    pub fn funding(&self) -> Option<OrbitFunding> {
+        Some(OrbitFunding {
+            timestamp: self.timestamp,
+            rate_8h: self.funding_8h?,
+            next_funding_at: None,
+        })
+    }
+}
+
+// deribit_price_index.{index_name} data
+#[derive(Deserialize, Debug, Clone)]
+pub struct DeribitIndexPrice {
+    pub index_name: String,
+    pub price: Decimal,
+    pub timestamp: u64,
+}
+
+// trades.{instrument}.{interval} data, direction is the taker's and iv (options only) in percent
+#[derive(Deserialize, Debug, Clone)]
+pub struct DeribitTrade {
+    pub trade_id: String,
+    pub instrument_name: String,
+    pub timestamp: u64,
+    pub price: Decimal,
+    pub amount: Decimal,
+    pub direction: DeribitDirection,
+    pub iv: Option<f64>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum DeribitDirection {
+    Buy,
+    Sell,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct DeribitGreeks {
+    pub delta: f64,
+    pub gamma: f64,
+    pub vega: f64,
+    pub theta: f64,
+    pub rho: f64,
+}
+
+// response of public/get_order_book, levels are plain [price, amount] pairs
+#[derive(Deserialize, Debug, Clone)]
+pub struct DeribitOrderbookSnapshotWrapper {
+    pub result: DeribitOrderbookSnapshot,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct DeribitOrderbookSnapshot {
+    pub asks: Vec<(Decimal, Decimal)>,
+    pub bids: Vec<(Decimal, Decimal)>,
+    pub change_id: i64,
+    pub instrument_name: String,
+    pub timestamp: u64,
+}
+
+impl From<DeribitOrderbookSnapshot> for DeribitOrderbook {
+    fn from(snapshot: DeribitOrderbookSnapshot) -> Self {
+        let levels = |levels: Vec<(Decimal, Decimal)>| {
+            levels
+                .into_iter()
+                .map(|(price, amount)| {
+                    DeribitOrderbookUpdate(DeribitOrderbookAction::New, price, amount)
+                })
+                .collect()
+        };
+        Self {
+            asks: levels(snapshot.asks),
+            bids: levels(snapshot.bids),
+            change_id: snapshot.change_id,
+            instrument_name: snapshot.instrument_name,
+            prev_change_id: None,
+            timestamp: snapshot.timestamp,
+            kind: DeribitOrderbookUpdateType::Snapshot,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeribitSequenceCheck {
+    Apply,
+    Stale,
+    Gap,
+}
+
+// deribit chains every incremental book message to the previous one through
+// prev_change_id, anything that doesn't continue the chain means we lost a message
+#[derive(Debug, Default)]
+pub struct DeribitBookSequencer {
+    last_change_id: HashMap<String, i64>,
+    awaiting_snapshot: HashSet<String>,
+}
+
+impl DeribitBookSequencer {
+    pub fn check(&mut self, ob: &DeribitOrderbook) -> DeribitSequenceCheck {
+        if let DeribitOrderbookUpdateType::Snapshot = ob.kind {
+            self.reset(ob);
+            return DeribitSequenceCheck::Apply;
+        }
+        match self.last_change_id.get(&ob.instrument_name) {
+            Some(last) if ob.change_id <= *last => DeribitSequenceCheck::Stale,
+            // prev_change_id below last only happens right after a REST snapshot that was
+            // taken in the middle of an aggregated interval, deribit levels carry absolute
+            // amounts so re-applying the overlapping part is harmless
+            Some(last) if ob.prev_change_id.is_some_and(|prev| prev <= *last) => {
+                self.last_change_id
+                    .insert(ob.instrument_name.clone(), ob.change_id);
+                DeribitSequenceCheck::Apply
+            }
+            _ if self.awaiting_snapshot.contains(&ob.instrument_name) => {
+                DeribitSequenceCheck::Stale
+            }
+            _ => {
+                self.last_change_id.remove(&ob.instrument_name);
+                DeribitSequenceCheck::Gap
+            }
+        }
+    }
+
+    pub fn reset(&mut self, snapshot: &DeribitOrderbook) {
+        self.awaiting_snapshot.remove(&snapshot.instrument_name);
+        self.last_change_id
+            .insert(snapshot.instrument_name.clone(), snapshot.change_id);
+    }
+
+    // drop everything for the instrument until a subscription snapshot comes in
+    pub fn invalidate(&mut self, instrument_name: &str) {
+        self.last_change_id.remove(instrument_name);
+        self.awaiting_snapshot.insert(instrument_name.to_owned());
+    }
+}
+
 impl From<&DeribitOrderbookUpdate> for OrderbookUpdateLevel {
     fn from(deribit_orderbook_level: &DeribitOrderbookUpdate) -> Self {
         let normalized_orderbook_update_type = match deribit_orderbook_level.0 {
@@ -354,6 +1071,7 @@ impl From<&DeribitOrderbookUpdate> for OrderbookUpdateLevel {
 impl From<DeribitOrderbook> for OrderbookUpdate {
     fn from(deribit_orderbook: DeribitOrderbook) -> Self {
         Self {
+            is_snapshot: matches!(deribit_orderbook.kind, DeribitOrderbookUpdateType::Snapshot),
             timestamp: deribit_orderbook.timestamp,
             bids: deribit_orderbook
                 .bids
@@ -369,6 +1087,45 @@ impl From<DeribitOrderbook> for OrderbookUpdate {
     }
 }
 
+impl From<DeribitTicker> for OrbitTicker {
+    fn from(ticker: DeribitTicker) -> Self {
+        let iv = |iv: Option<f64>| iv.filter(|iv| *iv > 0.0).map(|iv| iv / 100.0);
+        Self {
+            timestamp: ticker.timestamp,
+            mark_price: ticker.mark_price,
+            mark_iv: iv(ticker.mark_iv),
+            bid_iv: iv(ticker.bid_iv),
+            ask_iv: iv(ticker.ask_iv),
+            greeks: ticker.greeks.map(|greeks| OrbitGreeks {
+                delta: greeks.delta,
+                gamma: greeks.gamma,
+                vega: greeks.vega,
+                theta: greeks.theta,
+                rho: greeks.rho,
+            }),
+            open_interest: ticker.open_interest,
+            underlying_price: ticker.underlying_price,
+            index_price: ticker.index_price,
+        }
+    }
+}
+
+impl From<DeribitTrade> for OrbitTrade {
+    fn from(trade: DeribitTrade) -> Self {
+        Self {
+            trade_id: Some(trade.trade_id),
+            timestamp: trade.timestamp,
+            price: trade.price,
+            size: trade.amount,
+            side: match trade.direction {
+                DeribitDirection::Buy => OrbitTradeSide::Buy,
+                DeribitDirection::Sell => OrbitTradeSide::Sell,
+            },
+            iv: trade.iv.map(|iv| iv / 100.0),
+        }
+    }
+}
+
 impl From<&DeribitInstrument> for OrbitContractType {
     fn from(deribit_product: &DeribitInstrument) -> Self {
         match deribit_product.kind {
@@ -394,39 +1151,73 @@ impl From<&String> for OrbitCurrency {
             "btc" => OrbitCurrency::Btc,
             "eth" => OrbitCurrency::Eth,
             "sol" => OrbitCurrency::Sol,
+            "usd" => OrbitCurrency::Usd,
+            "usdt" => OrbitCurrency::Usdt,
+            "usdc" => OrbitCurrency::Usdc,
             _ => OrbitCurrency::Unimplemented,
         }
     }
 }
 
+// option fees are capped at 12.5% of the premium, the listings don't say so, the fee
+// schedule does
+const DERIBIT_OPTION_FEE_CAP: f64 = 0.125;
+
 impl From<&DeribitInstrument> for OrbitInstrument {
     fn from(deribit_product: &DeribitInstrument) -> Self {
         let contract_type = OrbitContractType::from(deribit_product);
-        let strike = deribit_product.strike.map(|strike| strike as u64);
+        let strike = deribit_product.strike.map(|strike| strike.normalize());
+        let base = OrbitCurrency::from(&deribit_product.base_currency);
+        let quote = OrbitCurrency::from(&deribit_product.quote_currency);
+        // futures say which they are, options are inverse when quoted in their own coin
+        let settlement = match deribit_product.future_type.as_deref() {
+            Some("reversed") => OrbitSettlement::Inverse,
+            Some(_) => OrbitSettlement::Linear,
+            None if base == quote => OrbitSettlement::Inverse,
+            None => OrbitSettlement::Linear,
+        };
+        // amounts are in the base currency except for inverse futures, which trade USD
+        let size_unit = match (&contract_type, settlement) {
+            (
+                OrbitContractType::Future | OrbitContractType::PerpetualFuture,
+                OrbitSettlement::Inverse,
+            ) => OrbitSizeUnit::UsdContracts(Decimal::ONE),
+            _ => OrbitSizeUnit::Underlying,
+        };
 
-        let expiration_date = {
-            let d: DateTime<Utc> = DateTime::from_utc(
-                NaiveDateTime::from_timestamp_millis(deribit_product.expiration_timestamp).unwrap(),
-                Utc,
-            );
-            let nd = d.date_naive();
-            let t = NaiveTime::from_hms_opt(0, 0, 0).unwrap();
-            let a = NaiveDateTime::new(nd, t);
-            let d: DateTime<Utc> = DateTime::from_local(a, Utc);
-            // let d = d.timestamp_millis();
-            Some(d)
+        let premium_fee_cap = match contract_type {
+            OrbitContractType::CallOption | OrbitContractType::PutOption => {
+                Some(DERIBIT_OPTION_FEE_CAP)
+            }
+            _ => None,
         };
 
+        let expiration_datetime: DateTime<Utc> = DateTime::from_utc(
+            NaiveDateTime::from_timestamp_millis(deribit_product.expiration_timestamp).unwrap(),
+            Utc,
+        );
+
         // debug!("deribit timestamp transformed {:?}, instrument {:?}", deribit_product.expiration_timestamp, deribit_product.instrument_name);
         Self {
             symbol: deribit_product.instrument_name.clone(),
-            base: OrbitCurrency::from(&deribit_product.base_currency),
-            quote: OrbitCurrency::from(&deribit_product.quote_currency),
+            base,
+            quote,
+            settlement,
             strike,
-            expiration_datetime: Some(DateTime::from_utc(NaiveDateTime::from_timestamp_millis(deribit_product.expiration_timestamp).unwrap(), Utc)),
-            expiration_date,
+            expiration_datetime: Some(expiration_datetime),
+            expiration_date: Some(expiration_key(expiration_datetime)),
             contract_type,
             exchange: OrbitExchange::Deribit,
+            price_index: Some(deribit_product.price_index.clone()),
+            specs: OrbitContractSpecs {
+                size_unit,
+                contract_size: deribit_product.contract_size,
+                tick_size: deribit_product.tick_size,
+                min_size: deribit_product.min_trade_amount,
+                maker_fee: deribit_product.maker_commission,
+                taker_fee: deribit_product.taker_commission,
+                premium_fee_cap,
+            },
             //todo add native instrument name for websocket subs
         }
     }
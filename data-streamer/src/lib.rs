@@ -1,56 +1,126 @@
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::Debug;
 use std::hash::Hash;
-use std::time::Instant;
+use std::sync::{Arc, RwLock};
 
-use anyhow::Error;
-use chrono::{DateTime, NaiveDateTime, Utc};
-use log::debug;
-use ordered_float::OrderedFloat;
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Duration, Utc};
+use log::{debug, error, info};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast::{self, Receiver, Sender};
+use tokio::task::JoinHandle;
 
-mod exchanges;
+pub mod config;
+pub mod error;
+pub mod exchanges;
+pub mod lifecycle;
+pub mod matching;
+pub mod recorder;
+pub mod replay;
+use config::OrbitConfig;
 use exchanges::delta::model::DeltaClient;
 use exchanges::deribit::model::DeribitClient;
-use tracing_subscriber::field::debug;
+use lifecycle::{of_exchange, OrbitListingDiff};
+use matching::{OrbitMatchReport, OrbitMatchRules};
+use recorder::OrbitRecorder;
 use uuid::Uuid;
 
 #[derive(Debug)]
 pub struct OrbitData {
     pub exchanges: Vec<OrbitExchange>,
     pub currencies: Vec<OrbitCurrency>,
-    pub clients: HashMap<OrbitExchange, OrbitExchangeClient>,
+    pub clients: HashMap<OrbitExchange, Box<dyn OrbitExchangeConnector>>,
     pub sender: Sender<OrbitEvent>,
     pub receiver: Receiver<OrbitEvent>,
 }
 
 impl OrbitData {
     pub fn new(exchanges: Vec<OrbitExchange>, currencies: Vec<OrbitCurrency>) -> Self {
-        let (sender, receiver) = broadcast::channel::<OrbitEvent>(250_000); //todo 10_000? check channel congestion
-        let mut clients: HashMap<OrbitExchange, OrbitExchangeClient> =
-            HashMap::with_capacity(exchanges.capacity());
-
-        exchanges.iter().for_each(|exchange| match exchange {
-            OrbitExchange::Delta => {
-                clients.insert(
-                    OrbitExchange::Delta,
-                    OrbitExchangeClient::Delta(DeltaClient::new()), //todo pass sender here?
-                );
-            }
-            OrbitExchange::Deribit => {
-                clients.insert(
-                    OrbitExchange::Deribit,
-                    OrbitExchangeClient::Deribit(DeribitClient::new()), //todo pass sender here?
-                );
-            }
-        });
+        Self::from_config(&OrbitConfig::default(), exchanges, currencies)
+    }
 
-        Self {
-            exchanges,
+    pub fn from_config(
+        config: &OrbitConfig,
+        exchanges: Vec<OrbitExchange>,
+        currencies: Vec<OrbitCurrency>,
+    ) -> Self {
+        Self::build(config, exchanges, currencies, None)
+    }
+
+    // same as from_config with every raw frame of every stream going to the recorder
+    pub fn recording(
+        config: &OrbitConfig,
+        exchanges: Vec<OrbitExchange>,
+        currencies: Vec<OrbitCurrency>,
+        recorder: OrbitRecorder,
+    ) -> Self {
+        Self::build(config, exchanges, currencies, Some(recorder))
+    }
+
+    fn build(
+        config: &OrbitConfig,
+        exchanges: Vec<OrbitExchange>,
+        currencies: Vec<OrbitCurrency>,
+        recorder: Option<OrbitRecorder>,
+    ) -> Self {
+        let connectors = exchanges
+            .iter()
+            .map(|exchange| -> Box<dyn OrbitExchangeConnector> {
+                match exchange {
+                    OrbitExchange::Delta => {
+                        let client = DeltaClient::from_config(config.delta.clone());
+                        Box::new(match recorder.clone() {
+                            Some(recorder) => client.with_recorder(recorder),
+                            None => client,
+                        })
+                    }
+                    OrbitExchange::Deribit => {
+                        let client = DeribitClient::from_config(config.deribit.clone());
+                        Box::new(match recorder.clone() {
+                            Some(recorder) => client.with_recorder(recorder),
+                            None => client,
+                        })
+                    }
+                }
+            })
+            .collect();
+        Self::with_connectors(connectors, currencies)
+    }
+
+    // lets callers bring their own venue without going through the OrbitExchange match in new()
+    pub fn with_connectors(
+        connectors: Vec<Box<dyn OrbitExchangeConnector>>,
+        currencies: Vec<OrbitCurrency>,
+    ) -> Self {
+        let (sender, receiver) = broadcast::channel::<OrbitEvent>(250_000); //todo 10_000? check channel congestion
+        let mut orbit_data = Self {
+            exchanges: Vec::with_capacity(connectors.len()),
             currencies,
-            clients,
+            clients: HashMap::with_capacity(connectors.len()),
             sender,
             receiver,
+        };
+        connectors
+            .into_iter()
+            .for_each(|connector| orbit_data.add_connector(connector));
+        orbit_data
+    }
+
+    pub fn add_connector(&mut self, connector: Box<dyn OrbitExchangeConnector>) {
+        let exchange = connector.exchange();
+        if !self.exchanges.contains(&exchange) {
+            self.exchanges.push(exchange.clone());
         }
+        self.clients.insert(exchange, connector);
+    }
+
+    pub fn health(&self) -> HashMap<OrbitExchange, OrbitConnectorHealth> {
+        self.clients
+            .iter()
+            .map(|(exchange, client)| (exchange.clone(), client.health()))
+            .collect()
     }
 
     pub async fn get_all_instruments_raw(
@@ -60,36 +130,13 @@ impl OrbitData {
         let mut instruments: HashMap<&OrbitExchange, Vec<OrbitInstrument>> =
             HashMap::with_capacity(self.clients.capacity());
         for (exchange, client) in self.clients.iter() {
-            match client {
-                OrbitExchangeClient::Delta(client) => {
-                    let data = client.get_products().await?;
-                    let orbit_data: Vec<OrbitInstrument> =
-                        data.result.iter().map(OrbitInstrument::from).collect();
-                    debug!(
-                        "received {:?} instruments from {:?}",
-                        orbit_data.len(),
-                        exchange
-                    );
-                    instruments.insert(exchange, orbit_data);
-                }
-                OrbitExchangeClient::Deribit(client) => {
-                    let data = client.get_instruments().await?;
-                    let mut norm_deribit_instruments = vec![];
-                    data.iter().for_each(|currency| {
-                        let orbit_data: Vec<OrbitInstrument> =
-                            currency.result.iter().map(OrbitInstrument::from).collect();
-                        norm_deribit_instruments.push(orbit_data);
-                    });
-                    let flattened_norm_data: Vec<OrbitInstrument> =
-                        norm_deribit_instruments.into_iter().flatten().collect();
-                    debug!(
-                        "received {:?} instruments from {:?}",
-                        flattened_norm_data.len(),
-                        exchange
-                    );
-                    instruments.insert(exchange, flattened_norm_data);
-                }
-            }
+            let orbit_data = client.get_instruments().await?;
+            debug!(
+                "received {:?} instruments from {:?}",
+                orbit_data.len(),
+                exchange
+            );
+            instruments.insert(exchange, orbit_data);
         }
 
         // Only use instruments we are interested in BTC ETH SOL; TODO optimize this with filter or fold, not critical
@@ -105,7 +152,7 @@ impl OrbitData {
                 })
             });
 
-            result.insert(k.clone(), a);
+            result.insert(*k, a);
         }
 
         Ok(result)
@@ -120,22 +167,23 @@ impl OrbitData {
 
     // todo error handling, what happens if common instruments number > total instruments for any of the exchanges etc
     pub async fn get_common_instruments(&self) -> Result<Vec<OrbitInstrument>, Error> {
+        let report = self.match_instruments(&OrbitMatchRules::default()).await?;
+        for near_miss in report.near_misses.iter() {
+            debug!("near miss {}", near_miss);
+        }
+        Ok(report.instruments())
+    }
+
+    // matches the listings of all exchanges, see OrbitMatchReport
+    pub async fn match_instruments(
+        &self,
+        rules: &OrbitMatchRules,
+    ) -> Result<OrbitMatchReport, Error> {
         let instruments_map: HashMap<&OrbitExchange, Vec<OrbitInstrument>> =
             self.get_all_instruments_raw().await?;
-        let mut map: HashMap<String, Vec<OrbitInstrument>> = HashMap::new();
-        for (_exchange, instruments) in instruments_map.iter() {
-            instruments.iter().for_each(|x| {
-                map.entry(x.get_cmp_code())
-                    .and_modify(|list| list.push(x.clone()))
-                    .or_insert_with(|| vec![x.clone()]);
-            });
-        }
-        map.retain(|_k, v| v.len() == instruments_map.len());
-        let result = map
-            .into_values()
-            .flatten()
-            .collect::<Vec<OrbitInstrument>>();
-        Ok(result)
+        let exchanges: Vec<OrbitExchange> = instruments_map.keys().map(|x| (*x).clone()).collect();
+        let instruments: Vec<OrbitInstrument> = instruments_map.into_values().flatten().collect();
+        Ok(OrbitMatchReport::new(&instruments, &exchanges, rules))
     }
 
     pub fn consume_all_instruments() {
@@ -146,60 +194,377 @@ impl OrbitData {
         &self,
         symbols: Vec<OrbitInstrument>,
     ) -> Result<Receiver<OrbitEvent>, Error> {
-        for (_exchange, client) in self.clients.iter() {
-            match client {
-                OrbitExchangeClient::Delta(client) => {
-                    client.consume(self.sender.clone(), symbols.clone()).await?;
-                }
-                OrbitExchangeClient::Deribit(client) => {
-                    client.consume(self.sender.clone(), symbols.clone()).await?;
+        for (exchange, client) in self.clients.iter() {
+            let exchange_symbols = of_exchange(&symbols, exchange);
+            client
+                .consume(self.sender.clone(), exchange_symbols)
+                .await?;
+        }
+        Ok(self.sender.subscribe())
+    }
+
+    // One pass of the instrument refresher over the consumed instruments. Listings are
+    // fetched again and diffed, new instruments are announced before they're subscribed
+    // and removed ones announced after they're unsubscribed, so storage has the book
+    // before its first update and drops it after the last one.
+    pub async fn refresh_instruments(
+        &self,
+        instruments: &mut Vec<OrbitInstrument>,
+    ) -> Result<OrbitListingDiff, Error> {
+        let latest = self.get_all_instruments().await?;
+        let diff = OrbitListingDiff::new(instruments, &latest, Utc::now());
+        if diff.is_empty() {
+            return Ok(diff);
+        }
+        for event in diff.listed_events() {
+            let _ = self.sender.send(event);
+        }
+        let removed: Vec<OrbitInstrument> = diff.removed().cloned().collect();
+        for (exchange, client) in self.clients.iter() {
+            let listed = of_exchange(&diff.listed, exchange);
+            if !listed.is_empty() {
+                client.subscribe(listed).await?;
+            }
+            let removed = of_exchange(&removed, exchange);
+            if !removed.is_empty() {
+                client.unsubscribe(removed).await?;
+            }
+        }
+        for event in diff.removed_events() {
+            let _ = self.sender.send(event);
+        }
+        diff.apply(instruments);
+        Ok(diff)
+    }
+
+    // refreshes the consumed instruments every interval, a failed refresh is retried on
+    // the next one
+    pub fn spawn_instrument_refresher(
+        self: &Arc<Self>,
+        mut instruments: Vec<OrbitInstrument>,
+        interval: std::time::Duration,
+    ) -> JoinHandle<()> {
+        let orbit_data = self.clone();
+        tokio::spawn(async move {
+            let mut timer = tokio::time::interval(interval);
+            // the first tick is immediate, the instruments were just fetched
+            timer.tick().await;
+            loop {
+                timer.tick().await;
+                match orbit_data.refresh_instruments(&mut instruments).await {
+                    Ok(diff) if !diff.is_empty() => info!(
+                        "instruments refreshed: {} listed, {} delisted, {} expired",
+                        diff.listed.len(),
+                        diff.delisted.len(),
+                        diff.expired.len()
+                    ),
+                    Ok(_) => {}
+                    Err(err) => error!("instrument refresh failed: {err}"),
                 }
             }
+        })
+    }
+}
+
+// seconds between two instrument refreshes unless the caller picks another interval
+pub const DEFAULT_INSTRUMENT_REFRESH_SECS: u64 = 300;
+
+// Everything OrbitData needs from a venue: instrument discovery, streaming and health.
+// Implementors normalize into Orbit types, so nothing exchange specific leaks out of
+// the exchanges module.
+#[async_trait]
+pub trait OrbitExchangeConnector: Debug + Send + Sync {
+    fn exchange(&self) -> OrbitExchange;
+
+    async fn get_instruments(&self) -> Result<Vec<OrbitInstrument>, Error>;
+
+    // spawns the stream tasks and returns, events are pushed into sender
+    async fn consume(
+        &self,
+        sender: Sender<OrbitEvent>,
+        instruments: Vec<OrbitInstrument>,
+    ) -> Result<(), Error>;
+
+    // follows more instruments on the running streams, after consume
+    async fn subscribe(&self, instruments: Vec<OrbitInstrument>) -> Result<(), Error> {
+        Err(anyhow!(
+            "{:?} can't add {} instruments to its streams",
+            self.exchange(),
+            instruments.len()
+        ))
+    }
+
+    async fn unsubscribe(&self, instruments: Vec<OrbitInstrument>) -> Result<(), Error> {
+        Err(anyhow!(
+            "{:?} can't drop {} instruments from its streams",
+            self.exchange(),
+            instruments.len()
+        ))
+    }
+
+    fn health(&self) -> OrbitConnectorHealth;
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OrbitConnectorHealth {
+    pub streams: usize,
+    pub connected: usize,
+    pub reconnects: u64,
+    pub messages: u64,
+    // failed connects, subscribes and dead lettered frames
+    pub errors: u64,
+    pub last_message_at: Option<DateTime<Utc>>,
+}
+
+impl OrbitConnectorHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.streams > 0 && self.connected == self.streams
+    }
+
+    pub fn on_spawn(health: &RwLock<Self>) {
+        if let Ok(mut h) = health.write() {
+            h.streams += 1;
+        }
+    }
+
+    pub fn on_connect(health: &RwLock<Self>) {
+        if let Ok(mut h) = health.write() {
+            h.connected += 1;
+        }
+    }
+
+    // the stream ended for good, its instruments were all unsubscribed
+    pub fn on_stop(health: &RwLock<Self>) {
+        if let Ok(mut h) = health.write() {
+            h.streams = h.streams.saturating_sub(1);
+            h.connected = h.connected.saturating_sub(1);
+        }
+    }
+
+    pub fn on_disconnect(health: &RwLock<Self>) {
+        if let Ok(mut h) = health.write() {
+            h.connected = h.connected.saturating_sub(1);
+            h.reconnects += 1;
+        }
+    }
+
+    pub fn on_error(health: &RwLock<Self>) {
+        if let Ok(mut h) = health.write() {
+            h.errors += 1;
+        }
+    }
+
+    pub fn on_message(health: &RwLock<Self>) {
+        if let Ok(mut h) = health.write() {
+            h.messages += 1;
+            h.last_message_at = Some(Utc::now());
         }
-        Ok(self.sender.subscribe())
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct OrbitInstrument {
     symbol: String,
     base: OrbitCurrency,
+    // what its prices are counted in, the underlying coin for inverse options
     quote: OrbitCurrency,
-    strike: Option<u64>,
+    settlement: OrbitSettlement,
+    strike: Option<Strike>,
     expiration_datetime: Option<DateTime<Utc>>, // datetime?todo
     expiration_date: Option<DateTime<Utc>>,     // datetime?
     contract_type: OrbitContractType,
     exchange: OrbitExchange,
+    // index the contract is marked and settled against, e.g. btc_usd on deribit
+    price_index: Option<String>,
+    specs: OrbitContractSpecs,
 }
 
 impl OrbitInstrument {
-    pub fn get_cmp_code(&self) -> String {
-        let d = match self.expiration_date {
-            Some(date) => {
-                let d: DateTime<Utc> = date;
-                    // DateTime::from_utc(NaiveDateTime::from_timestamp_millis(date).unwrap(), Utc);
-                Some(d)
-            }
-            None => None,
+    // an instrument listed outside the exchange clients, settled in its quote currency unless
+    // that's the underlying itself
+    pub fn new(
+        exchange: OrbitExchange,
+        symbol: String,
+        base: OrbitCurrency,
+        quote: OrbitCurrency,
+        contract_type: OrbitContractType,
+    ) -> Self {
+        let settlement = if base == quote {
+            OrbitSettlement::Inverse
+        } else {
+            OrbitSettlement::Linear
         };
-        format!(
-            "{:?}_{:?}_{:?}_{:?}",
-            self.contract_type, self.base, d, self.strike
-        )
+        Self {
+            symbol,
+            base,
+            quote,
+            settlement,
+            strike: None,
+            expiration_datetime: None,
+            expiration_date: None,
+            contract_type,
+            exchange,
+            price_index: None,
+            specs: OrbitContractSpecs::default(),
+        }
+    }
+
+    pub fn with_settlement(mut self, settlement: OrbitSettlement) -> Self {
+        self.settlement = settlement;
+        self
+    }
+
+    pub fn with_strike(mut self, strike: Strike) -> Self {
+        self.strike = Some(strike.normalize());
+        self
+    }
+
+    // the exact settlement time, the day it's keyed by follows
+    pub fn with_expiration(mut self, expiration_datetime: DateTime<Utc>) -> Self {
+        self.expiration_datetime = Some(expiration_datetime);
+        self.expiration_date = Some(expiration_key(expiration_datetime));
+        self
+    }
+
+    pub fn with_price_index(mut self, price_index: String) -> Self {
+        self.price_index = Some(price_index);
+        self
+    }
+
+    pub fn with_specs(mut self, specs: OrbitContractSpecs) -> Self {
+        self.specs = specs;
+        self
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    pub fn base(&self) -> &OrbitCurrency {
+        &self.base
+    }
+
+    pub fn quote(&self) -> &OrbitCurrency {
+        &self.quote
+    }
+
+    pub fn settlement(&self) -> OrbitSettlement {
+        self.settlement
+    }
+
+    pub fn strike(&self) -> Option<Strike> {
+        self.strike
+    }
+
+    // exact settlement time, needed for time to expiry when pricing
+    pub fn expiration_datetime(&self) -> Option<DateTime<Utc>> {
+        self.expiration_datetime
+    }
+
+    pub fn expiration_date(&self) -> Option<Expiration> {
+        self.expiration_date
+    }
+
+    pub fn contract_type(&self) -> &OrbitContractType {
+        &self.contract_type
+    }
+
+    pub fn exchange(&self) -> &OrbitExchange {
+        &self.exchange
+    }
+
+    pub fn price_index(&self) -> Option<&str> {
+        self.price_index.as_deref()
+    }
+
+    pub fn specs(&self) -> &OrbitContractSpecs {
+        &self.specs
+    }
+
+    // the venue's size at the given price in units of the underlying
+    pub fn to_underlying(&self, amount: Amount, price: Price) -> Amount {
+        self.specs.size_unit.to_underlying(amount, price)
+    }
+
+    // book levels and trades sized in the underlying, the rest as is
+    pub fn normalize(&self, payload: OrbitEventPayload) -> OrbitEventPayload {
+        match payload {
+            OrbitEventPayload::OrderbookUpdate(mut update) => {
+                for level in update.bids.iter_mut().chain(update.asks.iter_mut()) {
+                    level.2 = self.to_underlying(level.2, level.1);
+                }
+                OrbitEventPayload::OrderbookUpdate(update)
+            }
+            OrbitEventPayload::Trade(mut trade) => {
+                trade.size = self.to_underlying(trade.size, trade.price);
+                OrbitEventPayload::Trade(trade)
+            }
+            payload => payload,
+        }
+    }
+}
+
+// how a venue counts order sizes
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum OrbitSizeUnit {
+    // coins of the underlying, deribit options and linear futures
+    Underlying,
+    // contracts of a fixed amount of underlying each, delta's linear contracts
+    Contracts(Decimal),
+    // contracts of a fixed USD notional each, 1.0 for the USD amounts of deribit's inverse
+    // futures
+    UsdContracts(Decimal),
+}
+
+impl OrbitSizeUnit {
+    pub fn to_underlying(&self, amount: Amount, price: Price) -> Amount {
+        match self {
+            OrbitSizeUnit::Underlying => amount,
+            OrbitSizeUnit::Contracts(contract_size) => amount * contract_size,
+            OrbitSizeUnit::UsdContracts(_) if price <= Decimal::ZERO => Decimal::ZERO,
+            OrbitSizeUnit::UsdContracts(contract_size) => amount * contract_size / price,
+        }
+    }
+}
+
+// Trading specs of a contract as the venue lists them. Contract, tick and min sizes are
+// in the venue's own units, fees are fractions of the notional and negative for rebates.
+// Specs a listing doesn't carry are left out and sizes are then taken as they come.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OrbitContractSpecs {
+    pub size_unit: OrbitSizeUnit,
+    pub contract_size: Option<Decimal>,
+    pub tick_size: Option<Price>,
+    pub min_size: Option<Amount>,
+    pub maker_fee: Option<f64>,
+    pub taker_fee: Option<f64>,
+    // option fees are capped at this fraction of the premium, None when they aren't
+    pub premium_fee_cap: Option<f64>,
+}
+
+impl Default for OrbitContractSpecs {
+    fn default() -> Self {
+        Self {
+            size_unit: OrbitSizeUnit::Underlying,
+            contract_size: None,
+            tick_size: None,
+            min_size: None,
+            maker_fee: None,
+            taker_fee: None,
+            premium_fee_cap: None,
+        }
     }
 }
-// this was built do do the common instrument algo, may not use it leaving it here in case i come back
-// impl PartialEq for OrbitInstrument {
-//     fn eq(&self, other: &Self) -> bool {
-//         self.base == other.base
-//             && self.quote == other.quote
-//             && self.strike == other.strike
-//             && self.expiration == other.expiration
-//             && self.contract_type == other.contract_type
-//     }
-// }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+// index name -> currency for the distinct price indices of the instruments, what the
+// streams subscribe index channels with
+pub fn price_indices(instruments: &[OrbitInstrument]) -> BTreeMap<String, OrbitCurrency> {
+    instruments
+        .iter()
+        .filter_map(|x| Some((x.price_index.clone()?, x.base.clone())))
+        .collect()
+}
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum OrbitContractType {
     Spot,
     Future,
@@ -212,369 +577,684 @@ pub enum OrbitContractType {
     Unimplemented,
 }
 
-#[derive(Debug)]
+// prints kept per book unless OrbitOrderbookStorage::with_trade_tape_len says otherwise
+pub const DEFAULT_TRADE_TAPE_LEN: usize = 100;
+
+// index prices older than this are stale, see OrbitOrderbookStorage::with_reference_max_age
+pub const DEFAULT_REFERENCE_MAX_AGE_MS: i64 = 5_000;
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct OrbitOrderbookStorage {
     pub id: Uuid,
-    pub storage: BTreeMap<(OrbitExchange, OrbitCurrency), [Option<OrbitContractTypeOrderbook>; 3]>,
+    #[serde(with = "as_pairs")]
+    pub storage: OrbitStorage,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    trade_tape_len: usize,
+    #[serde(with = "as_pairs")]
+    reference_prices: HashMap<(OrbitExchange, OrbitCurrency), OrbitReferencePrice>,
+    #[serde(with = "as_millis")]
+    reference_max_age: Duration,
+    // every book the storage keeps, call and put of a strike apart
+    listed: HashSet<OrbitBookKey>,
 }
 
 impl OrbitOrderbookStorage {
     pub fn new(instruments: Vec<OrbitInstrument>) -> Self {
-        let mut storage: BTreeMap<
-            (OrbitExchange, OrbitCurrency),
-            [Option<OrbitContractTypeOrderbook>; 3],
-        > = BTreeMap::new();
-
-        for (i, instrument) in instruments.iter().enumerate() {
-            // if i > 20 {
-            //     break;
-            // }
-            // debug!("");
-            // debug!("---- instrument {:?}", instrument);
-            // if let Some(expiration) = instrument.expiration_date {
-            //     d.insert(expiration);
-            // }
-            storage
-                .entry((instrument.exchange.clone(), instrument.base.clone()))
-                .and_modify(|contract_types| match instrument.contract_type {
-                    OrbitContractType::Future => {
-                        if let Some(expiration) = instrument.expiration_date {
-                            if let Some(OrbitContractTypeOrderbook::Future(future_orderbook)) =
-                                &mut contract_types[0]
-                            {
-                                debug!("future orderbook exists, inserting expiration and empty OB");
-                                future_orderbook.insert(expiration, OrbitStorageOrderbook::default());
-                            } else {
-                                debug!("future doesnt exist, creating it and inserting expiration and empty OB");
-                                let mut future_orderbook: BTreeMap<DateTime<Utc>, OrbitStorageOrderbook> = BTreeMap::new();
-                                future_orderbook.insert(expiration, OrbitStorageOrderbook::default());
-                                contract_types[0] = Some(OrbitContractTypeOrderbook::Future(future_orderbook));
-                            }
-                        }
-                    }
-                    OrbitContractType::CallOption | OrbitContractType::PutOption => {
-                        if let Some(expiration) = instrument.expiration_date {
-                            if let Some(strike) = instrument.strike {
-                                if let Some(OrbitContractTypeOrderbook::Option(option_orderbook)) =
-                                    &mut contract_types[1]
-                                {
-                                    option_orderbook.get_mut(&expiration).map(|inner| {
-                                        inner
-                                            .insert(strike, OrbitStorageOptionOrderbook::default());
-                                    });
-                                    debug!("option exists, inserting new expiration: {}", expiration);
-                                    if let Some(inner) = option_orderbook.get_mut(&expiration) {
-                                        debug!("expiration exists");
-                                        let res = inner.insert(strike, OrbitStorageOptionOrderbook::default());
-                                        debug!("inserted : {:?}", res);
-                                    }else {
-                                        debug!("cant find this expiration");
-                                        let mut inner = BTreeMap::new();
-                                        inner.insert(strike, OrbitStorageOptionOrderbook::default());
-                                        option_orderbook.insert(expiration, inner);
-                                    };  
-
-                                } else {
-                                    let mut inner = BTreeMap::new();
-                                    inner.insert(strike, OrbitStorageOptionOrderbook::default());
-
-                                    let mut outter = BTreeMap::new();
-                                    outter.insert(expiration, inner);
-
-                                    contract_types[1] = Some(OrbitContractTypeOrderbook::Option(outter));
-                                }
-                            }
-                        }
-                    }
-                    OrbitContractType::PerpetualFuture => {
-                        contract_types[2] = Some(OrbitContractTypeOrderbook::Perpetual(
-                            OrbitStorageOrderbook::default(),
-                        ));
-                    }
-                    _ => {}
-                })
-                .or_insert({
-                    let mut contract_types = [None, None, None]; // Futures, Options, Perpetuals
-                    match instrument.contract_type {
-                        OrbitContractType::Future => {
-                            if let Some(expiration) = instrument.expiration_date {
-                                debug!("inserting futures orderbook");
-                                let mut future_orderbook: BTreeMap<DateTime<Utc>, OrbitStorageOrderbook> = BTreeMap::new();
-                                future_orderbook.insert(expiration, OrbitStorageOrderbook::default());
-                                contract_types[0] = Some(OrbitContractTypeOrderbook::Future(future_orderbook));
-                            }
-                        }
-                        OrbitContractType::CallOption | OrbitContractType::PutOption => {
-                            if let Some(expiration) = instrument.expiration_date {
-                                if let Some(strike) = instrument.strike {
-                                    debug!("inserting options orderbook");
-                                    let mut inner = BTreeMap::new();
-                                    inner.insert(strike, OrbitStorageOptionOrderbook::default());
-                                    
-                                    let mut outter = BTreeMap::new();
-                                    outter.insert(expiration, inner);
-                                    
-                                    contract_types[1] = Some(OrbitContractTypeOrderbook::Option(outter));
-                                }
-                            }
-                        }
-                        OrbitContractType::PerpetualFuture => {
-                            debug!("inserting perps orderbook");
-                            contract_types[2] = Some(OrbitContractTypeOrderbook::Perpetual(
-                                OrbitStorageOrderbook::default(),
-                            ));
-                        }
-                        _ => {
-                            debug!("found unimplemented contract type {:?}", instrument);
-                            continue;
-                        }
-                    }
-                    contract_types
-                });
-            }
-        Self {
+        let mut orbit_storage = Self {
             id: Uuid::new_v4(),
-            storage,
+            storage: BTreeMap::new(),
             created_at: chrono::offset::Utc::now(),
             updated_at: chrono::offset::Utc::now(),
+            listed: HashSet::new(),
+            trade_tape_len: DEFAULT_TRADE_TAPE_LEN,
+            reference_prices: HashMap::new(),
+            reference_max_age: Duration::milliseconds(DEFAULT_REFERENCE_MAX_AGE_MS),
+        };
+        for instrument in instruments.iter() {
+            orbit_storage.add_instrument(instrument);
         }
+        orbit_storage
+    }
+
+    pub fn with_trade_tape_len(mut self, trade_tape_len: usize) -> Self {
+        self.trade_tape_len = trade_tape_len;
+        self
+    }
+
+    pub fn with_reference_max_age(mut self, reference_max_age: Duration) -> Self {
+        self.reference_max_age = reference_max_age;
+        self
+    }
+
+    pub fn trade_tape_len(&self) -> usize {
+        self.trade_tape_len
+    }
+
+    pub fn reference_max_age(&self) -> Duration {
+        self.reference_max_age
+    }
+
+    // an empty book for a newly listed instrument, false when it's already there or of a
+    // contract type the storage doesn't keep
+    pub fn add_instrument(&mut self, instrument: &OrbitInstrument) -> bool {
+        self.add_book(OrbitBookKey::from_instrument(instrument))
+    }
+
+    // drops the book of a delisted or expired instrument, false when it wasn't listed
+    pub fn remove_instrument(&mut self, instrument: &OrbitInstrument) -> bool {
+        self.remove_book(&OrbitBookKey::from_instrument(instrument))
+    }
+
+    pub fn is_listed(&self, key: &OrbitBookKey) -> bool {
+        self.listed.contains(key)
     }
     
-    pub fn process(&mut self, event: OrbitEvent) -> Result<BTreeMap< (OrbitExchange, OrbitCurrency),  [Option<OrbitContractTypeOrderbook>; 3]>, Error> {
-        let k = (event.exchange, event.currency.unwrap());
-        let contract_type = self.storage.get_mut(&k).unwrap(); // static sized array, we know length and items beforehand
-        match event.contract_type.expect("This should unwrap fine") {
-            OrbitContractType::Future => {
-                if let Some(OrbitContractTypeOrderbook::Future(orderbook)) = &mut contract_type[0] {
-                    // match &mut contract_type[0] {
-                    // OrbitContractTypeOrderbook::Future(orderbook) => {
-                    let k = event
-                        .expiration
-                        .expect("futures should always have expiration");
-                    orderbook.get_mut(&k).map(|orbit_orderbook| {
-                        event.payload.map(|p| match p {
-                            OrbitEventPayload::OrderbookUpdate(event_orderbook) => {
-                                event_orderbook.asks.iter().for_each(|ask| {
-                                    match ask.0 {
-                                        OrderbookUpdateType::New => {
-                                            orbit_orderbook.asks.insert(OrderedFloat(ask.1), ask.2);
-                                        }
-                                        OrderbookUpdateType::Change => {
-                                            orbit_orderbook.asks.insert(OrderedFloat(ask.1), ask.2);
-                                            // todo, check entry.and modify....
-                                        }
-                                        OrderbookUpdateType::Delete => {
-                                            orbit_orderbook.asks.remove(&OrderedFloat(ask.1));
-                                        }
-                                    }
-                                });
-
-                                event_orderbook.bids.iter().for_each(|bid| {
-                                    match bid.0 {
-                                        OrderbookUpdateType::New => {
-                                            orbit_orderbook.bids.insert(OrderedFloat(bid.1), bid.2);
-                                        }
-                                        OrderbookUpdateType::Change => {
-                                            orbit_orderbook.bids.insert(OrderedFloat(bid.1), bid.2);
-                                            // todo, check entry.and modify....
-                                        }
-                                        OrderbookUpdateType::Delete => {
-                                            orbit_orderbook.bids.remove(&OrderedFloat(bid.1));
-                                        }
-                                    }
-                                });
-                            }
-                        })
-                    });
-                    // }
-                }
-                // _ => {}
-                // }
+    // applies the event to its book and describes what changed, the cost is bounded by
+    // the size of the event rather than the size of the storage
+    pub fn process(&mut self, event: OrbitEvent) -> Result<StorageUpdate, Error> {
+        let key = OrbitBookKey::from_event(&event).ok_or_else(|| {
+            anyhow!(
+                "event for unknown instrument {:?} {}",
+                event.exchange,
+                event.symbol
+            )
+        })?;
+        // index prices belong to the currency, not to a book
+        if let Some(OrbitEventPayload::IndexPrice(index)) = &event.payload {
+            self.reference_prices.insert(
+                (key.exchange.clone(), key.currency.clone()),
+                OrbitReferencePrice {
+                    index_name: event.symbol.clone(),
+                    price: index.price,
+                    timestamp: index.timestamp,
+                    updated_at: Utc::now(),
+                },
+            );
+            return Ok(StorageUpdate {
+                key,
+                is_snapshot: false,
+                changes: vec![],
+                top_of_book: OrbitTopOfBook::default(),
+            });
+        }
+        if let Some(OrbitEventPayload::Lifecycle(lifecycle, _)) = &event.payload {
+            match lifecycle {
+                OrbitLifecycle::Listed => self.add_book(key.clone()),
+                OrbitLifecycle::Delisted | OrbitLifecycle::Expired => self.remove_book(&key),
+            };
+            return Ok(StorageUpdate {
+                key,
+                is_snapshot: false,
+                changes: vec![],
+                top_of_book: OrbitTopOfBook::default(),
+            });
+        }
+        let trade_tape_len = self.trade_tape_len;
+        let orbit_orderbook = self.get_orderbook_mut(&event)?;
+        let (is_snapshot, changes) = match &event.payload {
+            Some(OrbitEventPayload::OrderbookUpdate(event_orderbook)) => (
+                event_orderbook.is_snapshot,
+                orbit_orderbook.apply(event_orderbook),
+            ),
+            Some(OrbitEventPayload::OrderbookResync) => (true, orbit_orderbook.clear()),
+            Some(OrbitEventPayload::Ticker(ticker)) => {
+                orbit_orderbook.ticker = Some(Box::new(ticker.clone()));
+                (false, vec![])
             }
-            OrbitContractType::CallOption => {
-                if let Some(OrbitContractTypeOrderbook::Option(orderbook)) = &mut contract_type[1] {
-                    let k = event
-                        .expiration
-                        .expect("options should always have expiration");
-                    orderbook.get_mut(&k).map(|expiration| {
-                        let k = event.strike.expect("options should always have strike");
-                        expiration.get_mut(&k).map(|orbit_option_orderbook| {
-                            event.payload.map(|p| match p {
-                                OrbitEventPayload::OrderbookUpdate(event_orderbook) => {
-                                    event_orderbook.asks.iter().for_each(|ask| {
-                                        match ask.0 {
-                                            OrderbookUpdateType::New => {
-                                                orbit_option_orderbook
-                                                    .calls
-                                                    .asks
-                                                    .insert(OrderedFloat(ask.1), ask.2);
-                                            }
-                                            OrderbookUpdateType::Change => {
-                                                orbit_option_orderbook
-                                                    .calls
-                                                    .asks
-                                                    .insert(OrderedFloat(ask.1), ask.2);
-                                                // todo, check entry.and modify....
-                                            }
-                                            OrderbookUpdateType::Delete => {
-                                                orbit_option_orderbook
-                                                    .calls
-                                                    .asks
-                                                    .remove(&OrderedFloat(ask.1));
-                                            }
-                                        }
-                                    });
-                                    event_orderbook.bids.iter().for_each(|bid| {
-                                        match bid.0 {
-                                            OrderbookUpdateType::New => {
-                                                orbit_option_orderbook
-                                                    .calls
-                                                    .bids
-                                                    .insert(OrderedFloat(bid.1), bid.2);
-                                            }
-                                            OrderbookUpdateType::Change => {
-                                                orbit_option_orderbook
-                                                    .calls
-                                                    .bids
-                                                    .insert(OrderedFloat(bid.1), bid.2);
-                                                // todo, check entry.and modify....
-                                            }
-                                            OrderbookUpdateType::Delete => {
-                                                orbit_option_orderbook
-                                                    .calls
-                                                    .bids
-                                                    .remove(&OrderedFloat(bid.1));
-                                            }
-                                        }
-                                    });
-                                }
-                            });
-                        });
-                    });
+            Some(OrbitEventPayload::Trade(trade)) => {
+                orbit_orderbook.record_trade(trade, trade_tape_len);
+                (false, vec![])
+            }
+            Some(OrbitEventPayload::Funding(funding)) => {
+                orbit_orderbook.funding = Some(funding.clone());
+                (false, vec![])
+            }
+            // handled above
+            Some(OrbitEventPayload::IndexPrice(_))
+            | Some(OrbitEventPayload::Lifecycle(..))
+            | None => (false, vec![]),
+        };
+        Ok(StorageUpdate {
+            key,
+            is_snapshot,
+            changes,
+            top_of_book: orbit_orderbook.top_of_book(),
+        })
+    }
+
+    pub fn option_chain(
+        &self,
+        exchange: &OrbitExchange,
+        currency: &OrbitCurrency,
+        settlement: OrbitSettlement,
+    ) -> Option<&OrbitOptionOrderbook> {
+        match &self
+            .storage
+            .get(&(exchange.clone(), currency.clone(), settlement))?[1]
+        {
+            Some(OrbitContractTypeOrderbook::Option(chain)) => Some(chain),
+            _ => None,
+        }
+    }
+
+    // the index price while it's fresh, the perp mid otherwise, both perps are priced in
+    // USD so either settlement does
+    pub fn reference_price(
+        &self,
+        exchange: &OrbitExchange,
+        currency: &OrbitCurrency,
+    ) -> Option<Price> {
+        self.fresh_index_price(exchange, currency, Utc::now())
+            .or_else(|| {
+                OrbitSettlement::ALL.into_iter().find_map(|settlement| {
+                    self.mid(&OrbitBookKey::perpetual(
+                        exchange.clone(),
+                        currency.clone(),
+                        settlement,
+                    ))
+                })
+            })
+    }
+
+    // latest index price, stale or not
+    pub fn index_price(
+        &self,
+        exchange: &OrbitExchange,
+        currency: &OrbitCurrency,
+    ) -> Option<&OrbitReferencePrice> {
+        self.reference_prices
+            .get(&(exchange.clone(), currency.clone()))
+    }
+
+    pub fn fresh_index_price(
+        &self,
+        exchange: &OrbitExchange,
+        currency: &OrbitCurrency,
+        now: DateTime<Utc>,
+    ) -> Option<Price> {
+        self.index_price(exchange, currency)
+            .filter(|reference| !reference.is_stale(now, self.reference_max_age))
+            .map(|reference| reference.price)
+    }
+
+    // every index price with its staleness flag as of now
+    pub fn index_prices(
+        &self,
+        now: DateTime<Utc>,
+    ) -> impl Iterator<Item = (&(OrbitExchange, OrbitCurrency), &OrbitReferencePrice, bool)> {
+        self.reference_prices.iter().map(move |(key, reference)| {
+            (
+                key,
+                reference,
+                reference.is_stale(now, self.reference_max_age),
+            )
+        })
+    }
+
+    // first listed expiry on or after the day of now, an expiry later today still counts
+    pub fn nearest_expiration(
+        &self,
+        exchange: &OrbitExchange,
+        currency: &OrbitCurrency,
+        settlement: OrbitSettlement,
+        now: DateTime<Utc>,
+    ) -> Option<Expiration> {
+        self.option_chain(exchange, currency, settlement)?
+            .range(expiration_key(now)..)
+            .next()
+            .map(|(expiration, _)| *expiration)
+    }
+
+    pub fn expiration_by_tenor(
+        &self,
+        exchange: &OrbitExchange,
+        currency: &OrbitCurrency,
+        settlement: OrbitSettlement,
+        tenor: OrbitTenor,
+        now: DateTime<Utc>,
+    ) -> Option<Expiration> {
+        let chain = self.option_chain(exchange, currency, settlement)?;
+        match tenor {
+            // the monthly is the last listed expiry of the month
+            OrbitTenor::Month { year, month } => chain
+                .keys()
+                .filter(|expiration| expiration.year() == year && expiration.month() == month)
+                .max()
+                .copied(),
+            OrbitTenor::Days(days) => {
+                let target = now + Duration::days(days);
+                chain
+                    .keys()
+                    .filter(|expiration| **expiration >= expiration_key(now))
+                    .min_by_key(|expiration| (**expiration - target).num_seconds().abs())
+                    .copied()
+            }
+        }
+    }
+
+    pub fn atm_strike(
+        &self,
+        exchange: &OrbitExchange,
+        currency: &OrbitCurrency,
+        settlement: OrbitSettlement,
+        expiration: Expiration,
+        underlying: Price,
+    ) -> Option<Strike> {
+        self.strikes_around_atm(exchange, currency, settlement, expiration, underlying, 1)
+            .first()
+            .copied()
+    }
+
+    // the count listed strikes closest to the underlying, returned in ascending order
+    pub fn strikes_around_atm(
+        &self,
+        exchange: &OrbitExchange,
+        currency: &OrbitCurrency,
+        settlement: OrbitSettlement,
+        expiration: Expiration,
+        underlying: Price,
+        count: usize,
+    ) -> Vec<Strike> {
+        let Some(strikes) = self
+            .option_chain(exchange, currency, settlement)
+            .and_then(|chain| chain.get(&expiration_key(expiration)))
+        else {
+            return vec![];
+        };
+        let mut closest: Vec<Strike> = strikes.keys().copied().collect();
+        closest.sort_by_key(|strike| (*strike - underlying).abs());
+        closest.truncate(count);
+        closest.sort_unstable();
+        closest
+    }
+
+    pub fn options_around_atm(
+        &self,
+        exchange: &OrbitExchange,
+        currency: &OrbitCurrency,
+        settlement: OrbitSettlement,
+        expiration: Expiration,
+        underlying: Price,
+        count: usize,
+    ) -> Vec<(Strike, &OrbitStorageOptionOrderbook)> {
+        let Some(strikes) = self
+            .option_chain(exchange, currency, settlement)
+            .and_then(|chain| chain.get(&expiration_key(expiration)))
+        else {
+            return vec![];
+        };
+        self.strikes_around_atm(
+            exchange, currency, settlement, expiration, underlying, count,
+        )
+        .into_iter()
+        .filter_map(|strike| strikes.get(&strike).map(|option| (strike, option)))
+        .collect()
+    }
+
+    pub fn book(&self, key: &OrbitBookKey) -> Option<&OrbitStorageOrderbook> {
+        self.get_orderbook(
+            &key.exchange,
+            &key.currency,
+            key.settlement,
+            &key.contract_type,
+            key.expiration,
+            key.strike,
+        )
+    }
+
+    pub fn ticker(&self, key: &OrbitBookKey) -> Option<&OrbitTicker> {
+        self.book(key).and_then(|book| book.ticker())
+    }
+
+    pub fn trades(&self, key: &OrbitBookKey) -> Option<&VecDeque<OrbitTrade>> {
+        self.book(key).map(|book| book.trades())
+    }
+
+    // latest funding of the currency's perpetual
+    pub fn funding(
+        &self,
+        exchange: &OrbitExchange,
+        currency: &OrbitCurrency,
+        settlement: OrbitSettlement,
+    ) -> Option<&OrbitFunding> {
+        self.book(&OrbitBookKey::perpetual(
+            exchange.clone(),
+            currency.clone(),
+            settlement,
+        ))
+        .and_then(|book| book.funding())
+    }
+
+    pub fn last_trade(&self, key: &OrbitBookKey) -> Option<&OrbitTrade> {
+        self.book(key).and_then(|book| book.trades().back())
+    }
+
+    pub fn top_of_book(&self, key: &OrbitBookKey) -> Option<OrbitTopOfBook> {
+        self.book(key).map(|book| book.top_of_book())
+    }
+
+    pub fn best(&self, key: &OrbitBookKey, side: OrbitBookSide) -> Option<(Price, Amount)> {
+        self.book(key).and_then(|book| book.levels(side).next())
+    }
+
+    pub fn mid(&self, key: &OrbitBookKey) -> Option<Price> {
+        self.book(key).and_then(|book| book.mid())
+    }
+
+    pub fn spread(&self, key: &OrbitBookKey) -> Option<Price> {
+        self.book(key).and_then(|book| book.spread())
+    }
+
+    pub fn depth(
+        &self,
+        key: &OrbitBookKey,
+        side: OrbitBookSide,
+        levels: usize,
+    ) -> Option<Vec<(Price, Amount)>> {
+        self.book(key).map(|book| book.depth(side, levels))
+    }
+
+    pub fn cumulative_size(
+        &self,
+        key: &OrbitBookKey,
+        side: OrbitBookSide,
+        price: Price,
+    ) -> Option<Amount> {
+        self.book(key).map(|book| book.cumulative_size(side, price))
+    }
+
+    pub fn vwap(&self, key: &OrbitBookKey, side: OrbitBookSide, size: Amount) -> Option<OrbitVwap> {
+        self.book(key).and_then(|book| book.vwap(side, size))
+    }
+
+    pub fn get_orderbook(
+        &self,
+        exchange: &OrbitExchange,
+        currency: &OrbitCurrency,
+        settlement: OrbitSettlement,
+        contract_type: &OrbitContractType,
+        expiration: Option<Expiration>,
+        strike: Option<Strike>,
+    ) -> Option<&OrbitStorageOrderbook> {
+        let contract_types = self
+            .storage
+            .get(&(exchange.clone(), currency.clone(), settlement))?;
+        match contract_type {
+            OrbitContractType::Future => match &contract_types[0] {
+                Some(OrbitContractTypeOrderbook::Future(orderbook)) => orderbook.get(&expiration?),
+                _ => None,
+            },
+            OrbitContractType::CallOption | OrbitContractType::PutOption => {
+                match &contract_types[1] {
+                    Some(OrbitContractTypeOrderbook::Option(orderbook)) => {
+                        let option = orderbook.get(&expiration?)?.get(&strike?)?;
+                        match contract_type {
+                            OrbitContractType::CallOption => Some(option.calls()),
+                            _ => Some(option.puts()),
+                        }
+                    }
+                    _ => None,
                 }
             }
-            OrbitContractType::PutOption => {
-                if let Some(OrbitContractTypeOrderbook::Option(orderbook)) = &mut contract_type[1] {
-                    let k = event
-                        .expiration
-                        .expect("options should always have expiration");
-                    orderbook.get_mut(&k).map(|expiration| {
-                        let k: u64 = event.strike.expect("options should always have strike");
-                        expiration.get_mut(&k).map(|orbit_option_orderbook| {
-                            event.payload.map(|p| match p {
-                                OrbitEventPayload::OrderbookUpdate(event_orderbook) => {
-                                    event_orderbook.asks.iter().for_each(|ask| {
-                                        match ask.0 {
-                                            OrderbookUpdateType::New => {
-                                                orbit_option_orderbook
-                                                    .puts
-                                                    .asks
-                                                    .insert(OrderedFloat(ask.1), ask.2);
-                                            }
-                                            OrderbookUpdateType::Change => {
-                                                orbit_option_orderbook
-                                                    .puts
-                                                    .asks
-                                                    .insert(OrderedFloat(ask.1), ask.2);
-                                                // todo, check entry.and modify....
-                                            }
-                                            OrderbookUpdateType::Delete => {
-                                                orbit_option_orderbook
-                                                    .puts
-                                                    .asks
-                                                    .remove(&OrderedFloat(ask.1));
-                                            }
-                                        }
-                                    });
-                                    event_orderbook.bids.iter().for_each(|bid| {
-                                        match bid.0 {
-                                            OrderbookUpdateType::New => {
-                                                orbit_option_orderbook
-                                                    .puts
-                                                    .bids
-                                                    .insert(OrderedFloat(bid.1), bid.2);
-                                            }
-                                            OrderbookUpdateType::Change => {
-                                                orbit_option_orderbook
-                                                    .puts
-                                                    .bids
-                                                    .insert(OrderedFloat(bid.1), bid.2);
-                                                // todo, check entry.and modify....
-                                            }
-                                            OrderbookUpdateType::Delete => {
-                                                orbit_option_orderbook
-                                                    .puts
-                                                    .bids
-                                                    .remove(&OrderedFloat(bid.1));
-                                            }
-                                        }
-                                    });
-                                }
-                            });
-                        });
-                    });
+            OrbitContractType::PerpetualFuture => match &contract_types[2] {
+                Some(OrbitContractTypeOrderbook::Perpetual(orderbook)) => Some(orderbook),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // creates an empty book for the key, false when it's listed already or not supported
+    fn add_book(&mut self, key: OrbitBookKey) -> bool {
+        if self.listed.contains(&key) {
+            return false;
+        }
+        let slot = match (&key.contract_type, key.expiration, key.strike) {
+            (OrbitContractType::Future, Some(_), _) => 0,
+            (OrbitContractType::CallOption | OrbitContractType::PutOption, Some(_), Some(_)) => 1,
+            (OrbitContractType::PerpetualFuture, _, _) => 2,
+            _ => {
+                debug!("found unimplemented contract type {:?}", key);
+                return false;
+            }
+        };
+        // Futures, Options, Perpetuals
+        let contract_types = self
+            .storage
+            .entry((key.exchange.clone(), key.currency.clone(), key.settlement))
+            .or_insert([None, None, None]);
+        let contract_type_orderbook = contract_types[slot].get_or_insert_with(|| match slot {
+            0 => OrbitContractTypeOrderbook::Future(BTreeMap::new()),
+            1 => OrbitContractTypeOrderbook::Option(BTreeMap::new()),
+            _ => OrbitContractTypeOrderbook::Perpetual(OrbitStorageOrderbook::default()),
+        });
+        match contract_type_orderbook {
+            OrbitContractTypeOrderbook::Future(futures) => {
+                futures.entry(key.expiration.unwrap()).or_default();
+            }
+            // the call and the put of a strike share an entry, listing one keeps the other
+            OrbitContractTypeOrderbook::Option(options) => {
+                options
+                    .entry(key.expiration.unwrap())
+                    .or_default()
+                    .entry(key.strike.unwrap())
+                    .or_default();
+            }
+            OrbitContractTypeOrderbook::Perpetual(_) => {}
+        }
+        self.listed.insert(key);
+        true
+    }
+
+    // emptied expiries and currencies go with their last book
+    fn remove_book(&mut self, key: &OrbitBookKey) -> bool {
+        if !self.listed.remove(key) {
+            return false;
+        }
+        let sibling_listed = match key.contract_type {
+            OrbitContractType::CallOption => OrbitContractType::PutOption,
+            OrbitContractType::PutOption => OrbitContractType::CallOption,
+            _ => key.contract_type.clone(),
+        };
+        let sibling_listed = self.listed.contains(&OrbitBookKey {
+            contract_type: sibling_listed,
+            ..key.clone()
+        });
+        let storage_key = (key.exchange.clone(), key.currency.clone(), key.settlement);
+        let Some(contract_types) = self.storage.get_mut(&storage_key) else {
+            return true;
+        };
+        match key.contract_type {
+            OrbitContractType::Future => {
+                if let Some(OrbitContractTypeOrderbook::Future(futures)) = &mut contract_types[0] {
+                    if let Some(expiration) = key.expiration {
+                        futures.remove(&expiration);
+                    }
+                    if futures.is_empty() {
+                        contract_types[0] = None;
+                    }
                 }
             }
-            OrbitContractType::PerpetualFuture => {
-                if let Some(OrbitContractTypeOrderbook::Perpetual(orbit_orderbook)) =
-                    &mut contract_type[2]
-                {
-                    if let Some(OrbitEventPayload::OrderbookUpdate(event_orderbook)) = event.payload
-                    {
-                        event_orderbook.asks.iter().for_each(|ask| {
-                            match ask.0 {
-                                OrderbookUpdateType::New => {
-                                    orbit_orderbook.asks.insert(OrderedFloat(ask.1), ask.2);
-                                }
-                                OrderbookUpdateType::Change => {
-                                    orbit_orderbook.asks.insert(OrderedFloat(ask.1), ask.2);
-                                    // todo, check entry.and modify....
-                                }
-                                OrderbookUpdateType::Delete => {
-                                    orbit_orderbook.asks.remove(&OrderedFloat(ask.1));
-                                }
-                            }
-                        });
-
-                        event_orderbook.bids.iter().for_each(|bid| {
-                            match bid.0 {
-                                OrderbookUpdateType::New => {
-                                    orbit_orderbook.bids.insert(OrderedFloat(bid.1), bid.2);
-                                }
-                                OrderbookUpdateType::Change => {
-                                    orbit_orderbook.bids.insert(OrderedFloat(bid.1), bid.2);
-                                    // todo, check entry.and modify....
-                                }
-                                OrderbookUpdateType::Delete => {
-                                    orbit_orderbook.bids.remove(&OrderedFloat(bid.1));
-                                }
+            OrbitContractType::CallOption | OrbitContractType::PutOption => {
+                if let Some(OrbitContractTypeOrderbook::Option(options)) = &mut contract_types[1] {
+                    let strikes = key
+                        .expiration
+                        .and_then(|expiration| options.get_mut(&expiration));
+                    if let (Some(strikes), Some(strike)) = (strikes, key.strike) {
+                        match strikes.get_mut(&strike) {
+                            // the other side stays, only this one is emptied
+                            Some(option) if sibling_listed => match key.contract_type {
+                                OrbitContractType::CallOption => option.calls = Default::default(),
+                                _ => option.puts = Default::default(),
+                            },
+                            _ => {
+                                strikes.remove(&strike);
                             }
-                        });
-                    };
+                        }
+                    }
+                    options.retain(|_, strikes| !strikes.is_empty());
+                    if options.is_empty() {
+                        contract_types[1] = None;
+                    }
                 }
             }
+            OrbitContractType::PerpetualFuture => contract_types[2] = None,
             _ => {}
         }
-        Ok(self.storage.clone())
+        if contract_types.iter().all(Option::is_none) {
+            self.storage.remove(&storage_key);
+        }
+        true
+    }
+
+    // resolves the single book an event belongs to, an event that matches no book
+    // is an error so a keying mismatch between instruments and events can't go unnoticed
+    fn get_orderbook_mut(
+        &mut self,
+        event: &OrbitEvent,
+    ) -> Result<&mut OrbitStorageOrderbook, Error> {
+        let (Some(currency), Some(settlement), Some(contract_type)) =
+            (&event.currency, event.settlement, &event.contract_type)
+        else {
+            return Err(anyhow!(
+                "event for unknown instrument {:?} {}",
+                event.exchange,
+                event.symbol
+            ));
+        };
+        let contract_types = self
+            .storage
+            .get_mut(&(event.exchange.clone(), currency.clone(), settlement))
+            .ok_or_else(|| {
+                anyhow!(
+                    "no orderbooks for {:?} {:?} {:?}",
+                    event.exchange,
+                    currency,
+                    settlement
+                )
+            })?;
+
+        let orderbook = match contract_type {
+            OrbitContractType::Future => match &mut contract_types[0] {
+                Some(OrbitContractTypeOrderbook::Future(orderbook)) => event
+                    .expiration
+                    .and_then(|expiration| orderbook.get_mut(&expiration)),
+                _ => None,
+            },
+            OrbitContractType::CallOption | OrbitContractType::PutOption => {
+                match &mut contract_types[1] {
+                    Some(OrbitContractTypeOrderbook::Option(orderbook)) => event
+                        .expiration
+                        .and_then(|expiration| orderbook.get_mut(&expiration))
+                        .zip(event.strike)
+                        .and_then(|(strikes, strike)| strikes.get_mut(&strike))
+                        .map(|orbit_option_orderbook| match contract_type {
+                            OrbitContractType::CallOption => &mut orbit_option_orderbook.calls,
+                            _ => &mut orbit_option_orderbook.puts,
+                        }),
+                    _ => None,
+                }
+            }
+            OrbitContractType::PerpetualFuture => match &mut contract_types[2] {
+                Some(OrbitContractTypeOrderbook::Perpetual(orderbook)) => Some(orderbook),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        orderbook.ok_or_else(|| {
+            anyhow!(
+                "no orderbook for {:?} {} ({:?}, expiration {:?}, strike {:?})",
+                event.exchange,
+                event.symbol,
+                contract_type,
+                event.expiration,
+                event.strike
+            )
+        })
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum OrbitContractTypeOrderbook {
     Future(OrbitFutureOrderbook),
     Option(OrbitOptionOrderbook),
     Perpetual(OrbitPerpetualOrderbook),
 }
 
+// Books are keyed by the UTC calendar day of expiry. Exchanges settle at different
+// hours (Deribit 08:00, Delta 12:00) so the raw expiration datetime can't be the key,
+// instruments and events must both go through this.
+pub fn expiration_key(expiration_datetime: DateTime<Utc>) -> Expiration {
+    let midnight = expiration_datetime
+        .date_naive()
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight is always a valid time");
+    DateTime::from_utc(midnight, Utc)
+}
+
+// Futures, Options, Perpetuals slots per (exchange, currency, settlement), a venue listing
+// a currency both inverse and linear (Deribit's BTC-PERPETUAL and BTC_USDC-PERPETUAL) has
+// a set of books for each
+pub type OrbitStorage = BTreeMap<
+    (OrbitExchange, OrbitCurrency, OrbitSettlement),
+    [Option<OrbitContractTypeOrderbook>; 3],
+>;
 pub type Expiration = DateTime<Utc>;
-pub type Strike = u64;
+pub type Strike = Decimal;
 pub type OrbitPerpetualOrderbook = OrbitStorageOrderbook;
 pub type OrbitFutureOrderbook = BTreeMap<Expiration, OrbitStorageOrderbook>;
 pub type OrbitOptionOrderbook = BTreeMap<Expiration, BTreeMap<Strike, OrbitStorageOptionOrderbook>>;
 
-#[derive(Clone, Debug)]
+// maps keyed by tuples, which JSON can't have as keys, are kept as lists of pairs
+mod as_pairs {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<'a, M, K, V, S>(map: &'a M, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        &'a M: IntoIterator<Item = (&'a K, &'a V)>,
+        K: Serialize + 'a,
+        V: Serialize + 'a,
+        S: Serializer,
+    {
+        serializer.collect_seq(map)
+    }
+
+    pub fn deserialize<'de, M, K, V, D>(deserializer: D) -> Result<M, D::Error>
+    where
+        M: FromIterator<(K, V)>,
+        K: Deserialize<'de>,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Ok(Vec::<(K, V)>::deserialize(deserializer)?
+            .into_iter()
+            .collect())
+    }
+}
+
+mod as_millis {
+    use chrono::Duration;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_i64(duration.num_milliseconds())
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Duration::milliseconds(i64::deserialize(deserializer)?))
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct OrbitOrderbook {
     id: Uuid,
     timestamp: i64,
@@ -582,39 +1262,475 @@ pub struct OrbitOrderbook {
     asks: Vec<OrderbookUpdateLevel>,
 }
 
-pub type OrbitOrderbookPrice = OrderedFloat<f64>;
-pub type OrbitOrderbookAmount = f64;
-#[derive(Clone, Debug, Default)]
+pub type OrbitOrderbookPrice = Price;
+pub type OrbitOrderbookAmount = Amount;
+#[allow(dead_code)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct OrbitStorageOrderbook {
     id: Uuid,
     timestamp: i64,
     bids: BTreeMap<OrbitOrderbookPrice, OrbitOrderbookAmount>,
     asks: BTreeMap<OrbitOrderbookPrice, OrbitOrderbookAmount>,
+    // latest venue ticker, kept apart from the levels and untouched by snapshots and resyncs,
+    // boxed as most books never get one
+    ticker: Option<Box<OrbitTicker>>,
+    // most recent prints, oldest first
+    trades: VecDeque<OrbitTrade>,
+    // perpetuals only
+    funding: Option<OrbitFunding>,
 }
 
-#[derive(Clone, Debug, Default)]
+impl OrbitStorageOrderbook {
+    pub fn best_bid(&self) -> Option<(Price, Amount)> {
+        self.bids
+            .iter()
+            .next_back()
+            .map(|(price, amount)| (*price, *amount))
+    }
+
+    pub fn best_ask(&self) -> Option<(Price, Amount)> {
+        self.asks
+            .iter()
+            .next()
+            .map(|(price, amount)| (*price, *amount))
+    }
+
+    pub fn mid(&self) -> Option<Price> {
+        self.best_bid()
+            .zip(self.best_ask())
+            .map(|((bid, _), (ask, _))| (bid + ask) / Decimal::TWO)
+    }
+
+    pub fn spread(&self) -> Option<Price> {
+        self.best_bid()
+            .zip(self.best_ask())
+            .map(|((bid, _), (ask, _))| ask - bid)
+    }
+
+    pub fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
+    pub fn ticker(&self) -> Option<&OrbitTicker> {
+        self.ticker.as_deref()
+    }
+
+    pub fn trades(&self) -> &VecDeque<OrbitTrade> {
+        &self.trades
+    }
+
+    pub fn funding(&self) -> Option<&OrbitFunding> {
+        self.funding.as_ref()
+    }
+
+    pub fn top_of_book(&self) -> OrbitTopOfBook {
+        OrbitTopOfBook {
+            bid: self.best_bid(),
+            ask: self.best_ask(),
+            timestamp: self.timestamp,
+        }
+    }
+
+    // levels of one side, best price first
+    pub fn levels(&self, side: OrbitBookSide) -> Box<dyn Iterator<Item = (Price, Amount)> + '_> {
+        match side {
+            OrbitBookSide::Bid => Box::new(
+                self.bids
+                    .iter()
+                    .rev()
+                    .map(|(price, amount)| (*price, *amount)),
+            ),
+            OrbitBookSide::Ask => {
+                Box::new(self.asks.iter().map(|(price, amount)| (*price, *amount)))
+            }
+        }
+    }
+
+    pub fn depth(&self, side: OrbitBookSide, levels: usize) -> Vec<(Price, Amount)> {
+        self.levels(side).take(levels).collect()
+    }
+
+    // size available at prices at least as good as price, bids at or above and asks at or below
+    pub fn cumulative_size(&self, side: OrbitBookSide, price: Price) -> Amount {
+        self.levels(side)
+            .take_while(|(level_price, _)| match side {
+                OrbitBookSide::Bid => *level_price >= price,
+                OrbitBookSide::Ask => *level_price <= price,
+            })
+            .map(|(_, amount)| amount)
+            .sum()
+    }
+
+    // average price of sweeping the side for size, filled is short of size on a thin book
+    pub fn vwap(&self, side: OrbitBookSide, size: Amount) -> Option<OrbitVwap> {
+        let mut filled = Decimal::ZERO;
+        let mut notional = Decimal::ZERO;
+        for (price, amount) in self.levels(side) {
+            if filled >= size {
+                break;
+            }
+            let take = amount.min(size - filled);
+            filled += take;
+            notional += take * price;
+        }
+        (filled > Decimal::ZERO).then(|| OrbitVwap {
+            price: notional / filled,
+            filled,
+        })
+    }
+
+    fn apply(&mut self, update: &OrderbookUpdate) -> Vec<OrbitLevelChange> {
+        let mut changes = Vec::with_capacity(update.bids.len() + update.asks.len());
+        if update.is_snapshot {
+            // build both sides first and swap them in together, levels pulled since the
+            // previous snapshot must not survive and a half replaced book is never visible
+            let mut bids = BTreeMap::new();
+            let mut asks = BTreeMap::new();
+            Self::apply_levels(&mut bids, OrbitBookSide::Bid, &update.bids, &mut changes);
+            Self::apply_levels(&mut asks, OrbitBookSide::Ask, &update.asks, &mut changes);
+            Self::removed_levels(&self.bids, &bids, OrbitBookSide::Bid, &mut changes);
+            Self::removed_levels(&self.asks, &asks, OrbitBookSide::Ask, &mut changes);
+            self.bids = bids;
+            self.asks = asks;
+        } else {
+            Self::apply_levels(
+                &mut self.bids,
+                OrbitBookSide::Bid,
+                &update.bids,
+                &mut changes,
+            );
+            Self::apply_levels(
+                &mut self.asks,
+                OrbitBookSide::Ask,
+                &update.asks,
+                &mut changes,
+            );
+        }
+        self.timestamp = update.timestamp as i64;
+        changes
+    }
+
+    // trade snapshots sent on (re)subscribing repeat prints already on the tape, anything
+    // older than the newest print or equal to one at the same time is dropped
+    fn record_trade(&mut self, trade: &OrbitTrade, tape_len: usize) {
+        let seen = match self.trades.back() {
+            Some(last) if trade.timestamp < last.timestamp => true,
+            _ => self
+                .trades
+                .iter()
+                .rev()
+                .take_while(|seen| seen.timestamp == trade.timestamp)
+                .any(|seen| seen == trade),
+        };
+        if seen {
+            return;
+        }
+        self.trades.push_back(trade.clone());
+        while self.trades.len() > tape_len {
+            self.trades.pop_front();
+        }
+    }
+
+    fn clear(&mut self) -> Vec<OrbitLevelChange> {
+        let mut changes = Vec::with_capacity(self.bids.len() + self.asks.len());
+        let empty = BTreeMap::new();
+        Self::removed_levels(&self.bids, &empty, OrbitBookSide::Bid, &mut changes);
+        Self::removed_levels(&self.asks, &empty, OrbitBookSide::Ask, &mut changes);
+        self.bids.clear();
+        self.asks.clear();
+        changes
+    }
+
+    // levels of the old side the new one no longer has, reported at size 0
+    fn removed_levels(
+        old: &BTreeMap<OrbitOrderbookPrice, OrbitOrderbookAmount>,
+        new: &BTreeMap<OrbitOrderbookPrice, OrbitOrderbookAmount>,
+        side: OrbitBookSide,
+        changes: &mut Vec<OrbitLevelChange>,
+    ) {
+        changes.extend(
+            old.keys()
+                .filter(|price| !new.contains_key(*price))
+                .map(|price| OrbitLevelChange {
+                    side,
+                    price: *price,
+                    amount: Decimal::ZERO,
+                }),
+        );
+    }
+
+    fn apply_levels(
+        book: &mut BTreeMap<OrbitOrderbookPrice, OrbitOrderbookAmount>,
+        side: OrbitBookSide,
+        levels: &[OrderbookUpdateLevel],
+        changes: &mut Vec<OrbitLevelChange>,
+    ) {
+        levels.iter().for_each(|level| {
+            let amount = match level.0 {
+                OrderbookUpdateType::New | OrderbookUpdateType::Change => {
+                    book.insert(level.1, level.2);
+                    level.2
+                }
+                OrderbookUpdateType::Delete => {
+                    book.remove(&level.1);
+                    Decimal::ZERO
+                }
+            };
+            changes.push(OrbitLevelChange {
+                side,
+                price: level.1,
+                amount,
+            });
+        });
+    }
+}
+
+// what a single OrbitOrderbookStorage::process call did. A snapshot (or a resync, which
+// empties the book) replaced the whole book, then changes lists every level of the new book
+// and, at size 0, every level the old one had that the new one doesn't.
+// Tickers and trades leave the levels alone and come back with no changes.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StorageUpdate {
+    pub key: OrbitBookKey,
+    pub is_snapshot: bool,
+    pub changes: Vec<OrbitLevelChange>,
+    pub top_of_book: OrbitTopOfBook,
+}
+
+// amount is the new size at price, 0 when the level was removed
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OrbitLevelChange {
+    pub side: OrbitBookSide,
+    pub price: Price,
+    pub amount: Amount,
+}
+
+// how an expiry is asked for, Month { year: 2024, month: 1 } is "January next year"
+// in 2023, Days(30) the listed expiry closest to 30 days out
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrbitTenor {
+    Month { year: i32, month: u32 },
+    Days(i64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum OrbitBookSide {
+    Bid,
+    Ask,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct OrbitTopOfBook {
+    pub bid: Option<(Price, Amount)>,
+    pub ask: Option<(Price, Amount)>,
+    pub timestamp: i64,
+}
+
+impl OrbitTopOfBook {
+    pub fn mid(&self) -> Option<Price> {
+        self.bid
+            .zip(self.ask)
+            .map(|((bid, _), (ask, _))| (bid + ask) / Decimal::TWO)
+    }
+
+    pub fn spread(&self) -> Option<Price> {
+        self.bid.zip(self.ask).map(|((bid, _), (ask, _))| ask - bid)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OrbitReferencePrice {
+    pub index_name: String,
+    pub price: Price,
+    // the venue's timestamp, when it sends one
+    pub timestamp: Option<u64>,
+    // local time the price was stored, staleness is judged on this
+    pub updated_at: DateTime<Utc>,
+}
+
+impl OrbitReferencePrice {
+    pub fn age(&self, now: DateTime<Utc>) -> Duration {
+        now - self.updated_at
+    }
+
+    pub fn is_stale(&self, now: DateTime<Utc>, max_age: Duration) -> bool {
+        self.age(now) > max_age
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OrbitVwap {
+    pub price: Price,
+    pub filled: Amount,
+}
+
+// identifies a single book in OrbitOrderbookStorage, expiration is the expiration_key
+// day and only set for futures and options, strike only for options. Index prices come
+// keyed as the linear Spot contract of their currency.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct OrbitBookKey {
+    pub exchange: OrbitExchange,
+    pub currency: OrbitCurrency,
+    pub settlement: OrbitSettlement,
+    pub contract_type: OrbitContractType,
+    pub expiration: Option<Expiration>,
+    pub strike: Option<Strike>,
+}
+
+impl OrbitBookKey {
+    pub fn new(
+        exchange: OrbitExchange,
+        currency: OrbitCurrency,
+        settlement: OrbitSettlement,
+        contract_type: OrbitContractType,
+        expiration: Option<Expiration>,
+        strike: Option<Strike>,
+    ) -> Self {
+        Self {
+            exchange,
+            currency,
+            settlement,
+            contract_type,
+            expiration: expiration.map(expiration_key),
+            strike,
+        }
+    }
+
+    // perps are keyed without expiration (deribit lists them as expiring in year 3000)
+    // and futures without strike, whatever the event or instrument carries
+    fn normalized(
+        exchange: OrbitExchange,
+        currency: OrbitCurrency,
+        settlement: OrbitSettlement,
+        contract_type: OrbitContractType,
+        expiration: Option<Expiration>,
+        strike: Option<Strike>,
+    ) -> Self {
+        let (expiration, strike) = match contract_type {
+            OrbitContractType::Future => (expiration, None),
+            OrbitContractType::CallOption | OrbitContractType::PutOption => (expiration, strike),
+            _ => (None, None),
+        };
+        Self::new(
+            exchange,
+            currency,
+            settlement,
+            contract_type,
+            expiration,
+            strike,
+        )
+    }
+
+    pub fn from_event(event: &OrbitEvent) -> Option<Self> {
+        Some(Self::normalized(
+            event.exchange.clone(),
+            event.currency.clone()?,
+            event.settlement?,
+            event.contract_type.clone()?,
+            event.expiration,
+            event.strike,
+        ))
+    }
+
+    pub fn from_instrument(instrument: &OrbitInstrument) -> Self {
+        Self::normalized(
+            instrument.exchange.clone(),
+            instrument.base.clone(),
+            instrument.settlement,
+            instrument.contract_type.clone(),
+            instrument.expiration_date,
+            instrument.strike,
+        )
+    }
+
+    pub fn perpetual(
+        exchange: OrbitExchange,
+        currency: OrbitCurrency,
+        settlement: OrbitSettlement,
+    ) -> Self {
+        Self::new(
+            exchange,
+            currency,
+            settlement,
+            OrbitContractType::PerpetualFuture,
+            None,
+            None,
+        )
+    }
+
+    pub fn future(
+        exchange: OrbitExchange,
+        currency: OrbitCurrency,
+        settlement: OrbitSettlement,
+        expiration: Expiration,
+    ) -> Self {
+        Self::new(
+            exchange,
+            currency,
+            settlement,
+            OrbitContractType::Future,
+            Some(expiration),
+            None,
+        )
+    }
+
+    pub fn option(
+        exchange: OrbitExchange,
+        currency: OrbitCurrency,
+        settlement: OrbitSettlement,
+        contract_type: OrbitContractType,
+        expiration: Expiration,
+        strike: Strike,
+    ) -> Self {
+        Self::new(
+            exchange,
+            currency,
+            settlement,
+            contract_type,
+            Some(expiration),
+            Some(strike),
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct OrbitStorageOptionOrderbook {
     puts: OrbitStorageOrderbook,
     calls: OrbitStorageOrderbook,
 }
 
-#[derive(Debug)]
-pub enum OrbitExchangeClient {
-    Delta(DeltaClient),
-    Deribit(DeribitClient),
+impl OrbitStorageOptionOrderbook {
+    pub fn puts(&self) -> &OrbitStorageOrderbook {
+        &self.puts
+    }
+
+    pub fn calls(&self) -> &OrbitStorageOrderbook {
+        &self.calls
+    }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum OrbitExchange {
     Deribit,
     Delta,
 }
 
-#[derive(Clone, Debug)]
+impl OrbitExchange {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrbitExchange::Deribit => "deribit",
+            OrbitExchange::Delta => "delta",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct OrbitEvent {
     pub exchange: OrbitExchange,
     pub symbol: String,
     pub currency: Option<OrbitCurrency>,
+    pub settlement: Option<OrbitSettlement>,
     pub contract_type: Option<OrbitContractType>,
     pub expiration: Option<DateTime<Utc>>,
     pub strike: Option<Strike>,
@@ -622,10 +1738,12 @@ pub struct OrbitEvent {
 }
 
 impl OrbitEvent {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         exchange: OrbitExchange,
         symbol: String,
         currency: Option<OrbitCurrency>,
+        settlement: Option<OrbitSettlement>,
         contract_type: Option<OrbitContractType>,
         expiration: Option<DateTime<Utc>>,
         strike: Option<Strike>,
@@ -635,46 +1753,201 @@ impl OrbitEvent {
             exchange,
             symbol,
             currency,
+            settlement,
             contract_type,
-            expiration,
+            expiration: expiration.map(expiration_key),
             strike,
             payload,
         }
     }
+
+    pub fn index_price(
+        exchange: OrbitExchange,
+        index_name: String,
+        currency: OrbitCurrency,
+        index: OrbitIndexPrice,
+    ) -> Self {
+        // an index is the USD price of the coin
+        Self::new(
+            exchange,
+            index_name,
+            Some(currency),
+            Some(OrbitSettlement::Linear),
+            Some(OrbitContractType::Spot),
+            None,
+            None,
+            Some(OrbitEventPayload::IndexPrice(index)),
+        )
+    }
+
+    // contract details come from the instrument when the stream knows the symbol
+    pub fn for_instrument(
+        exchange: OrbitExchange,
+        symbol: String,
+        instrument: Option<&OrbitInstrument>,
+        payload: OrbitEventPayload,
+    ) -> Self {
+        Self::new(
+            exchange,
+            symbol,
+            instrument.map(|x| x.base.clone()),
+            instrument.map(|x| x.settlement),
+            instrument.map(|x| x.contract_type.clone()),
+            instrument.and_then(|x| x.expiration_date),
+            instrument.and_then(|x| x.strike),
+            // sizes of every venue end up in the underlying
+            Some(match instrument {
+                Some(instrument) => instrument.normalize(payload),
+                None => payload,
+            }),
+        )
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum OrbitEventPayload {
     // OrderbookSnapshot(OrderbookUpdate),
     OrderbookUpdate(OrderbookUpdate),
+    // the exchange stream lost sync on this book, it's emptied until the next snapshot
+    OrderbookResync,
+    Ticker(OrbitTicker),
+    Trade(OrbitTrade),
+    // the event symbol is the index name
+    IndexPrice(OrbitIndexPrice),
+    Funding(OrbitFunding),
+    // with the instrument itself, its quote and specs are more than the event carries
+    Lifecycle(OrbitLifecycle, Box<OrbitInstrument>),
+}
+
+// a change of the listings found by OrbitData::refresh_instruments. Storage adds or drops
+// the book, the market data stops or starts around it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrbitLifecycle {
+    Listed,
+    Delisted,
+    Expired,
 }
 
-// orderbook snapshots are just orderbooks updates with
-// more levels and all them are "New" type
-#[derive(Clone, Debug)]
+// orderbook snapshots are orderbook updates with is_snapshot set, all their levels
+// are "New" type and they replace the whole book instead of being applied on top of it
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct OrderbookUpdate {
+    pub is_snapshot: bool,
     pub timestamp: u64,
     pub bids: Vec<OrderbookUpdateLevel>,
     pub asks: Vec<OrderbookUpdateLevel>,
 }
 
-pub type Price = f64;
-pub type Amount = f64;
+// fixed point, so that levels and strikes match exactly and sizes add up without drift
+pub type Price = Decimal;
+pub type Amount = Decimal;
+
+// the venue's own marks for an instrument, prices are in the units its book is quoted in
+// and vols are fractions (0.65 is 65%), None when the venue quotes none
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OrbitTicker {
+    pub timestamp: u64,
+    pub mark_price: Price,
+    pub mark_iv: Option<f64>,
+    pub bid_iv: Option<f64>,
+    pub ask_iv: Option<f64>,
+    pub greeks: Option<OrbitGreeks>,
+    pub open_interest: Option<Amount>,
+    pub underlying_price: Option<Price>,
+    pub index_price: Option<Price>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OrbitIndexPrice {
+    pub price: Price,
+    // ms on deribit, delta's spot price carries none
+    pub timestamp: Option<u64>,
+}
+
+// funding of a perpetual as a fraction of notional, positive when longs pay shorts. Both
+// venues publish an 8h rate whatever their payment schedule, deribit accrues continuously
+// so it has no next funding time. Timestamps are the venue's own (ms on deribit, us on delta).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OrbitFunding {
+    pub timestamp: u64,
+    pub rate_8h: f64,
+    pub next_funding_at: Option<DateTime<Utc>>,
+}
+
+impl OrbitFunding {
+    // three periods a day, not compounded
+    pub fn annualized(&self) -> f64 {
+        self.rate_8h * 3.0 * 365.0
+    }
+}
 
-#[derive(Clone, Debug)]
+// one print, side is the aggressor's. Timestamps are the venue's own like those of book
+// updates (ms on deribit, us on delta), delta has no trade ids and neither iv.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OrbitTrade {
+    pub trade_id: Option<String>,
+    pub timestamp: u64,
+    pub price: Price,
+    pub size: Amount,
+    pub side: OrbitTradeSide,
+    pub iv: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum OrbitTradeSide {
+    Buy,
+    Sell,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OrbitGreeks {
+    pub delta: f64,
+    pub gamma: f64,
+    pub vega: f64,
+    pub theta: f64,
+    pub rho: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct OrderbookUpdateLevel(pub OrderbookUpdateType, pub Price, pub Amount);
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum OrderbookUpdateType {
     New,
     Change,
     Delete,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum OrbitCurrency {
     Btc,
     Eth,
     Sol,
+    Usd,
+    Usdt,
+    Usdc,
     Unimplemented,
 }
+
+impl OrbitCurrency {
+    // dollars and the stablecoins tracking them, all taken at par
+    pub fn is_usd(&self) -> bool {
+        matches!(
+            self,
+            OrbitCurrency::Usd | OrbitCurrency::Usdt | OrbitCurrency::Usdc
+        )
+    }
+}
+
+// Inverse contracts are margined and settled in the underlying coin (Deribit's BTC and ETH
+// books), linear ones in the quote currency (Delta's USDT books, Deribit's USDC ones).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum OrbitSettlement {
+    Linear,
+    Inverse,
+}
+
+impl OrbitSettlement {
+    // inverse first, it is preferred where either settlement would do
+    pub const ALL: [Self; 2] = [OrbitSettlement::Inverse, OrbitSettlement::Linear];
+}
@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use tokio::sync::{
+    broadcast::Sender,
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+};
+
+use crate::{OrbitEvent, OrbitEventPayload, OrbitExchange, OrbitInstrument, OrbitLifecycle};
+
+// What changed between two instrument lists, instruments are told apart by exchange and
+// symbol. An instrument past its expiration counts as expired whether the exchange still
+// lists it or not, and is never listed again.
+#[derive(Clone, Debug, Default)]
+pub struct OrbitListingDiff {
+    pub listed: Vec<OrbitInstrument>,
+    pub delisted: Vec<OrbitInstrument>,
+    pub expired: Vec<OrbitInstrument>,
+}
+
+impl OrbitListingDiff {
+    pub fn new(
+        current: &[OrbitInstrument],
+        latest: &[OrbitInstrument],
+        now: DateTime<Utc>,
+    ) -> Self {
+        let key = |x: &OrbitInstrument| (x.exchange.clone(), x.symbol.clone());
+        let is_expired = |x: &OrbitInstrument| x.expiration_datetime.is_some_and(|e| e <= now);
+        let current_keys: HashSet<_> = current.iter().map(key).collect();
+        let latest_keys: HashSet<_> = latest.iter().map(key).collect();
+
+        let mut diff = Self::default();
+        for instrument in latest.iter() {
+            if !current_keys.contains(&key(instrument)) && !is_expired(instrument) {
+                diff.listed.push(instrument.clone());
+            }
+        }
+        for instrument in current.iter() {
+            if is_expired(instrument) {
+                diff.expired.push(instrument.clone());
+            } else if !latest_keys.contains(&key(instrument)) {
+                diff.delisted.push(instrument.clone());
+            }
+        }
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listed.is_empty() && self.delisted.is_empty() && self.expired.is_empty()
+    }
+
+    // delisted and expired ones, the streams drop both alike
+    pub fn removed(&self) -> impl Iterator<Item = &OrbitInstrument> {
+        self.delisted.iter().chain(self.expired.iter())
+    }
+
+    // brings an instrument list up to date
+    pub fn apply(&self, instruments: &mut Vec<OrbitInstrument>) {
+        let removed: HashSet<_> = self
+            .removed()
+            .map(|x| (x.exchange.clone(), x.symbol.clone()))
+            .collect();
+        instruments.retain(|x| !removed.contains(&(x.exchange.clone(), x.symbol.clone())));
+        instruments.extend(self.listed.iter().cloned());
+    }
+
+    pub fn listed_events(&self) -> Vec<OrbitEvent> {
+        self.listed
+            .iter()
+            .map(|x| lifecycle_event(x, OrbitLifecycle::Listed))
+            .collect()
+    }
+
+    pub fn removed_events(&self) -> Vec<OrbitEvent> {
+        self.delisted
+            .iter()
+            .map(|x| lifecycle_event(x, OrbitLifecycle::Delisted))
+            .chain(
+                self.expired
+                    .iter()
+                    .map(|x| lifecycle_event(x, OrbitLifecycle::Expired)),
+            )
+            .collect()
+    }
+}
+
+fn lifecycle_event(instrument: &OrbitInstrument, lifecycle: OrbitLifecycle) -> OrbitEvent {
+    OrbitEvent::for_instrument(
+        instrument.exchange.clone(),
+        instrument.symbol.clone(),
+        Some(instrument),
+        OrbitEventPayload::Lifecycle(lifecycle, Box::new(instrument.clone())),
+    )
+}
+
+// sent by the connector to one of its running streams, which (un)subscribes on its live
+// connection and follows the new set from then on, reconnects included
+#[derive(Clone, Debug)]
+pub enum OrbitSubscriptionChange {
+    Subscribe(Vec<OrbitInstrument>),
+    Unsubscribe(Vec<OrbitInstrument>),
+}
+
+#[derive(Debug)]
+struct OrbitStreamHandle {
+    symbols: HashSet<String>,
+    control: UnboundedSender<OrbitSubscriptionChange>,
+}
+
+// The running streams of a connector with the symbols each follows. A stream takes up to
+// chunk_size instruments, new instruments fill the streams with room left before new
+// streams are started, and a stream whose last instrument goes away is stopped.
+#[derive(Debug, Default)]
+pub(crate) struct OrbitStreamSet {
+    // where the streams push their events, set by the first consume
+    sender: Option<Sender<OrbitEvent>>,
+    streams: Vec<OrbitStreamHandle>,
+}
+
+impl OrbitStreamSet {
+    pub fn set_sender(&mut self, sender: Sender<OrbitEvent>) {
+        self.sender = Some(sender);
+    }
+
+    pub fn sender(&self) -> Option<Sender<OrbitEvent>> {
+        self.sender.clone()
+    }
+
+    // hands instruments not followed yet to the running streams, returns the chunks that
+    // need a stream of their own together with its control end
+    pub fn assign(
+        &mut self,
+        instruments: Vec<OrbitInstrument>,
+        chunk_size: usize,
+    ) -> Vec<(
+        Vec<OrbitInstrument>,
+        UnboundedReceiver<OrbitSubscriptionChange>,
+    )> {
+        let mut followed: HashSet<String> = self
+            .streams
+            .iter()
+            .flat_map(|stream| stream.symbols.iter().cloned())
+            .collect();
+        let mut pending: Vec<OrbitInstrument> = instruments
+            .into_iter()
+            .filter(|x| followed.insert(x.symbol.clone()))
+            .collect();
+
+        for stream in self.streams.iter_mut() {
+            let room = chunk_size.saturating_sub(stream.symbols.len());
+            if room == 0 || pending.is_empty() {
+                continue;
+            }
+            let added: Vec<OrbitInstrument> = pending.drain(..room.min(pending.len())).collect();
+            stream
+                .symbols
+                .extend(added.iter().map(|x| x.symbol.clone()));
+            if stream
+                .control
+                .send(OrbitSubscriptionChange::Subscribe(added))
+                .is_err()
+            {
+                warn!("stream is gone, its new instruments are lost");
+            }
+        }
+
+        pending
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let (control, receiver) = mpsc::unbounded_channel();
+                self.streams.push(OrbitStreamHandle {
+                    symbols: chunk.iter().map(|x| x.symbol.clone()).collect(),
+                    control,
+                });
+                (chunk.to_vec(), receiver)
+            })
+            .collect()
+    }
+
+    // instruments no stream follows are ignored
+    pub fn remove(&mut self, instruments: &[OrbitInstrument]) {
+        for stream in self.streams.iter_mut() {
+            let removed: Vec<OrbitInstrument> = instruments
+                .iter()
+                .filter(|x| stream.symbols.remove(&x.symbol))
+                .cloned()
+                .collect();
+            if removed.is_empty() {
+                continue;
+            }
+            if stream
+                .control
+                .send(OrbitSubscriptionChange::Unsubscribe(removed))
+                .is_err()
+            {
+                warn!("stream is gone, nothing to unsubscribe");
+            }
+        }
+        // dropping the control end stops the stream once it went through its messages
+        self.streams.retain(|stream| !stream.symbols.is_empty());
+    }
+}
+
+// instruments of one exchange
+pub fn of_exchange(
+    instruments: &[OrbitInstrument],
+    exchange: &OrbitExchange,
+) -> Vec<OrbitInstrument> {
+    instruments
+        .iter()
+        .filter(|x| x.exchange == *exchange)
+        .cloned()
+        .collect()
+}
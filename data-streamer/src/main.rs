@@ -1,7 +1,10 @@
+use std::env;
 use std::time::Instant;
 
 use anyhow::{Error, Result};
 
+use data_streamer::config::OrbitConfig;
+use data_streamer::recorder::{OrbitRecorder, OrbitRecorderConfig};
 use data_streamer::{OrbitCurrency, OrbitData, OrbitExchange, OrbitOrderbookStorage};
 // use exchanges::delta::model::*;
 // use exchanges::deribit::model::*;
@@ -20,7 +23,17 @@ async fn main() -> Result<(), Error> {
     // let exchanges = vec![OrbitExchange::Deribit];
     let currencies = vec![OrbitCurrency::Btc, OrbitCurrency::Eth, OrbitCurrency::Sol];
     // let currencies = vec![OrbitCurrency::Btc];
-    let orbit_data = OrbitData::new(exchanges, currencies);
+    let config = OrbitConfig::load()?;
+    info!("{:?} config {:?}", config.environment, config);
+    // ORBIT_RECORD_DIR keeps the raw frames of the session for research and replay
+    let recorder = match env::var("ORBIT_RECORD_DIR") {
+        Ok(dir) => Some(OrbitRecorder::start(OrbitRecorderConfig::new(dir))?),
+        Err(_) => None,
+    };
+    let orbit_data = match recorder.clone() {
+        Some(recorder) => OrbitData::recording(&config, exchanges, currencies, recorder),
+        None => OrbitData::from_config(&config, exchanges, currencies),
+    };
     debug!("orbit {:?}", orbit_data);
 
     let products = orbit_data.get_all_instruments().await?;
@@ -43,7 +56,9 @@ async fn main() -> Result<(), Error> {
         let elapsed = begin.elapsed().as_nanos();
         
         let begin2 = Instant::now();
-        let storage = orbit_storage.process(event)?;
+        if let Err(err) = orbit_storage.process(event) {
+            warn!("dropping event: {err}");
+        }
         let elapsed2 = begin2.elapsed().as_nanos();
         if i % 1000 == 0 {
             // info!("storage {:?}", storage);
@@ -61,6 +76,9 @@ async fn main() -> Result<(), Error> {
     let event_avg = event_times.iter().sum::<u128>() as f64 / event_times.len() as f64;
 
     info!("sample of 10k or up to 100kclog...avg process time {process_avg}, avg time between events {event_avg}");
+    if let Some(recorder) = recorder {
+        recorder.flush()?;
+    }
     Ok(())
 }
 
@@ -0,0 +1,192 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::{
+    Expiration, OrbitBookKey, OrbitContractType, OrbitCurrency, OrbitExchange, OrbitInstrument,
+    OrbitSettlement, Strike,
+};
+
+// What makes two listings the same contract, whatever the exchange calls it. Keyed like
+// the books: expiries by UTC day since Deribit settles at 08:00 and Delta at 12:00, perps
+// without expiration and futures without strike. A key without settlement stands for the
+// contract however it settles, see OrbitMatchRules.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CanonicalContractKey {
+    pub underlying: OrbitCurrency,
+    pub kind: OrbitContractType,
+    pub expiry: Option<Expiration>,
+    pub strike: Option<Strike>,
+    pub settlement: Option<OrbitSettlement>,
+}
+
+impl CanonicalContractKey {
+    pub fn from_instrument(instrument: &OrbitInstrument) -> Self {
+        Self::from_book_key(&OrbitBookKey::from_instrument(instrument))
+    }
+
+    // the contract a book belongs to, i.e. its key without the exchange
+    pub fn from_book_key(book: &OrbitBookKey) -> Self {
+        let book = book.clone();
+        Self {
+            underlying: book.currency,
+            kind: book.contract_type,
+            expiry: book.expiration,
+            strike: book.strike,
+            settlement: Some(book.settlement),
+        }
+    }
+
+    // whole days from this expiry to the other's, None unless both have one
+    fn days_apart(&self, other: &Self) -> Option<i64> {
+        Some((other.expiry? - self.expiry?).num_days())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OrbitMatchRules {
+    // inverse and linear listings only match among themselves, off by default as prices
+    // are compared in USD anyway
+    pub same_settlement: bool,
+    // listings of a strike whose expiries are up to this many days apart are reported
+    pub near_miss_days: i64,
+}
+
+impl Default for OrbitMatchRules {
+    fn default() -> Self {
+        Self {
+            same_settlement: false,
+            near_miss_days: 1,
+        }
+    }
+}
+
+impl OrbitMatchRules {
+    // the key listings are matched on
+    pub fn identity(&self, key: &CanonicalContractKey) -> CanonicalContractKey {
+        CanonicalContractKey {
+            settlement: key.settlement.filter(|_| self.same_settlement),
+            ..key.clone()
+        }
+    }
+
+    // why two keys that aren't the same contract come close, None when they don't
+    fn mismatch(
+        &self,
+        a: &CanonicalContractKey,
+        b: &CanonicalContractKey,
+    ) -> Option<OrbitMismatch> {
+        let settles_alike = !self.same_settlement || a.settlement == b.settlement;
+        if a.expiry == b.expiry {
+            return (!settles_alike).then_some(OrbitMismatch::Settlement);
+        }
+        match a.days_apart(b) {
+            Some(days) if days.abs() <= self.near_miss_days && settles_alike => {
+                Some(OrbitMismatch::Expiry(days))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrbitMismatch {
+    // days from the first listing's expiry to the second's
+    Expiry(i64),
+    Settlement,
+}
+
+// two listings on different exchanges that are nearly the same contract, at least one of
+// them left without a match
+#[derive(Clone, Debug)]
+pub struct OrbitNearMiss {
+    pub first: OrbitInstrument,
+    pub second: OrbitInstrument,
+    pub mismatch: OrbitMismatch,
+}
+
+impl fmt::Display for OrbitNearMiss {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} {} and {:?} {}: ",
+            self.first.exchange, self.first.symbol, self.second.exchange, self.second.symbol
+        )?;
+        match self.mismatch {
+            OrbitMismatch::Expiry(days) => write!(f, "expiries {} days apart", days),
+            OrbitMismatch::Settlement => write!(f, "settled differently"),
+        }
+    }
+}
+
+// Listings matched across exchanges. A contract matches when every exchange lists it,
+// the listings that don't are checked for near misses.
+#[derive(Clone, Debug, Default)]
+pub struct OrbitMatchReport {
+    pub matched: HashMap<CanonicalContractKey, Vec<OrbitInstrument>>,
+    pub near_misses: Vec<OrbitNearMiss>,
+}
+
+impl OrbitMatchReport {
+    pub fn new(
+        instruments: &[OrbitInstrument],
+        exchanges: &[OrbitExchange],
+        rules: &OrbitMatchRules,
+    ) -> Self {
+        let keys: Vec<CanonicalContractKey> = instruments
+            .iter()
+            .map(CanonicalContractKey::from_instrument)
+            .collect();
+
+        let mut groups: HashMap<CanonicalContractKey, Vec<usize>> = HashMap::new();
+        for (i, key) in keys.iter().enumerate() {
+            groups.entry(rules.identity(key)).or_default().push(i);
+        }
+        let mut report = Self::default();
+        let mut matched = HashSet::new();
+        for (key, group) in groups.into_iter() {
+            let listed_on: HashSet<_> = group.iter().map(|i| &instruments[*i].exchange).collect();
+            if exchanges.iter().all(|x| listed_on.contains(x)) {
+                matched.extend(group.iter().copied());
+                report
+                    .matched
+                    .insert(key, group.iter().map(|i| instruments[*i].clone()).collect());
+            }
+        }
+
+        // near misses share underlying, kind and strike, so only those are compared
+        let mut buckets: HashMap<_, Vec<usize>> = HashMap::new();
+        for (i, key) in keys.iter().enumerate() {
+            buckets
+                .entry((&key.underlying, &key.kind, key.strike))
+                .or_default()
+                .push(i);
+        }
+        let mut buckets: Vec<_> = buckets.into_values().collect();
+        buckets.sort();
+        for bucket in buckets.iter() {
+            for (n, &i) in bucket.iter().enumerate() {
+                for &j in bucket[n + 1..].iter() {
+                    let (first, second) = (&instruments[i], &instruments[j]);
+                    if first.exchange == second.exchange
+                        || (matched.contains(&i) && matched.contains(&j))
+                    {
+                        continue;
+                    }
+                    if let Some(mismatch) = rules.mismatch(&keys[i], &keys[j]) {
+                        report.near_misses.push(OrbitNearMiss {
+                            first: first.clone(),
+                            second: second.clone(),
+                            mismatch,
+                        });
+                    }
+                }
+            }
+        }
+        report
+    }
+
+    // every matched listing
+    pub fn instruments(&self) -> Vec<OrbitInstrument> {
+        self.matched.values().flatten().cloned().collect()
+    }
+}
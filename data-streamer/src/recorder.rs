@@ -0,0 +1,230 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+use anyhow::{anyhow, Context, Error};
+use chrono::{NaiveDate, TimeZone, Utc};
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::OrbitExchange;
+
+pub const RECORDING_EXTENSION: &str = "ndjson.gz";
+
+// one websocket text frame exactly as the exchange sent it, one json line per frame
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrbitRawFrame {
+    // local receive time, ns since the unix epoch
+    pub received_at: i64,
+    pub exchange: OrbitExchange,
+    pub connection_id: Uuid,
+    pub raw: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct OrbitRecorderConfig {
+    pub dir: PathBuf,
+    // uncompressed bytes per file before rolling over to the next one
+    pub max_file_bytes: u64,
+    pub compression: Compression,
+}
+
+impl OrbitRecorderConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            max_file_bytes: 256 * 1024 * 1024,
+            compression: Compression::default(),
+        }
+    }
+}
+
+enum RecorderMessage {
+    Frame(OrbitRawFrame),
+    // finish the open files so they can be read, answered once done
+    Flush(mpsc::Sender<()>),
+}
+
+// Cheap to clone handle shared by every stream. Frames go through a channel to a
+// writer thread so the message loops never touch the disk.
+//
+// Files land in <dir>/<yyyy-mm-dd>/<exchange>/<nnnnn>.ndjson.gz, a new file is started
+// on a new (UTC) day, once max_file_bytes is reached and after every flush.
+#[derive(Clone, Debug)]
+pub struct OrbitRecorder {
+    sender: mpsc::Sender<RecorderMessage>,
+}
+
+impl OrbitRecorder {
+    pub fn start(config: OrbitRecorderConfig) -> Result<Self, Error> {
+        fs::create_dir_all(&config.dir)
+            .with_context(|| format!("creating recording dir {:?}", config.dir))?;
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("orbit-recorder".to_string())
+            .spawn(move || RecorderWriter::new(config).run(receiver))?;
+        Ok(Self { sender })
+    }
+
+    pub fn record(&self, exchange: OrbitExchange, connection_id: Uuid, raw: &str) {
+        let frame = OrbitRawFrame {
+            received_at: Utc::now().timestamp_nanos(),
+            exchange,
+            connection_id,
+            raw: raw.to_string(),
+        };
+        if self.sender.send(RecorderMessage::Frame(frame)).is_err() {
+            error!("recorder writer is gone, dropping frame");
+        }
+    }
+
+    // blocks until everything recorded so far is on disk in finished files, call it
+    // before exiting or the open files miss their gzip trailer
+    pub fn flush(&self) -> Result<(), Error> {
+        let (done, wait) = mpsc::channel();
+        self.sender
+            .send(RecorderMessage::Flush(done))
+            .map_err(|_| anyhow!("recorder writer is gone"))?;
+        wait.recv().map_err(|_| anyhow!("recorder writer is gone"))
+    }
+}
+
+struct RecorderFile {
+    date: NaiveDate,
+    bytes: u64,
+    encoder: GzEncoder<BufWriter<File>>,
+}
+
+struct RecorderWriter {
+    config: OrbitRecorderConfig,
+    files: HashMap<OrbitExchange, RecorderFile>,
+}
+
+impl RecorderWriter {
+    fn new(config: OrbitRecorderConfig) -> Self {
+        Self {
+            config,
+            files: HashMap::new(),
+        }
+    }
+
+    fn run(mut self, receiver: mpsc::Receiver<RecorderMessage>) {
+        while let Ok(message) = receiver.recv() {
+            match message {
+                RecorderMessage::Frame(frame) => {
+                    if let Err(err) = self.write(&frame) {
+                        error!("recording {:?} frame failed: {err}", frame.exchange);
+                    }
+                }
+                RecorderMessage::Flush(done) => {
+                    self.finish_all();
+                    let _ = done.send(());
+                }
+            }
+        }
+        self.finish_all();
+    }
+
+    fn write(&mut self, frame: &OrbitRawFrame) -> Result<(), Error> {
+        let date = Utc.timestamp_nanos(frame.received_at).date_naive();
+        let rotate = match self.files.get(&frame.exchange) {
+            Some(file) => file.date != date || file.bytes >= self.config.max_file_bytes,
+            None => true,
+        };
+        if rotate {
+            if let Some(file) = self.files.remove(&frame.exchange) {
+                Self::finish(file);
+            }
+            let file = self.open(&frame.exchange, date)?;
+            self.files.insert(frame.exchange.clone(), file);
+        }
+        let file = self
+            .files
+            .get_mut(&frame.exchange)
+            .ok_or_else(|| anyhow!("no open file"))?;
+        let mut line = serde_json::to_vec(frame)?;
+        line.push(b'\n');
+        file.encoder.write_all(&line)?;
+        file.bytes += line.len() as u64;
+        Ok(())
+    }
+
+    fn open(&self, exchange: &OrbitExchange, date: NaiveDate) -> Result<RecorderFile, Error> {
+        let dir = partition_dir(&self.config.dir, date, exchange);
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{:05}.{RECORDING_EXTENSION}", next_sequence(&dir)?));
+        debug!("recording {:?} to {:?}", exchange, path);
+        Ok(RecorderFile {
+            date,
+            bytes: 0,
+            encoder: GzEncoder::new(BufWriter::new(File::create(path)?), self.config.compression),
+        })
+    }
+
+    fn finish(file: RecorderFile) {
+        let result = file.encoder.finish().and_then(|mut writer| writer.flush());
+        if let Err(err) = result {
+            error!("finishing recording failed: {err}");
+        }
+    }
+
+    fn finish_all(&mut self) {
+        for (_, file) in self.files.drain() {
+            Self::finish(file);
+        }
+    }
+}
+
+pub fn partition_dir(root: &Path, date: NaiveDate, exchange: &OrbitExchange) -> PathBuf {
+    root.join(date.format("%Y-%m-%d").to_string())
+        .join(exchange.as_str())
+}
+
+// never reopen an existing file, a restarted recorder continues the numbering
+fn next_sequence(dir: &Path) -> Result<u32, Error> {
+    let mut next = 0;
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let sequence = name
+            .to_str()
+            .and_then(|name| name.strip_suffix(&format!(".{RECORDING_EXTENSION}")))
+            .and_then(|sequence| sequence.parse::<u32>().ok());
+        if let Some(sequence) = sequence {
+            next = next.max(sequence + 1);
+        }
+    }
+    Ok(next)
+}
+
+// every recording under root, in day, exchange and sequence order
+pub fn recording_files(root: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut files = vec![];
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir).with_context(|| format!("reading {dir:?}"))? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if path.to_string_lossy().ends_with(RECORDING_EXTENSION) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+pub fn read_frames(
+    path: &Path,
+) -> Result<impl Iterator<Item = Result<OrbitRawFrame, Error>>, Error> {
+    let file = File::open(path).with_context(|| format!("opening recording {path:?}"))?;
+    let lines = BufReader::new(MultiGzDecoder::new(file)).lines();
+    Ok(lines.map(|line| Ok(serde_json::from_str::<OrbitRawFrame>(&line?)?)))
+}
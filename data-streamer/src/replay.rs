@@ -0,0 +1,203 @@
+use std::{
+    collections::BTreeMap,
+    iter::Peekable,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Error};
+use log::{error, warn};
+use tokio::{
+    sync::broadcast::{self, Receiver},
+    time::Instant,
+};
+
+use crate::{
+    exchanges::{delta::model::DeltaFrameDecoder, deribit::model::DeribitFrameDecoder},
+    recorder::{read_frames, recording_files, OrbitRawFrame},
+    OrbitEvent, OrbitExchange, OrbitInstrument,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OrbitReplaySpeed {
+    // as fast as the files can be decoded
+    Max,
+    // frames keep the spacing they were received with, 2.0 replays twice as fast
+    WallClock(f64),
+}
+
+// Feeds recorded frames back through the exchange decoders, so storage and analyzer see
+// the same events they saw live. Frames of all exchanges are merged on receive time.
+#[derive(Clone, Debug)]
+pub struct OrbitReplay {
+    files: Vec<PathBuf>,
+    instruments: Vec<OrbitInstrument>,
+    speed: OrbitReplaySpeed,
+    capacity: usize,
+}
+
+impl OrbitReplay {
+    pub fn new(files: Vec<PathBuf>, instruments: Vec<OrbitInstrument>) -> Self {
+        Self {
+            files,
+            instruments,
+            speed: OrbitReplaySpeed::Max,
+            capacity: 250_000,
+        }
+    }
+
+    // every recording under a recorder dir
+    pub fn from_dir(root: &Path, instruments: Vec<OrbitInstrument>) -> Result<Self, Error> {
+        Ok(Self::new(recording_files(root)?, instruments))
+    }
+
+    // a rate that isn't positive would never get to the next frame
+    pub fn with_speed(mut self, speed: OrbitReplaySpeed) -> Result<Self, Error> {
+        if let OrbitReplaySpeed::WallClock(rate) = speed {
+            if rate.is_nan() || rate <= 0.0 {
+                return Err(anyhow!("replay rate must be positive, got {rate}"));
+            }
+        }
+        self.speed = speed;
+        Ok(self)
+    }
+
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    // pull based and lossless, the one to use for backtests and regression tests
+    pub fn events(&self) -> OrbitReplayEvents {
+        OrbitReplayEvents {
+            frames: merge_frames(&self.files),
+            delta: DeltaFrameDecoder::new(&self.instruments),
+            deribit: DeribitFrameDecoder::new(&self.instruments),
+            pending: vec![],
+        }
+    }
+
+    // drop-in for OrbitData::consume_instruments, the channel closes when the files run out.
+    // Like the live feed a receiver that falls more than the capacity behind lags, at
+    // Max speed the replay only yields between events to let consumers keep up.
+    pub fn start(self) -> Receiver<OrbitEvent> {
+        let (sender, receiver) = broadcast::channel(self.capacity);
+        let mut events = self.events();
+        tokio::spawn(async move {
+            let mut clock: Option<(i64, Instant)> = None;
+            while let Some((received_at, event)) = events.next_timed() {
+                if let OrbitReplaySpeed::WallClock(rate) = self.speed {
+                    let (first_at, started) = *clock.get_or_insert((received_at, Instant::now()));
+                    let offset = (received_at - first_at).max(0) as f64 / rate;
+                    tokio::time::sleep_until(started + Duration::from_nanos(offset as u64)).await;
+                } else {
+                    tokio::task::yield_now().await;
+                }
+                if sender.send(event).is_err() {
+                    warn!("replay has no receivers left, stopping");
+                    break;
+                }
+            }
+        });
+        receiver
+    }
+}
+
+pub struct OrbitReplayEvents {
+    frames: FrameMerge,
+    delta: DeltaFrameDecoder,
+    deribit: DeribitFrameDecoder,
+    // events of the current frame, reversed
+    pending: Vec<(i64, OrbitEvent)>,
+}
+
+impl OrbitReplayEvents {
+    // the event with the receive time (ns) of the frame it came from
+    pub fn next_timed(&mut self) -> Option<(i64, OrbitEvent)> {
+        while self.pending.is_empty() {
+            let frame = self.frames.next()?;
+            let decoded = match frame.exchange {
+                OrbitExchange::Delta => self.delta.decode(frame.connection_id, &frame.raw),
+                OrbitExchange::Deribit => self.deribit.decode(frame.connection_id, &frame.raw),
+            };
+            match decoded {
+                Ok(events) => {
+                    self.pending = events
+                        .into_iter()
+                        .rev()
+                        .map(|event| (frame.received_at, event))
+                        .collect()
+                }
+                // the live stream dead lettered these as well
+                Err(err) => warn!("skipping recorded frame: {err}"),
+            }
+        }
+        self.pending.pop()
+    }
+}
+
+impl Iterator for OrbitReplayEvents {
+    type Item = OrbitEvent;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_timed().map(|(_, event)| event)
+    }
+}
+
+type FrameIter = Box<dyn Iterator<Item = OrbitRawFrame> + Send>;
+
+// each exchange directory is already in time order, only the exchanges need merging
+struct FrameMerge {
+    sources: Vec<Peekable<FrameIter>>,
+}
+
+fn merge_frames(files: &[PathBuf]) -> FrameMerge {
+    let mut by_exchange: BTreeMap<Option<PathBuf>, Vec<PathBuf>> = BTreeMap::new();
+    for path in files {
+        let exchange_dir = path.parent().and_then(Path::file_name).map(PathBuf::from);
+        by_exchange
+            .entry(exchange_dir)
+            .or_default()
+            .push(path.clone());
+    }
+    let sources = by_exchange
+        .into_values()
+        .map(|mut files| {
+            files.sort();
+            let frames: FrameIter = Box::new(files.into_iter().flat_map(read_file));
+            frames.peekable()
+        })
+        .collect();
+    FrameMerge { sources }
+}
+
+fn read_file(path: PathBuf) -> FrameIter {
+    match read_frames(&path) {
+        Ok(frames) => Box::new(frames.filter_map(move |frame| {
+            frame
+                .map_err(|err| error!("bad line in {path:?}: {err}"))
+                .ok()
+        })),
+        Err(err) => {
+            error!("skipping recording: {err}");
+            Box::new(std::iter::empty())
+        }
+    }
+}
+
+impl Iterator for FrameMerge {
+    type Item = OrbitRawFrame;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut earliest: Option<(usize, i64)> = None;
+        for (i, source) in self.sources.iter_mut().enumerate() {
+            if let Some(frame) = source.peek() {
+                if earliest.is_none_or(|(_, at)| frame.received_at < at) {
+                    earliest = Some((i, frame.received_at));
+                }
+            }
+        }
+        let (i, _) = earliest?;
+        self.sources[i].next()
+    }
+}
@@ -0,0 +1,224 @@
+// Local websocket exchange for the stream tests. Each accepted connection plays the
+// next script, so reconnects can be scripted as a list of connections.
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+use data_streamer::{OrbitEvent, OrbitInstrument};
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::Receiver;
+use tokio::sync::mpsc;
+use tokio_tungstenite::{accept_async, tungstenite::Message};
+
+#[derive(Clone, Debug)]
+pub enum Step {
+    // wait for the next text frame from the client and record it
+    Receive,
+    Send(String),
+    Sleep(Duration),
+    // drop the connection without a close frame
+    Disconnect,
+    // keep the connection open until the test ends
+    Hold,
+}
+
+pub struct MockExchange {
+    pub url: String,
+    // every text frame received, tagged with the connection number
+    pub received: mpsc::UnboundedReceiver<(usize, String)>,
+}
+
+impl MockExchange {
+    pub async fn start(connections: Vec<Vec<Step>>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (received_tx, received) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            for (connection, script) in connections.into_iter().enumerate() {
+                let (tcp, _) = listener.accept().await.unwrap();
+                let mut ws = accept_async(tcp).await.unwrap();
+                for step in script {
+                    match step {
+                        Step::Receive => loop {
+                            match ws.next().await {
+                                Some(Ok(Message::Text(text))) => {
+                                    let _ = received_tx.send((connection, text));
+                                    break;
+                                }
+                                Some(Ok(_)) => continue,
+                                _ => break,
+                            }
+                        },
+                        Step::Send(text) => ws.send(Message::Text(text)).await.unwrap(),
+                        Step::Sleep(duration) => tokio::time::sleep(duration).await,
+                        Step::Disconnect => {
+                            drop(ws);
+                            break;
+                        }
+                        Step::Hold => {
+                            // drain client frames so its writes never block
+                            while let Some(Ok(msg)) = ws.next().await {
+                                if let Message::Text(text) = msg {
+                                    let _ = received_tx.send((connection, text));
+                                }
+                            }
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        Self { url, received }
+    }
+
+    pub async fn next_received(&mut self) -> (usize, String) {
+        tokio::time::timeout(Duration::from_secs(5), self.received.recv())
+            .await
+            .expect("mock exchange received nothing")
+            .expect("mock exchange stopped")
+    }
+}
+
+// Local REST endpoint answering every request with the same JSON body, records the
+// request lines so a test can tell what was asked for.
+pub struct MockRest {
+    pub url: String,
+    pub requested: mpsc::UnboundedReceiver<String>,
+}
+
+impl MockRest {
+    pub async fn start(body: String) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (requested_tx, requested) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            loop {
+                let (mut tcp, _) = listener.accept().await.unwrap();
+                let mut request = vec![];
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|x| x == b"\r\n\r\n") {
+                    match tcp.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request);
+                let _ = requested_tx.send(request.lines().next().unwrap_or("").to_string());
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = tcp.write_all(response.as_bytes()).await;
+            }
+        });
+
+        Self { url, requested }
+    }
+}
+
+pub async fn next_events(rx: &mut Receiver<OrbitEvent>, count: usize) -> Vec<OrbitEvent> {
+    let mut events = Vec::with_capacity(count);
+    for _ in 0..count {
+        let event = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .expect("timed out waiting for an OrbitEvent")
+            .unwrap();
+        events.push(event);
+    }
+    events
+}
+
+pub async fn assert_no_event(rx: &mut Receiver<OrbitEvent>) {
+    let result = tokio::time::timeout(Duration::from_millis(300), rx.recv()).await;
+    assert!(result.is_err(), "unexpected event {:?}", result);
+}
+
+pub fn delta_option(symbol: &str, strike: &str, settlement_time: &str) -> OrbitInstrument {
+    let contract_type = if symbol.starts_with("C-") {
+        "call_options"
+    } else {
+        "put_options"
+    };
+    let product: data_streamer::exchanges::delta::model::DeltaProduct =
+        serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "symbol": symbol,
+            "strike_price": strike,
+            "contract_type": contract_type,
+            "settlement_time": settlement_time,
+            "launch_time": null,
+            "underlying_asset": { "symbol": "BTC" },
+            "quoting_asset": { "symbol": "USDT" },
+            "spot_index": { "symbol": ".DEXBTUSD" }
+        }))
+        .unwrap();
+    OrbitInstrument::from(&product)
+}
+
+pub fn deribit_option(name: &str, strike: f64, expiration_timestamp: i64) -> OrbitInstrument {
+    let option_type = if name.ends_with("-C") { "call" } else { "put" };
+    let instrument: data_streamer::exchanges::deribit::model::DeribitInstrument =
+        serde_json::from_value(serde_json::json!({
+            "base_currency": "BTC",
+            "counter_currency": "USD",
+            "creation_timestamp": 0,
+            "expiration_timestamp": expiration_timestamp,
+            "future_type": null,
+            "instrument_id": 1,
+            "instrument_name": name,
+            "is_active": true,
+            "kind": "option",
+            "option_type": option_type,
+            "price_index": "btc_usd",
+            "quote_currency": "BTC",
+            "settlement_period": "week",
+            "strike": strike
+        }))
+        .unwrap();
+    OrbitInstrument::from(&instrument)
+}
+
+pub fn deribit_perpetual(name: &str) -> OrbitInstrument {
+    let instrument: data_streamer::exchanges::deribit::model::DeribitInstrument =
+        serde_json::from_value(serde_json::json!({
+            "base_currency": "BTC",
+            "counter_currency": "USD",
+            "creation_timestamp": 0,
+            "expiration_timestamp": 32503680000000i64,
+            "future_type": "reversed",
+            "instrument_id": 2,
+            "instrument_name": name,
+            "is_active": true,
+            "kind": "future",
+            "option_type": null,
+            "price_index": "btc_usd",
+            "quote_currency": "USD",
+            "settlement_period": "perpetual",
+            "strike": null
+        }))
+        .unwrap();
+    OrbitInstrument::from(&instrument)
+}
+
+pub fn delta_perpetual(symbol: &str) -> OrbitInstrument {
+    let product: data_streamer::exchanges::delta::model::DeltaProduct =
+        serde_json::from_value(serde_json::json!({
+            "id": 2,
+            "symbol": symbol,
+            "strike_price": null,
+            "contract_type": "perpetual_futures",
+            "settlement_time": null,
+            "launch_time": null,
+            "underlying_asset": { "symbol": "BTC" },
+            "quoting_asset": { "symbol": "USDT" },
+            "spot_index": { "symbol": ".DEXBTUSD" }
+        }))
+        .unwrap();
+    OrbitInstrument::from(&product)
+}
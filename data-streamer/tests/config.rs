@@ -0,0 +1,122 @@
+use data_streamer::config::{OrbitBookInterval, OrbitConfig, OrbitEnvironment};
+
+fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+    vars.iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[test]
+fn defaults_to_mainnet_everywhere() {
+    let config = OrbitConfig::from_sources(None, vec![]).unwrap();
+    assert_eq!(config, OrbitConfig::new(OrbitEnvironment::Mainnet));
+    assert_eq!(config.delta.rest_url, "https://api.delta.exchange");
+    assert_eq!(config.deribit.rest_url, "https://www.deribit.com/api/v2");
+    assert_eq!(config.deribit.ws_url, "wss://www.deribit.com/ws/api/v2");
+    assert_eq!(
+        config.deribit.book_channel("BTC-PERPETUAL"),
+        "book.BTC-PERPETUAL.100ms"
+    );
+}
+
+#[test]
+fn testnet_switches_rest_and_ws_together() {
+    let config = OrbitConfig::from_toml(r#"environment = "testnet""#).unwrap();
+    assert_eq!(config.environment, OrbitEnvironment::Testnet);
+    assert_eq!(config.deribit.rest_url, "https://test.deribit.com/api/v2");
+    assert_eq!(config.deribit.ws_url, "wss://test.deribit.com/ws/api/v2");
+    assert_eq!(config.delta.rest_url, "https://testnet-api.delta.exchange");
+    assert_eq!(config.delta.ws_url, "wss://testnet-socket.delta.exchange");
+}
+
+#[test]
+fn toml_overrides_single_fields() {
+    let config = OrbitConfig::from_toml(
+        r#"
+        [delta]
+        chunk_size = 10
+
+        [deribit]
+        ws_url = "ws://127.0.0.1:9000"
+        heartbeat_interval = 10
+        book_interval = "raw"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.delta.chunk_size, 10);
+    assert_eq!(config.delta.ws_url, "wss://socket.delta.exchange");
+    assert_eq!(config.deribit.ws_url, "ws://127.0.0.1:9000");
+    assert_eq!(config.deribit.rest_url, "https://www.deribit.com/api/v2");
+    assert_eq!(config.deribit.heartbeat_interval, 10);
+    assert_eq!(config.deribit.book_interval, OrbitBookInterval::Raw);
+    assert_eq!(
+        config.deribit.book_channel("BTC-PERPETUAL"),
+        "book.BTC-PERPETUAL.raw"
+    );
+    // raw tickers and trades aren't public
+    assert_eq!(
+        config.deribit.ticker_channel("BTC-PERPETUAL"),
+        "ticker.BTC-PERPETUAL.100ms"
+    );
+    assert_eq!(
+        config.deribit.trades_channel("BTC-PERPETUAL"),
+        "trades.BTC-PERPETUAL.100ms"
+    );
+}
+
+#[test]
+fn streams_raw_tickers_and_trades_with_an_api_key() {
+    let config = OrbitConfig::from_sources(
+        Some("[deribit]\nbook_interval = \"raw\"\nclient_id = \"id\""),
+        vars(&[("ORBIT_DERIBIT_CLIENT_SECRET", "secret")]),
+    )
+    .unwrap();
+    assert_eq!(config.deribit.credentials(), Some(("id", "secret")));
+    assert_eq!(
+        config.deribit.ticker_channel("BTC-PERPETUAL"),
+        "ticker.BTC-PERPETUAL.raw"
+    );
+    assert_eq!(
+        config.deribit.trades_channel("BTC-PERPETUAL"),
+        "trades.BTC-PERPETUAL.raw"
+    );
+}
+
+#[test]
+fn variables_override_the_file() {
+    let config = OrbitConfig::from_sources(
+        Some(
+            r#"
+            environment = "mainnet"
+            [deribit]
+            chunk_size = 200
+            "#,
+        ),
+        vars(&[
+            ("ORBIT_ENVIRONMENT", "testnet"),
+            ("ORBIT_DERIBIT_CHUNK_SIZE", "50"),
+            ("ORBIT_DELTA_HEARTBEAT_INTERVAL", "15"),
+            ("ORBIT_CONFIG", "ignored.toml"),
+            ("PATH", "/usr/bin"),
+        ]),
+    )
+    .unwrap();
+    assert_eq!(config.environment, OrbitEnvironment::Testnet);
+    assert_eq!(config.deribit.ws_url, "wss://test.deribit.com/ws/api/v2");
+    assert_eq!(config.deribit.chunk_size, 50);
+    assert_eq!(config.delta.heartbeat_interval, 15);
+}
+
+#[test]
+fn rejects_bad_values() {
+    assert!(OrbitConfig::from_toml(r#"environment = "staging""#).is_err());
+    assert!(OrbitConfig::from_toml("[deribit]\nbook_interval = \"1s\"").is_err());
+    assert!(OrbitConfig::from_toml("[deribit]\nunknown = 1").is_err());
+    assert!(OrbitConfig::from_toml("[delta]\nchunk_size = 21").is_err());
+    assert!(OrbitConfig::from_toml("[deribit]\nchunk_size = 0").is_err());
+    // 3 channels an instrument
+    assert!(OrbitConfig::from_toml("[deribit]\nchunk_size = 333").is_ok());
+    assert!(OrbitConfig::from_toml("[deribit]\nchunk_size = 334").is_err());
+    assert!(OrbitConfig::from_toml("[deribit]\nclient_id = \"id\"").is_err());
+    assert!(OrbitConfig::from_sources(None, vars(&[("ORBIT_DELTA_CHUNK_SIZE", "many")])).is_err());
+}
@@ -0,0 +1,518 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use common::{assert_no_event, delta_option, delta_perpetual, next_events, MockExchange, Step};
+use data_streamer::error::OrbitDeadLetter;
+use data_streamer::exchanges::delta::model::DeltaClient;
+use data_streamer::{
+    expiration_key, OrbitBookKey, OrbitContractType, OrbitCurrency, OrbitEvent, OrbitEventPayload,
+    OrbitExchange, OrbitExchangeConnector, OrbitFunding, OrbitIndexPrice, OrbitInstrument,
+    OrbitOrderbookStorage, OrbitSettlement, OrbitTrade, OrbitTradeSide, OrderbookUpdate,
+    OrderbookUpdateLevel, OrderbookUpdateType,
+};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc};
+
+const SYMBOL: &str = "C-BTC-20000-301222";
+const SETTLEMENT: &str = "2022-12-30T12:00:00Z";
+
+fn l2_orderbook(timestamp: u64, bid: &str, ask: &str) -> String {
+    json!({
+        "buy": [{ "depth": "10", "limit_price": bid, "size": 10 }],
+        "sell": [{ "depth": "4", "limit_price": ask, "size": 4 }],
+        "symbol": SYMBOL,
+        "type": "l2_orderbook",
+        "timestamp": timestamp
+    })
+    .to_string()
+}
+
+fn expected_event(timestamp: u64, bid: Decimal, ask: Decimal) -> OrbitEvent {
+    let settlement: DateTime<Utc> = SETTLEMENT.parse().unwrap();
+    OrbitEvent::new(
+        OrbitExchange::Delta,
+        SYMBOL.to_string(),
+        Some(OrbitCurrency::Btc),
+        Some(OrbitSettlement::Linear),
+        Some(OrbitContractType::CallOption),
+        Some(expiration_key(settlement)),
+        Some(dec!(20000)),
+        Some(OrbitEventPayload::OrderbookUpdate(OrderbookUpdate {
+            is_snapshot: true,
+            timestamp,
+            bids: vec![OrderbookUpdateLevel(
+                OrderbookUpdateType::New,
+                bid,
+                dec!(10),
+            )],
+            asks: vec![OrderbookUpdateLevel(OrderbookUpdateType::New, ask, dec!(4))],
+        })),
+    )
+}
+
+fn handshake() -> Vec<Step> {
+    vec![Step::Receive, Step::Receive]
+}
+
+async fn consume(mock: &MockExchange) -> (DeltaClient, broadcast::Receiver<OrbitEvent>) {
+    consume_with(DeltaClient::new().with_ws_url(&mock.url)).await
+}
+
+async fn consume_with(client: DeltaClient) -> (DeltaClient, broadcast::Receiver<OrbitEvent>) {
+    let (sender, rx) = broadcast::channel(100);
+    client
+        .consume(sender, vec![delta_option(SYMBOL, "20000", SETTLEMENT)])
+        .await
+        .unwrap();
+    (client, rx)
+}
+
+#[tokio::test]
+async fn subscribes_to_l2_orderbook_all_trades_spot_price_and_heartbeat() {
+    let mut mock = MockExchange::start(vec![vec![Step::Hold]]).await;
+    let (_client, _rx) = consume(&mock).await;
+
+    let (_, subscribe) = mock.next_received().await;
+    let subscribe: Value = serde_json::from_str(&subscribe).unwrap();
+    assert_eq!(
+        subscribe,
+        json!({
+            "type": "subscribe",
+            "payload": {
+                "channels": [
+                    { "name": "l2_orderbook", "symbols": [SYMBOL] },
+                    { "name": "all_trades", "symbols": [SYMBOL] },
+                    { "name": "v2/spot_price", "symbols": [".DEXBTUSD"] }
+                ]
+            }
+        })
+    );
+    let (_, heartbeat) = mock.next_received().await;
+    let heartbeat: Value = serde_json::from_str(&heartbeat).unwrap();
+    assert_eq!(heartbeat, json!({ "type": "enable_heartbeat" }));
+}
+
+#[tokio::test]
+async fn leaves_move_options_and_spot_pairs_unsubscribed() {
+    let mut mock = MockExchange::start(vec![vec![Step::Hold]]).await;
+    let unbooked = |symbol: &str, contract_type| {
+        OrbitInstrument::new(
+            OrbitExchange::Delta,
+            symbol.to_string(),
+            OrbitCurrency::Btc,
+            OrbitCurrency::Usdt,
+            contract_type,
+        )
+        .with_price_index(".DEXBTUSD".to_string())
+    };
+    let (sender, _rx) = broadcast::channel(100);
+    DeltaClient::new()
+        .with_ws_url(&mock.url)
+        .consume(
+            sender,
+            vec![
+                unbooked("MV-BTC-20000-301222", OrbitContractType::MoveOption),
+                delta_option(SYMBOL, "20000", SETTLEMENT),
+                unbooked("BTC_USDT", OrbitContractType::Spot),
+            ],
+        )
+        .await
+        .unwrap();
+
+    let (_, subscribe) = mock.next_received().await;
+    let subscribe: Value = serde_json::from_str(&subscribe).unwrap();
+    assert_eq!(
+        subscribe["payload"]["channels"],
+        json!([
+            { "name": "l2_orderbook", "symbols": [SYMBOL] },
+            { "name": "all_trades", "symbols": [SYMBOL] },
+            { "name": "v2/spot_price", "symbols": [".DEXBTUSD"] }
+        ])
+    );
+}
+
+#[tokio::test]
+async fn normalizes_snapshots_into_orbit_events() {
+    let mut script = handshake();
+    script.push(Step::Send(
+        json!({ "type": "subscriptions", "channels": [] }).to_string(),
+    ));
+    script.push(Step::Send(l2_orderbook(1, "100.5", "101")));
+    script.push(Step::Send(
+        json!({ "type": "heartbeat", "ts_origin": 1, "ts_publish": 2 }).to_string(),
+    ));
+    script.push(Step::Send(l2_orderbook(2, "99", "100")));
+    script.push(Step::Hold);
+    let mock = MockExchange::start(vec![script]).await;
+    let (client, mut rx) = consume(&mock).await;
+
+    let events = next_events(&mut rx, 2).await;
+    assert_eq!(
+        events,
+        vec![
+            expected_event(1, dec!(100.5), dec!(101.0)),
+            expected_event(2, dec!(99.0), dec!(100.0))
+        ]
+    );
+    assert_no_event(&mut rx).await;
+
+    let health = client.health();
+    assert_eq!(health.streams, 1);
+    assert!(health.is_healthy());
+}
+
+fn trade(timestamp: u64, price: &str, buyer_role: &str) -> Value {
+    let seller_role = if buyer_role == "taker" {
+        "maker"
+    } else {
+        "taker"
+    };
+    json!({
+        "price": price,
+        "size": 2,
+        "buyer_role": buyer_role,
+        "seller_role": seller_role,
+        "timestamp": timestamp
+    })
+}
+
+fn expected_trade(timestamp: u64, price: Decimal, side: OrbitTradeSide) -> OrbitTrade {
+    OrbitTrade {
+        trade_id: None,
+        timestamp,
+        price,
+        size: dec!(2),
+        side,
+        iv: None,
+    }
+}
+
+#[tokio::test]
+async fn normalizes_trades_into_a_bounded_tape() {
+    let snapshot = json!({
+        "type": "all_trades_snapshot",
+        "symbol": SYMBOL,
+        "trades": [trade(20, "101", "maker"), trade(10, "100", "taker")]
+    });
+    let mut live = trade(30, "102", "taker");
+    live["type"] = json!("all_trades");
+    live["symbol"] = json!(SYMBOL);
+    let mut script = handshake();
+    script.push(Step::Send(snapshot.to_string()));
+    script.push(Step::Send(live.to_string()));
+    // a resubscription repeats prints that are already on the tape
+    script.push(Step::Send(snapshot.to_string()));
+    script.push(Step::Hold);
+    let mock = MockExchange::start(vec![script]).await;
+    let (_client, mut rx) = consume(&mock).await;
+
+    let events = next_events(&mut rx, 5).await;
+    let trades: Vec<_> = events
+        .iter()
+        .map(|event| match &event.payload {
+            Some(OrbitEventPayload::Trade(trade)) => trade.clone(),
+            other => panic!("expected a trade, got {other:?}"),
+        })
+        .collect();
+    assert_eq!(
+        trades[..3],
+        [
+            expected_trade(10, dec!(100.0), OrbitTradeSide::Buy),
+            expected_trade(20, dec!(101.0), OrbitTradeSide::Sell),
+            expected_trade(30, dec!(102.0), OrbitTradeSide::Buy),
+        ]
+    );
+
+    let instrument = delta_option(SYMBOL, "20000", SETTLEMENT);
+    let key = OrbitBookKey::from_instrument(&instrument);
+    let mut storage = OrbitOrderbookStorage::new(vec![instrument]).with_trade_tape_len(2);
+    for event in events {
+        let update = storage.process(event).unwrap();
+        assert!(update.changes.is_empty());
+    }
+    assert_eq!(
+        storage.trades(&key).unwrap(),
+        &[
+            expected_trade(20, dec!(101.0), OrbitTradeSide::Sell),
+            expected_trade(30, dec!(102.0), OrbitTradeSide::Buy),
+        ]
+    );
+    assert_eq!(
+        storage.last_trade(&key).map(|trade| trade.price),
+        Some(dec!(102))
+    );
+}
+
+#[tokio::test]
+async fn keeps_the_spot_index_as_reference_price() {
+    let mut script = handshake();
+    script.push(Step::Send(
+        json!({ "type": "v2/spot_price", "s": ".DEXBTUSD", "p": "16850.5" }).to_string(),
+    ));
+    // not an index of the instruments
+    script.push(Step::Send(
+        json!({ "type": "v2/spot_price", "s": ".DEXETHUSD", "p": "1200" }).to_string(),
+    ));
+    script.push(Step::Send(
+        json!({ "type": "v2/spot_price", "s": ".DEXBTUSD", "p": 16851 }).to_string(),
+    ));
+    script.push(Step::Hold);
+    let mock = MockExchange::start(vec![script]).await;
+    let (_client, mut rx) = consume(&mock).await;
+
+    let events = next_events(&mut rx, 2).await;
+    assert_no_event(&mut rx).await;
+    assert_eq!(
+        events[0],
+        OrbitEvent::index_price(
+            OrbitExchange::Delta,
+            ".DEXBTUSD".to_string(),
+            OrbitCurrency::Btc,
+            OrbitIndexPrice {
+                price: dec!(16850.5),
+                timestamp: None
+            }
+        )
+    );
+
+    let mut storage = OrbitOrderbookStorage::new(vec![delta_option(SYMBOL, "20000", SETTLEMENT)]);
+    for event in events {
+        let update = storage.process(event).unwrap();
+        assert_eq!(update.key.contract_type, OrbitContractType::Spot);
+    }
+    let reference = storage
+        .index_price(&OrbitExchange::Delta, &OrbitCurrency::Btc)
+        .unwrap();
+    assert_eq!(reference.index_name, ".DEXBTUSD");
+    assert_eq!(reference.price, dec!(16851));
+    assert_eq!(
+        storage.reference_price(&OrbitExchange::Delta, &OrbitCurrency::Btc),
+        Some(dec!(16851))
+    );
+    let later = reference.updated_at + chrono::Duration::seconds(10);
+    assert!(reference.is_stale(later, chrono::Duration::seconds(5)));
+    assert_eq!(
+        storage.fresh_index_price(&OrbitExchange::Delta, &OrbitCurrency::Btc, later),
+        None
+    );
+}
+
+#[tokio::test]
+async fn follows_the_funding_rate_of_perpetuals() {
+    const PERPETUAL: &str = "BTCUSD";
+    let funding = json!({
+        "type": "funding_rate",
+        "symbol": PERPETUAL,
+        "product_id": 2,
+        "funding_rate": 0.0041,
+        "funding_rate_8h": "0.0082",
+        "next_funding_realization": 1672387200000000i64,
+        "predicted_funding_rate": 0.005,
+        "timestamp": 1672380000000000u64
+    });
+    let mut script = handshake();
+    script.push(Step::Send(funding.to_string()));
+    script.push(Step::Hold);
+    let mut mock = MockExchange::start(vec![script]).await;
+    let instruments = vec![
+        delta_option(SYMBOL, "20000", SETTLEMENT),
+        delta_perpetual(PERPETUAL),
+    ];
+    let (sender, mut rx) = broadcast::channel(100);
+    let client = DeltaClient::new().with_ws_url(&mock.url);
+    client.consume(sender, instruments.clone()).await.unwrap();
+
+    let (_, subscribe) = mock.next_received().await;
+    let subscribe: Value = serde_json::from_str(&subscribe).unwrap();
+    assert_eq!(
+        subscribe["payload"]["channels"][2],
+        json!({ "name": "funding_rate", "symbols": [PERPETUAL] })
+    );
+
+    let events = next_events(&mut rx, 1).await;
+    let expected_funding = OrbitFunding {
+        timestamp: 1672380000000000,
+        // percent on the wire
+        rate_8h: 0.000082,
+        next_funding_at: Some("2022-12-30T08:00:00Z".parse().unwrap()),
+    };
+    assert_eq!(
+        events[0].payload,
+        Some(OrbitEventPayload::Funding(expected_funding.clone()))
+    );
+    assert_eq!(
+        events[0].contract_type,
+        Some(OrbitContractType::PerpetualFuture)
+    );
+
+    let mut storage = OrbitOrderbookStorage::new(instruments);
+    for event in events {
+        let update = storage.process(event).unwrap();
+        assert!(update.changes.is_empty());
+    }
+    let funding = storage
+        .funding(
+            &OrbitExchange::Delta,
+            &OrbitCurrency::Btc,
+            OrbitSettlement::Linear,
+        )
+        .unwrap();
+    assert_eq!(funding, &expected_funding);
+    assert!((funding.annualized() - 0.000082 * 1095.0).abs() < 1e-12);
+}
+
+#[tokio::test]
+async fn subscribes_and_unsubscribes_listing_changes_on_the_live_connection() {
+    const PERPETUAL: &str = "BTCUSD";
+    let mut mock = MockExchange::start(vec![vec![Step::Hold]]).await;
+    let (client, _rx) = consume(&mock).await;
+    // subscribe and enable_heartbeat
+    mock.next_received().await;
+    mock.next_received().await;
+
+    client
+        .subscribe(vec![delta_perpetual(PERPETUAL)])
+        .await
+        .unwrap();
+    let (connection, subscribe) = mock.next_received().await;
+    let subscribe: Value = serde_json::from_str(&subscribe).unwrap();
+    assert_eq!(connection, 0);
+    assert_eq!(
+        subscribe,
+        json!({
+            "type": "subscribe",
+            "payload": {
+                "channels": [
+                    { "name": "l2_orderbook", "symbols": [PERPETUAL] },
+                    { "name": "all_trades", "symbols": [PERPETUAL] },
+                    { "name": "funding_rate", "symbols": [PERPETUAL] }
+                ]
+            }
+        })
+    );
+
+    // the perpetual still needs the spot index
+    client
+        .unsubscribe(vec![delta_option(SYMBOL, "20000", SETTLEMENT)])
+        .await
+        .unwrap();
+    let (_, unsubscribe) = mock.next_received().await;
+    let unsubscribe: Value = serde_json::from_str(&unsubscribe).unwrap();
+    assert_eq!(
+        unsubscribe,
+        json!({
+            "type": "unsubscribe",
+            "payload": {
+                "channels": [
+                    { "name": "l2_orderbook", "symbols": [SYMBOL] },
+                    { "name": "all_trades", "symbols": [SYMBOL] }
+                ]
+            }
+        })
+    );
+    assert_eq!(client.health().streams, 1);
+}
+
+#[tokio::test]
+async fn reconnects_and_resubscribes_after_a_disconnect() {
+    let mut first = handshake();
+    first.push(Step::Send(l2_orderbook(1, "100", "101")));
+    first.push(Step::Disconnect);
+    let mut second = handshake();
+    second.push(Step::Send(l2_orderbook(2, "102", "103")));
+    second.push(Step::Hold);
+    let mut mock = MockExchange::start(vec![first, second]).await;
+    let (client, mut rx) = consume(&mock).await;
+
+    let events = next_events(&mut rx, 2).await;
+    assert_eq!(
+        events,
+        vec![
+            expected_event(1, dec!(100.0), dec!(101.0)),
+            expected_event(2, dec!(102.0), dec!(103.0))
+        ]
+    );
+
+    let received: Vec<(usize, String)> = vec![
+        mock.next_received().await,
+        mock.next_received().await,
+        mock.next_received().await,
+        mock.next_received().await,
+    ];
+    assert_eq!(received.iter().filter(|(c, _)| *c == 1).count(), 2);
+    assert!(received[2].1.contains("subscribe"));
+    assert_eq!(client.health().reconnects, 1);
+}
+
+#[tokio::test]
+async fn dead_letters_unexpected_message_types_and_keeps_streaming() {
+    let unexpected = json!({ "type": "something_new" }).to_string();
+    let mut script = handshake();
+    script.push(Step::Send(unexpected.clone()));
+    script.push(Step::Send(l2_orderbook(3, "100", "101")));
+    script.push(Step::Hold);
+    let mock = MockExchange::start(vec![script]).await;
+    let (dead_tx, mut dead_rx) = mpsc::unbounded_channel::<OrbitDeadLetter>();
+    let client = DeltaClient::new()
+        .with_ws_url(&mock.url)
+        .with_dead_letter_sink(Arc::new(dead_tx));
+    let (client, mut rx) = consume_with(client).await;
+
+    let events = next_events(&mut rx, 1).await;
+    assert_eq!(events, vec![expected_event(3, dec!(100.0), dec!(101.0))]);
+    assert_eq!(dead_rx.recv().await.unwrap().raw, unexpected);
+    let health = client.health();
+    assert_eq!(health.errors, 1);
+    assert_eq!(health.reconnects, 0);
+}
+
+#[tokio::test]
+async fn dead_letters_unparseable_frames_and_keeps_streaming() {
+    let garbage = vec![
+        "not json".to_string(),
+        l2_orderbook(1, "abc", "101"),
+        json!({ "no_type": true }).to_string(),
+    ];
+    let mut script = handshake();
+    script.extend(garbage.iter().cloned().map(Step::Send));
+    script.push(Step::Send(l2_orderbook(2, "100", "101")));
+    script.push(Step::Hold);
+    let mock = MockExchange::start(vec![script]).await;
+    let (dead_tx, mut dead_rx) = mpsc::unbounded_channel::<OrbitDeadLetter>();
+    let client = DeltaClient::new()
+        .with_ws_url(&mock.url)
+        .with_dead_letter_sink(Arc::new(dead_tx));
+    let (client, mut rx) = consume_with(client).await;
+
+    let events = next_events(&mut rx, 1).await;
+    assert_eq!(events, vec![expected_event(2, dec!(100.0), dec!(101.0))]);
+    for raw in garbage {
+        let letter = dead_rx.recv().await.unwrap();
+        assert_eq!(letter.exchange, OrbitExchange::Delta);
+        assert_eq!(letter.raw, raw);
+    }
+    let health = client.health();
+    assert_eq!(health.errors, 3);
+    assert_eq!(health.reconnects, 0);
+    assert!(health.is_healthy());
+}
+
+#[tokio::test]
+async fn keeps_retrying_when_the_exchange_is_unreachable() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    drop(listener);
+    let (client, _rx) = consume_with(DeltaClient::new().with_ws_url(&url)).await;
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let health = client.health();
+    assert_eq!(health.streams, 1);
+    assert_eq!(health.connected, 0);
+    assert!(health.errors >= 2);
+}
@@ -0,0 +1,815 @@
+mod common;
+
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use common::{
+    assert_no_event, deribit_option, deribit_perpetual, next_events, MockExchange, MockRest, Step,
+};
+use data_streamer::config::{DeribitConfig, OrbitBookInterval};
+use data_streamer::error::OrbitDeadLetter;
+use data_streamer::exchanges::deribit::model::{
+    DeribitBookSequencer, DeribitClient, DeribitOrderbook, DeribitOrderbookUpdateType,
+    DeribitSequenceCheck,
+};
+use data_streamer::{
+    expiration_key, OrbitBookKey, OrbitBookSide, OrbitContractType, OrbitCurrency, OrbitEvent,
+    OrbitEventPayload, OrbitExchange, OrbitExchangeConnector, OrbitFunding, OrbitGreeks,
+    OrbitIndexPrice, OrbitOrderbookStorage, OrbitSettlement, OrbitTicker, OrbitTrade,
+    OrbitTradeSide, OrderbookUpdate, OrderbookUpdateLevel, OrderbookUpdateType,
+};
+use rust_decimal_macros::dec;
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, mpsc};
+
+const NAME: &str = "BTC-30DEC22-20000-P";
+const EXPIRATION: i64 = 1672387200000;
+// nothing listens here, snapshot requests fail fast instead of reaching deribit
+const DEAD_REST_URL: &str = "http://127.0.0.1:1/api/v2";
+
+fn book(kind: &str, change_id: i64, prev_change_id: Option<i64>, bids: Value) -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "subscription",
+        "params": {
+            "channel": format!("book.{}.100ms", NAME),
+            "data": {
+                "type": kind,
+                "timestamp": change_id as u64,
+                "instrument_name": NAME,
+                "change_id": change_id,
+                "prev_change_id": prev_change_id,
+                "bids": bids,
+                "asks": []
+            }
+        }
+    })
+    .to_string()
+}
+
+fn expected_event(
+    is_snapshot: bool,
+    timestamp: u64,
+    bids: Vec<OrderbookUpdateLevel>,
+) -> OrbitEvent {
+    let expiration: DateTime<Utc> = DateTime::from_utc(
+        NaiveDateTime::from_timestamp_millis(EXPIRATION).unwrap(),
+        Utc,
+    );
+    OrbitEvent::new(
+        OrbitExchange::Deribit,
+        NAME.to_string(),
+        Some(OrbitCurrency::Btc),
+        Some(OrbitSettlement::Inverse),
+        Some(OrbitContractType::PutOption),
+        Some(expiration_key(expiration)),
+        Some(dec!(20000)),
+        Some(OrbitEventPayload::OrderbookUpdate(OrderbookUpdate {
+            is_snapshot,
+            timestamp,
+            bids,
+            asks: vec![],
+        })),
+    )
+}
+
+async fn consume(mock: &MockExchange) -> (DeribitClient, broadcast::Receiver<OrbitEvent>) {
+    let client = DeribitClient::new()
+        .with_ws_url(&mock.url)
+        .with_rest_url(DEAD_REST_URL);
+    let (sender, rx) = broadcast::channel(100);
+    client
+        .consume(sender, vec![deribit_option(NAME, 20000.0, EXPIRATION)])
+        .await
+        .unwrap();
+    (client, rx)
+}
+
+#[tokio::test]
+async fn subscribes_to_instrument_and_index_channels_and_heartbeat() {
+    let mut mock = MockExchange::start(vec![vec![Step::Hold]]).await;
+    let (_client, _rx) = consume(&mock).await;
+
+    let (_, subscribe) = mock.next_received().await;
+    let subscribe: Value = serde_json::from_str(&subscribe).unwrap();
+    assert_eq!(subscribe["method"], "public/subscribe");
+    assert_eq!(
+        subscribe["params"]["channels"],
+        json!([
+            format!("book.{}.100ms", NAME),
+            format!("ticker.{}.100ms", NAME),
+            format!("trades.{}.100ms", NAME),
+            "deribit_price_index.btc_usd"
+        ])
+    );
+
+    let (_, heartbeat) = mock.next_received().await;
+    let heartbeat: Value = serde_json::from_str(&heartbeat).unwrap();
+    assert_eq!(heartbeat["method"], "public/set_heartbeat");
+}
+
+#[tokio::test]
+async fn authorizes_before_subscribing_raw_tickers_and_trades() {
+    let mut mock = MockExchange::start(vec![vec![Step::Hold]]).await;
+    let config = DeribitConfig {
+        book_interval: OrbitBookInterval::Raw,
+        client_id: Some("id".to_string()),
+        client_secret: Some("secret".to_string()),
+        ..Default::default()
+    };
+    let client = DeribitClient::from_config(config)
+        .with_ws_url(&mock.url)
+        .with_rest_url(DEAD_REST_URL);
+    let (sender, _rx) = broadcast::channel(100);
+    client
+        .consume(sender, vec![deribit_option(NAME, 20000.0, EXPIRATION)])
+        .await
+        .unwrap();
+
+    let (_, auth) = mock.next_received().await;
+    let auth: Value = serde_json::from_str(&auth).unwrap();
+    assert_eq!(auth["method"], "public/auth");
+    assert_eq!(
+        auth["params"],
+        json!({
+            "grant_type": "client_credentials",
+            "client_id": "id",
+            "client_secret": "secret"
+        })
+    );
+    let (_, subscribe) = mock.next_received().await;
+    let subscribe: Value = serde_json::from_str(&subscribe).unwrap();
+    assert_eq!(subscribe["method"], "public/subscribe");
+    assert_eq!(
+        subscribe["params"]["channels"],
+        json!([
+            format!("book.{}.raw", NAME),
+            format!("ticker.{}.raw", NAME),
+            format!("trades.{}.raw", NAME),
+            "deribit_price_index.btc_usd"
+        ])
+    );
+}
+
+#[tokio::test]
+async fn applies_snapshot_then_chained_changes_and_drops_stale_ones() {
+    let script = vec![
+        Step::Receive,
+        Step::Receive,
+        Step::Send(json!({ "jsonrpc": "2.0", "id": 42, "result": [] }).to_string()),
+        Step::Send(book("snapshot", 10, None, json!([["new", 0.05, 10.0]]))),
+        Step::Send(book("change", 11, Some(10), json!([["change", 0.05, 4.0]]))),
+        // already covered by change_id 11
+        Step::Send(book("change", 11, Some(10), json!([["delete", 0.05, 0.0]]))),
+        Step::Send(book("change", 12, Some(11), json!([["delete", 0.05, 0.0]]))),
+        Step::Hold,
+    ];
+    let mock = MockExchange::start(vec![script]).await;
+    let (_client, mut rx) = consume(&mock).await;
+
+    let events = next_events(&mut rx, 3).await;
+    assert_eq!(
+        events,
+        vec![
+            expected_event(
+                true,
+                10,
+                vec![OrderbookUpdateLevel(
+                    OrderbookUpdateType::New,
+                    dec!(0.05),
+                    dec!(10.0)
+                )]
+            ),
+            expected_event(
+                false,
+                11,
+                vec![OrderbookUpdateLevel(
+                    OrderbookUpdateType::Change,
+                    dec!(0.05),
+                    dec!(4.0)
+                )]
+            ),
+            expected_event(
+                false,
+                12,
+                vec![OrderbookUpdateLevel(
+                    OrderbookUpdateType::Delete,
+                    dec!(0.05),
+                    dec!(0.0)
+                )]
+            ),
+        ]
+    );
+    assert_no_event(&mut rx).await;
+}
+
+#[tokio::test]
+async fn normalizes_tickers_and_stores_them_next_to_the_book() {
+    let ticker = json!({
+        "jsonrpc": "2.0",
+        "method": "subscription",
+        "params": {
+            "channel": format!("ticker.{}.100ms", NAME),
+            "data": {
+                "timestamp": 15,
+                "instrument_name": NAME,
+                "state": "open",
+                "mark_price": 0.0125,
+                "mark_iv": 65.5,
+                "bid_iv": 0.0,
+                "ask_iv": 70.0,
+                "greeks": { "delta": -0.31, "gamma": 0.0001, "vega": 9.5, "theta": -21.0, "rho": -2.5 },
+                "open_interest": 120.5,
+                "underlying_price": 16850.0,
+                "underlying_index": "BTC-30DEC22",
+                "index_price": 16840.0,
+                "best_bid_price": 0.0,
+                "best_ask_price": 0.013
+            }
+        }
+    });
+    let script = vec![
+        Step::Receive,
+        Step::Receive,
+        Step::Send(book("snapshot", 10, None, json!([["new", 0.05, 10.0]]))),
+        Step::Send(ticker.to_string()),
+        Step::Hold,
+    ];
+    let mock = MockExchange::start(vec![script]).await;
+    let (_client, mut rx) = consume(&mock).await;
+
+    let events = next_events(&mut rx, 2).await;
+    let expected_ticker = OrbitTicker {
+        timestamp: 15,
+        mark_price: dec!(0.0125),
+        mark_iv: Some(0.655),
+        // deribit's 0 for an empty side
+        bid_iv: None,
+        ask_iv: Some(0.7),
+        greeks: Some(OrbitGreeks {
+            delta: -0.31,
+            gamma: 0.0001,
+            vega: 9.5,
+            theta: -21.0,
+            rho: -2.5,
+        }),
+        open_interest: Some(dec!(120.5)),
+        underlying_price: Some(dec!(16850)),
+        index_price: Some(dec!(16840)),
+    };
+    let mut expected = expected_event(true, 0, vec![]);
+    expected.payload = Some(OrbitEventPayload::Ticker(expected_ticker.clone()));
+    assert_eq!(events[1], expected);
+
+    let instrument = deribit_option(NAME, 20000.0, EXPIRATION);
+    let key = OrbitBookKey::from_instrument(&instrument);
+    let mut storage = OrbitOrderbookStorage::new(vec![instrument]);
+    for event in events {
+        storage.process(event).unwrap();
+    }
+    assert_eq!(storage.ticker(&key), Some(&expected_ticker));
+    // the ticker doesn't touch the levels
+    assert_eq!(
+        storage.best(&key, OrbitBookSide::Bid),
+        Some((dec!(0.05), dec!(10)))
+    );
+}
+
+#[tokio::test]
+async fn takes_the_funding_of_perpetual_tickers() {
+    const PERPETUAL: &str = "BTC-PERPETUAL";
+    let ticker = json!({
+        "jsonrpc": "2.0",
+        "method": "subscription",
+        "params": {
+            "channel": format!("ticker.{}.100ms", PERPETUAL),
+            "data": {
+                "timestamp": 25,
+                "instrument_name": PERPETUAL,
+                "state": "open",
+                "mark_price": 16852.5,
+                "open_interest": 250000000.0,
+                "index_price": 16840.0,
+                "current_funding": 0.00002,
+                "funding_8h": 0.0001,
+                "best_bid_price": 16852.0,
+                "best_ask_price": 16853.0
+            }
+        }
+    });
+    let script = vec![
+        Step::Receive,
+        Step::Receive,
+        Step::Send(ticker.to_string()),
+        Step::Hold,
+    ];
+    let mock = MockExchange::start(vec![script]).await;
+    let client = DeribitClient::new()
+        .with_ws_url(&mock.url)
+        .with_rest_url(DEAD_REST_URL);
+    let (sender, mut rx) = broadcast::channel(100);
+    let instrument = deribit_perpetual(PERPETUAL);
+    client
+        .consume(sender, vec![instrument.clone()])
+        .await
+        .unwrap();
+
+    let events = next_events(&mut rx, 2).await;
+    assert_no_event(&mut rx).await;
+    assert!(matches!(
+        events[0].payload,
+        Some(OrbitEventPayload::Ticker(_))
+    ));
+    let expected_funding = OrbitFunding {
+        timestamp: 25,
+        rate_8h: 0.0001,
+        // accrues continuously
+        next_funding_at: None,
+    };
+    assert_eq!(
+        events[1].payload,
+        Some(OrbitEventPayload::Funding(expected_funding.clone()))
+    );
+
+    let mut storage = OrbitOrderbookStorage::new(vec![instrument]);
+    for event in events {
+        storage.process(event).unwrap();
+    }
+    assert_eq!(
+        storage.funding(
+            &OrbitExchange::Deribit,
+            &OrbitCurrency::Btc,
+            OrbitSettlement::Inverse
+        ),
+        Some(&expected_funding)
+    );
+}
+
+#[tokio::test]
+async fn normalizes_every_print_of_a_trades_notification() {
+    let print = |trade_id: &str, timestamp: u64, direction: &str, iv: f64| {
+        json!({
+            "trade_seq": 1,
+            "trade_id": trade_id,
+            "timestamp": timestamp,
+            "tick_direction": 0,
+            "price": 0.0125,
+            "mark_price": 0.0124,
+            "iv": iv,
+            "index_price": 16840.0,
+            "instrument_name": NAME,
+            "direction": direction,
+            "amount": 1.5
+        })
+    };
+    let trades = json!({
+        "jsonrpc": "2.0",
+        "method": "subscription",
+        "params": {
+            "channel": format!("trades.{}.100ms", NAME),
+            "data": [print("1001", 15, "buy", 65.0), print("1002", 16, "sell", 64.0)]
+        }
+    });
+    let script = vec![
+        Step::Receive,
+        Step::Receive,
+        Step::Send(trades.to_string()),
+        Step::Hold,
+    ];
+    let mock = MockExchange::start(vec![script]).await;
+    let (_client, mut rx) = consume(&mock).await;
+
+    let events = next_events(&mut rx, 2).await;
+    let expected = [
+        ("1001", 15, OrbitTradeSide::Buy, 0.65),
+        ("1002", 16, OrbitTradeSide::Sell, 0.64),
+    ]
+    .into_iter()
+    .map(|(trade_id, timestamp, side, iv)| {
+        let mut event = expected_event(true, 0, vec![]);
+        event.payload = Some(OrbitEventPayload::Trade(OrbitTrade {
+            trade_id: Some(trade_id.to_string()),
+            timestamp,
+            price: dec!(0.0125),
+            size: dec!(1.5),
+            side,
+            iv: Some(iv),
+        }));
+        event
+    })
+    .collect::<Vec<_>>();
+    assert_eq!(events, expected);
+}
+
+#[tokio::test]
+async fn keeps_the_price_index_as_reference_price() {
+    let index = |timestamp: u64, price: f64| {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "subscription",
+            "params": {
+                "channel": "deribit_price_index.btc_usd",
+                "data": { "timestamp": timestamp, "price": price, "index_name": "btc_usd" }
+            }
+        })
+        .to_string()
+    };
+    let script = vec![
+        Step::Receive,
+        Step::Receive,
+        Step::Send(index(15, 16840.0)),
+        Step::Send(index(16, 16842.5)),
+        Step::Hold,
+    ];
+    let mock = MockExchange::start(vec![script]).await;
+    let (_client, mut rx) = consume(&mock).await;
+
+    let events = next_events(&mut rx, 2).await;
+    assert_eq!(
+        events[1],
+        OrbitEvent::index_price(
+            OrbitExchange::Deribit,
+            "btc_usd".to_string(),
+            OrbitCurrency::Btc,
+            OrbitIndexPrice {
+                price: dec!(16842.5),
+                timestamp: Some(16)
+            }
+        )
+    );
+
+    let instruments = vec![deribit_option(NAME, 20000.0, EXPIRATION)];
+    let mut storage = OrbitOrderbookStorage::new(instruments.clone());
+    for event in events.clone() {
+        storage.process(event).unwrap();
+    }
+    let reference = storage
+        .index_price(&OrbitExchange::Deribit, &OrbitCurrency::Btc)
+        .unwrap();
+    assert_eq!(reference.timestamp, Some(16));
+    assert_eq!(
+        storage.reference_price(&OrbitExchange::Deribit, &OrbitCurrency::Btc),
+        Some(dec!(16842.5))
+    );
+
+    // a stale index is still there but no longer the reference, with no perp book
+    // there is nothing to fall back to
+    let mut storage =
+        OrbitOrderbookStorage::new(instruments).with_reference_max_age(Duration::zero());
+    for event in events {
+        storage.process(event).unwrap();
+    }
+    let (_, _, stale) = storage.index_prices(Utc::now()).next().unwrap();
+    assert!(stale);
+    assert_eq!(
+        storage.reference_price(&OrbitExchange::Deribit, &OrbitCurrency::Btc),
+        None
+    );
+}
+
+#[tokio::test]
+async fn subscribes_and_unsubscribes_listing_changes_on_the_live_connection() {
+    const LISTED: &str = "BTC-30DEC22-25000-C";
+    let mut mock = MockExchange::start(vec![vec![Step::Hold]]).await;
+    let (client, _rx) = consume(&mock).await;
+    // subscribe and heartbeat
+    mock.next_received().await;
+    mock.next_received().await;
+
+    let listed = deribit_option(LISTED, 25000.0, EXPIRATION);
+    client.subscribe(vec![listed.clone()]).await.unwrap();
+    let (connection, subscribe) = mock.next_received().await;
+    let subscribe: Value = serde_json::from_str(&subscribe).unwrap();
+    // same connection, the index is already followed
+    assert_eq!(connection, 0);
+    assert_eq!(subscribe["method"], "public/subscribe");
+    assert_eq!(
+        subscribe["params"]["channels"],
+        json!([
+            format!("book.{}.100ms", LISTED),
+            format!("ticker.{}.100ms", LISTED),
+            format!("trades.{}.100ms", LISTED)
+        ])
+    );
+    assert_eq!(client.health().streams, 1);
+
+    let original = deribit_option(NAME, 20000.0, EXPIRATION);
+    client.unsubscribe(vec![original, listed]).await.unwrap();
+    let (_, unsubscribe) = mock.next_received().await;
+    let unsubscribe: Value = serde_json::from_str(&unsubscribe).unwrap();
+    assert_eq!(unsubscribe["method"], "public/unsubscribe");
+    assert_eq!(
+        unsubscribe["params"]["channels"],
+        json!([
+            format!("book.{}.100ms", NAME),
+            format!("ticker.{}.100ms", NAME),
+            format!("trades.{}.100ms", NAME),
+            format!("book.{}.100ms", LISTED),
+            format!("ticker.{}.100ms", LISTED),
+            format!("trades.{}.100ms", LISTED),
+            "deribit_price_index.btc_usd"
+        ])
+    );
+    // nothing left to follow, the stream stops instead of reconnecting
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let health = client.health();
+    assert_eq!((health.streams, health.connected), (0, 0));
+}
+
+#[tokio::test]
+async fn answers_heartbeat_test_requests() {
+    let script = vec![
+        Step::Receive,
+        Step::Receive,
+        Step::Send(
+            json!({ "jsonrpc": "2.0", "method": "heartbeat", "params": { "type": "test_request" } })
+                .to_string(),
+        ),
+        Step::Receive,
+        Step::Hold,
+    ];
+    let mut mock = MockExchange::start(vec![script]).await;
+    let (_client, mut rx) = consume(&mock).await;
+
+    mock.next_received().await;
+    mock.next_received().await;
+    let (_, pong) = mock.next_received().await;
+    let pong: Value = serde_json::from_str(&pong).unwrap();
+    assert_eq!(pong["method"], "public/test");
+    assert_no_event(&mut rx).await;
+}
+
+#[tokio::test]
+async fn reconnects_after_a_disconnect() {
+    let first = vec![
+        Step::Receive,
+        Step::Receive,
+        Step::Send(book("snapshot", 10, None, json!([["new", 0.05, 10.0]]))),
+        Step::Disconnect,
+    ];
+    let second = vec![
+        Step::Receive,
+        Step::Receive,
+        Step::Send(book("snapshot", 20, None, json!([["new", 0.06, 1.0]]))),
+        Step::Hold,
+    ];
+    let mock = MockExchange::start(vec![first, second]).await;
+    let (client, mut rx) = consume(&mock).await;
+
+    let events = next_events(&mut rx, 2).await;
+    assert_eq!(
+        events,
+        vec![
+            expected_event(
+                true,
+                10,
+                vec![OrderbookUpdateLevel(
+                    OrderbookUpdateType::New,
+                    dec!(0.05),
+                    dec!(10.0)
+                )]
+            ),
+            expected_event(
+                true,
+                20,
+                vec![OrderbookUpdateLevel(
+                    OrderbookUpdateType::New,
+                    dec!(0.06),
+                    dec!(1.0)
+                )]
+            ),
+        ]
+    );
+    assert_eq!(client.health().reconnects, 1);
+}
+
+#[tokio::test]
+async fn resyncs_on_a_sequence_gap() {
+    let script = vec![
+        Step::Receive,
+        Step::Receive,
+        Step::Send(book("snapshot", 10, None, json!([["new", 0.05, 10.0]]))),
+        Step::Send(book("change", 13, Some(12), json!([["change", 0.05, 4.0]]))),
+        Step::Receive,
+        Step::Receive,
+        Step::Send(book("snapshot", 20, None, json!([["new", 0.06, 1.0]]))),
+        Step::Hold,
+    ];
+    let mut mock = MockExchange::start(vec![script]).await;
+    let (_client, mut rx) = consume(&mock).await;
+
+    let events = next_events(&mut rx, 3).await;
+    let mut resync = expected_event(true, 0, vec![]);
+    resync.payload = Some(OrbitEventPayload::OrderbookResync);
+    assert_eq!(
+        events,
+        vec![
+            expected_event(
+                true,
+                10,
+                vec![OrderbookUpdateLevel(
+                    OrderbookUpdateType::New,
+                    dec!(0.05),
+                    dec!(10.0)
+                )]
+            ),
+            resync,
+            expected_event(
+                true,
+                20,
+                vec![OrderbookUpdateLevel(
+                    OrderbookUpdateType::New,
+                    dec!(0.06),
+                    dec!(1.0)
+                )]
+            ),
+        ]
+    );
+
+    // the rest snapshot is unreachable so the channel is resubscribed instead
+    mock.next_received().await;
+    mock.next_received().await;
+    let channel = json!([format!("book.{}.100ms", NAME)]);
+    for method in ["public/unsubscribe", "public/subscribe"] {
+        let (_, request) = mock.next_received().await;
+        let request: Value = serde_json::from_str(&request).unwrap();
+        assert_eq!(request["method"], method);
+        assert_eq!(request["params"]["channels"], channel);
+    }
+}
+
+#[tokio::test]
+async fn resyncs_from_a_rest_snapshot_and_continues_its_chain() {
+    let rest = json!({
+        "jsonrpc": "2.0",
+        "result": {
+            "timestamp": 30,
+            "instrument_name": NAME,
+            "change_id": 30,
+            "bids": [[0.06, 2.0]],
+            "asks": []
+        }
+    });
+    let mut rest = MockRest::start(rest.to_string()).await;
+    let script = vec![
+        Step::Receive,
+        Step::Receive,
+        Step::Send(book("snapshot", 10, None, json!([["new", 0.05, 10.0]]))),
+        Step::Send(book("change", 13, Some(12), json!([["change", 0.05, 4.0]]))),
+        // taken before the rest snapshot, which already has it
+        Step::Send(book("change", 29, Some(27), json!([["delete", 0.06, 0.0]]))),
+        Step::Send(book("change", 31, Some(30), json!([["change", 0.06, 3.0]]))),
+        Step::Hold,
+    ];
+    let mut mock = MockExchange::start(vec![script]).await;
+    let client = DeribitClient::new()
+        .with_ws_url(&mock.url)
+        .with_rest_url(&rest.url);
+    let (sender, mut rx) = broadcast::channel(100);
+    client
+        .consume(sender, vec![deribit_option(NAME, 20000.0, EXPIRATION)])
+        .await
+        .unwrap();
+
+    let events = next_events(&mut rx, 4).await;
+    let mut resync = expected_event(true, 0, vec![]);
+    resync.payload = Some(OrbitEventPayload::OrderbookResync);
+    assert_eq!(
+        events,
+        vec![
+            expected_event(
+                true,
+                10,
+                vec![OrderbookUpdateLevel(
+                    OrderbookUpdateType::New,
+                    dec!(0.05),
+                    dec!(10.0)
+                )]
+            ),
+            resync,
+            expected_event(
+                true,
+                30,
+                vec![OrderbookUpdateLevel(
+                    OrderbookUpdateType::New,
+                    dec!(0.06),
+                    dec!(2.0)
+                )]
+            ),
+            expected_event(
+                false,
+                31,
+                vec![OrderbookUpdateLevel(
+                    OrderbookUpdateType::Change,
+                    dec!(0.06),
+                    dec!(3.0)
+                )]
+            ),
+        ]
+    );
+    assert_no_event(&mut rx).await;
+
+    let request = rest.requested.recv().await.unwrap();
+    assert!(request.contains(&format!("/public/get_order_book?instrument_name={}", NAME)));
+    // the snapshot healed the book, nothing is resubscribed
+    mock.next_received().await;
+    mock.next_received().await;
+    assert!(mock.received.try_recv().is_err());
+}
+
+fn sequenced(
+    kind: DeribitOrderbookUpdateType,
+    change_id: i64,
+    prev_change_id: Option<i64>,
+) -> DeribitOrderbook {
+    DeribitOrderbook {
+        asks: vec![],
+        bids: vec![],
+        change_id,
+        instrument_name: NAME.to_string(),
+        prev_change_id,
+        timestamp: change_id as u64,
+        kind,
+    }
+}
+
+#[test]
+fn drops_or_applies_overlapping_deltas_without_a_gap() {
+    let mut sequencer = DeribitBookSequencer::default();
+    let mut check = |kind, change_id, prev_change_id| {
+        sequencer.check(&sequenced(kind, change_id, prev_change_id))
+    };
+    assert_eq!(
+        check(DeribitOrderbookUpdateType::Snapshot, 10, None),
+        DeribitSequenceCheck::Apply
+    );
+    // everything it changed is already in, dropped without a resync
+    assert_eq!(
+        check(DeribitOrderbookUpdateType::Change, 9, Some(7)),
+        DeribitSequenceCheck::Stale
+    );
+    // starts before the last change and ends after it, levels are absolute amounts so
+    // applying the part already in is harmless
+    assert_eq!(
+        check(DeribitOrderbookUpdateType::Change, 12, Some(8)),
+        DeribitSequenceCheck::Apply
+    );
+    // and the chain continues from it
+    assert_eq!(
+        check(DeribitOrderbookUpdateType::Change, 13, Some(12)),
+        DeribitSequenceCheck::Apply
+    );
+    assert_eq!(
+        check(DeribitOrderbookUpdateType::Change, 15, Some(14)),
+        DeribitSequenceCheck::Gap
+    );
+}
+
+#[tokio::test]
+async fn dead_letters_unparseable_frames_and_keeps_streaming() {
+    let garbage = vec![
+        "{\"jsonrpc\": \"2.0\", \"method\": ".to_string(),
+        json!({ "jsonrpc": "2.0", "method": "subscription", "params": { "data": {} } }).to_string(),
+    ];
+    let mut script = vec![Step::Receive, Step::Receive];
+    script.extend(garbage.iter().cloned().map(Step::Send));
+    script.push(Step::Send(book(
+        "snapshot",
+        10,
+        None,
+        json!([["new", 0.05, 10.0]]),
+    )));
+    script.push(Step::Hold);
+    let mock = MockExchange::start(vec![script]).await;
+    let (dead_tx, mut dead_rx) = mpsc::unbounded_channel::<OrbitDeadLetter>();
+    let client = DeribitClient::new()
+        .with_ws_url(&mock.url)
+        .with_rest_url(DEAD_REST_URL)
+        .with_dead_letter_sink(Arc::new(dead_tx));
+    let (sender, mut rx) = broadcast::channel(100);
+    client
+        .consume(sender, vec![deribit_option(NAME, 20000.0, EXPIRATION)])
+        .await
+        .unwrap();
+
+    let events = next_events(&mut rx, 1).await;
+    assert_eq!(
+        events,
+        vec![expected_event(
+            true,
+            10,
+            vec![OrderbookUpdateLevel(
+                OrderbookUpdateType::New,
+                dec!(0.05),
+                dec!(10.0)
+            )]
+        )]
+    );
+    for raw in garbage {
+        let letter = dead_rx.recv().await.unwrap();
+        assert_eq!(letter.exchange, OrbitExchange::Deribit);
+        assert_eq!(letter.raw, raw);
+    }
+    assert_eq!(client.health().errors, 2);
+    assert_eq!(client.health().reconnects, 0);
+}
@@ -0,0 +1,255 @@
+mod common;
+
+use common::{delta_option, delta_perpetual, deribit_option, deribit_perpetual};
+use data_streamer::exchanges::delta::model::DeltaProduct;
+use data_streamer::exchanges::deribit::model::DeribitInstrument;
+use data_streamer::{
+    OrbitContractSpecs, OrbitCurrency, OrbitEvent, OrbitEventPayload, OrbitInstrument,
+    OrbitSettlement, OrbitSizeUnit, OrderbookUpdate, OrderbookUpdateLevel, OrderbookUpdateType,
+};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+fn settlement(instrument: &OrbitInstrument) -> (&OrbitCurrency, &OrbitCurrency, OrbitSettlement) {
+    (
+        instrument.base(),
+        instrument.quote(),
+        instrument.settlement(),
+    )
+}
+
+#[test]
+fn tells_inverse_deribit_contracts_from_linear_ones() {
+    let option = deribit_option("BTC-30DEC22-20000-P", 20000.0, 1672387200000);
+    assert_eq!(
+        settlement(&option),
+        (
+            &OrbitCurrency::Btc,
+            &OrbitCurrency::Btc,
+            OrbitSettlement::Inverse
+        )
+    );
+    // priced in USD but margined in BTC
+    let perpetual = deribit_perpetual("BTC-PERPETUAL");
+    assert_eq!(
+        settlement(&perpetual),
+        (
+            &OrbitCurrency::Btc,
+            &OrbitCurrency::Usd,
+            OrbitSettlement::Inverse
+        )
+    );
+
+    let usdc_perpetual: DeribitInstrument = serde_json::from_value(serde_json::json!({
+        "base_currency": "BTC",
+        "counter_currency": "USDC",
+        "creation_timestamp": 0,
+        "expiration_timestamp": 32503680000000i64,
+        "future_type": "linear",
+        "instrument_id": 3,
+        "instrument_name": "BTC_USDC-PERPETUAL",
+        "is_active": true,
+        "kind": "future",
+        "option_type": null,
+        "price_index": "btc_usdc",
+        "quote_currency": "USDC",
+        "settlement_period": "perpetual",
+        "strike": null
+    }))
+    .unwrap();
+    assert_eq!(
+        settlement(&OrbitInstrument::from(&usdc_perpetual)),
+        (
+            &OrbitCurrency::Btc,
+            &OrbitCurrency::Usdc,
+            OrbitSettlement::Linear
+        )
+    );
+}
+
+#[test]
+fn tells_inverse_delta_contracts_from_linear_ones() {
+    let option = delta_option("C-BTC-20000-301222", "20000", "2022-12-30T12:00:00Z");
+    assert_eq!(
+        settlement(&option),
+        (
+            &OrbitCurrency::Btc,
+            &OrbitCurrency::Usdt,
+            OrbitSettlement::Linear
+        )
+    );
+    let perpetual = delta_perpetual("BTCUSDT");
+    assert_eq!(perpetual.settlement(), OrbitSettlement::Linear);
+
+    let inverse: DeltaProduct = serde_json::from_value(serde_json::json!({
+        "id": 3,
+        "symbol": "BTCUSD",
+        "strike_price": null,
+        "contract_type": "perpetual_futures",
+        "settlement_time": null,
+        "launch_time": null,
+        "underlying_asset": { "symbol": "BTC" },
+        "quoting_asset": { "symbol": "USD" },
+        "settling_asset": { "symbol": "BTC" },
+        "spot_index": { "symbol": ".DEXBTUSD" }
+    }))
+    .unwrap();
+    assert_eq!(
+        settlement(&OrbitInstrument::from(&inverse)),
+        (
+            &OrbitCurrency::Btc,
+            &OrbitCurrency::Usd,
+            OrbitSettlement::Inverse
+        )
+    );
+}
+
+#[test]
+fn takes_dollars_and_stablecoins_at_par() {
+    for currency in [OrbitCurrency::Usd, OrbitCurrency::Usdt, OrbitCurrency::Usdc] {
+        assert!(currency.is_usd());
+    }
+    for currency in [OrbitCurrency::Btc, OrbitCurrency::Unimplemented] {
+        assert!(!currency.is_usd());
+    }
+}
+
+fn book_sizes(instrument: &OrbitInstrument, levels: &[(Decimal, Decimal)]) -> Vec<Decimal> {
+    let update = OrderbookUpdate {
+        is_snapshot: true,
+        timestamp: 1,
+        bids: levels
+            .iter()
+            .map(|(price, size)| OrderbookUpdateLevel(OrderbookUpdateType::New, *price, *size))
+            .collect(),
+        asks: vec![],
+    };
+    let event = OrbitEvent::for_instrument(
+        instrument.exchange().clone(),
+        instrument.symbol().to_string(),
+        Some(instrument),
+        OrbitEventPayload::OrderbookUpdate(update),
+    );
+    match event.payload {
+        Some(OrbitEventPayload::OrderbookUpdate(update)) => {
+            update.bids.iter().map(|level| level.2).collect()
+        }
+        payload => panic!("unexpected payload {:?}", payload),
+    }
+}
+
+#[test]
+fn sizes_delta_contracts_in_the_underlying() {
+    let option: DeltaProduct = serde_json::from_value(serde_json::json!({
+        "id": 4,
+        "symbol": "C-BTC-20000-301222",
+        "strike_price": "20000",
+        "contract_type": "call_options",
+        "settlement_time": "2022-12-30T12:00:00Z",
+        "launch_time": null,
+        "underlying_asset": { "symbol": "BTC" },
+        "quoting_asset": { "symbol": "USDT" },
+        "settling_asset": { "symbol": "USDT" },
+        "contract_value": "0.001",
+        "tick_size": "0.1",
+        "maker_commission_rate": "0.0003",
+        "taker_commission_rate": "0.0005",
+        "spot_index": { "symbol": ".DEXBTUSD" }
+    }))
+    .unwrap();
+    let option = OrbitInstrument::from(&option);
+    assert_eq!(
+        option.specs(),
+        &OrbitContractSpecs {
+            size_unit: OrbitSizeUnit::Contracts(dec!(0.001)),
+            contract_size: Some(dec!(0.001)),
+            tick_size: Some(dec!(0.1)),
+            min_size: Some(dec!(1)),
+            maker_fee: Some(0.0003),
+            taker_fee: Some(0.0005),
+            premium_fee_cap: Some(0.1),
+        }
+    );
+    assert_eq!(
+        book_sizes(&option, &[(dec!(150), dec!(250))]),
+        vec![dec!(0.25)]
+    );
+
+    // listings without a contract value are taken as they come
+    let untold = delta_option("C-BTC-20000-301222", "20000", "2022-12-30T12:00:00Z");
+    assert_eq!(
+        book_sizes(&untold, &[(dec!(150), dec!(250))]),
+        vec![dec!(250)]
+    );
+}
+
+#[test]
+fn sizes_deribit_inverse_futures_in_the_underlying() {
+    let perpetual: DeribitInstrument = serde_json::from_value(serde_json::json!({
+        "base_currency": "BTC",
+        "contract_size": 10.0,
+        "counter_currency": "USD",
+        "creation_timestamp": 0,
+        "expiration_timestamp": 32503680000000i64,
+        "future_type": "reversed",
+        "instrument_id": 2,
+        "instrument_name": "BTC-PERPETUAL",
+        "is_active": true,
+        "kind": "future",
+        "maker_commission": 0.0,
+        "min_trade_amount": 10.0,
+        "option_type": null,
+        "price_index": "btc_usd",
+        "quote_currency": "USD",
+        "settlement_period": "perpetual",
+        "strike": null,
+        "taker_commission": 0.0005,
+        "tick_size": 0.5
+    }))
+    .unwrap();
+    let perpetual = OrbitInstrument::from(&perpetual);
+    assert_eq!(
+        perpetual.specs().size_unit,
+        OrbitSizeUnit::UsdContracts(dec!(1))
+    );
+    assert_eq!(perpetual.specs().contract_size, Some(dec!(10)));
+    assert_eq!(perpetual.specs().min_size, Some(dec!(10)));
+    assert_eq!(perpetual.specs().taker_fee, Some(0.0005));
+    assert_eq!(perpetual.specs().premium_fee_cap, None);
+    // USD amounts, and deletes stay empty
+    assert_eq!(
+        book_sizes(
+            &perpetual,
+            &[(dec!(20000), dec!(50000)), (dec!(19999.5), dec!(0))]
+        ),
+        vec![dec!(2.5), dec!(0)]
+    );
+
+    // options already trade in the underlying
+    let option = deribit_option("BTC-30DEC22-20000-P", 20000.0, 1672387200000);
+    assert_eq!(option.specs().size_unit, OrbitSizeUnit::Underlying);
+    assert_eq!(option.specs().premium_fee_cap, Some(0.125));
+    assert_eq!(
+        book_sizes(&option, &[(dec!(0.05), dec!(1.5))]),
+        vec![dec!(1.5)]
+    );
+}
+
+#[test]
+fn keeps_fractional_strikes_exact() {
+    let deribit = deribit_option("SOL_USDC-30DEC22-22d5-C", 22.5, 1672387200000);
+    assert_eq!(deribit.strike(), Some(dec!(22.5)));
+
+    // listed with trailing zeros, keyed and shown like the Deribit one
+    let delta = delta_option("C-SOL-22.50-301222", "22.50", "2022-12-30T12:00:00Z");
+    assert_eq!(delta.strike(), deribit.strike());
+    assert_eq!(delta.strike().unwrap().to_string(), "22.5");
+}
+
+#[test]
+fn leaves_unreadable_delta_strikes_and_expiries_out() {
+    let option = delta_option("C-BTC-20000-301222", "20k", "30 Dec 2022");
+    assert_eq!(option.symbol(), "C-BTC-20000-301222");
+    assert_eq!(option.strike(), None);
+    assert_eq!(option.expiration_datetime(), None);
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use chrono::{DateTime, Utc};
+use common::deribit_option;
+use data_streamer::lifecycle::OrbitListingDiff;
+use data_streamer::{
+    expiration_key, OrbitBookKey, OrbitBookSide, OrbitContractType, OrbitCurrency, OrbitEvent,
+    OrbitEventPayload, OrbitExchange, OrbitInstrument, OrbitLifecycle, OrbitOrderbookStorage,
+    OrbitSettlement, OrderbookUpdate, OrderbookUpdateLevel, OrderbookUpdateType,
+};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+// 2022-12-30 08:00 and 2022-12-29 08:00 UTC
+const EXPIRATION: i64 = 1672387200000;
+const EARLIER_EXPIRATION: i64 = 1672300800000;
+
+fn symbols(instruments: &[OrbitInstrument]) -> Vec<&str> {
+    instruments.iter().map(|x| x.symbol()).collect()
+}
+
+fn snapshot(instrument: &OrbitInstrument, bid: Decimal) -> OrbitEvent {
+    OrbitEvent::for_instrument(
+        OrbitExchange::Deribit,
+        instrument.symbol().to_string(),
+        Some(instrument),
+        OrbitEventPayload::OrderbookUpdate(OrderbookUpdate {
+            is_snapshot: true,
+            timestamp: 1,
+            bids: vec![OrderbookUpdateLevel(OrderbookUpdateType::New, bid, dec!(1))],
+            asks: vec![],
+        }),
+    )
+}
+
+#[test]
+fn diffs_listings_into_listed_delisted_and_expired() {
+    let now: DateTime<Utc> = "2022-12-29T09:00:00Z".parse().unwrap();
+    let kept = deribit_option("BTC-30DEC22-20000-P", 20000.0, EXPIRATION);
+    let delisted = deribit_option("BTC-30DEC22-25000-P", 25000.0, EXPIRATION);
+    // settled an hour ago but still in the listings
+    let expired = deribit_option("BTC-29DEC22-20000-C", 20000.0, EARLIER_EXPIRATION);
+    let listed = deribit_option("BTC-30DEC22-30000-C", 30000.0, EXPIRATION);
+    let listed_expired = deribit_option("BTC-29DEC22-30000-C", 30000.0, EARLIER_EXPIRATION);
+
+    let mut current = vec![kept.clone(), delisted, expired.clone()];
+    let latest = vec![kept, expired, listed, listed_expired];
+    let diff = OrbitListingDiff::new(&current, &latest, now);
+    assert_eq!(symbols(&diff.listed), vec!["BTC-30DEC22-30000-C"]);
+    assert_eq!(symbols(&diff.delisted), vec!["BTC-30DEC22-25000-P"]);
+    assert_eq!(symbols(&diff.expired), vec!["BTC-29DEC22-20000-C"]);
+
+    let lifecycles: Vec<_> = diff
+        .listed_events()
+        .into_iter()
+        .chain(diff.removed_events())
+        .map(|event| match event.payload {
+            Some(OrbitEventPayload::Lifecycle(lifecycle, instrument)) => {
+                assert_eq!(instrument.symbol(), event.symbol);
+                (event.symbol, lifecycle)
+            }
+            payload => panic!("not a lifecycle event {payload:?}"),
+        })
+        .collect();
+    assert_eq!(
+        lifecycles,
+        vec![
+            ("BTC-30DEC22-30000-C".to_string(), OrbitLifecycle::Listed),
+            ("BTC-30DEC22-25000-P".to_string(), OrbitLifecycle::Delisted),
+            ("BTC-29DEC22-20000-C".to_string(), OrbitLifecycle::Expired),
+        ]
+    );
+
+    diff.apply(&mut current);
+    assert_eq!(
+        symbols(&current),
+        vec!["BTC-30DEC22-20000-P", "BTC-30DEC22-30000-C"]
+    );
+    assert!(OrbitListingDiff::new(&current, &latest, now).is_empty());
+}
+
+#[test]
+fn storage_adds_and_drops_books_on_lifecycle_events() {
+    let call = deribit_option("BTC-30DEC22-20000-C", 20000.0, EXPIRATION);
+    let put = deribit_option("BTC-30DEC22-20000-P", 20000.0, EXPIRATION);
+    let call_key = OrbitBookKey::from_instrument(&call);
+    let put_key = OrbitBookKey::from_instrument(&put);
+    let mut storage = OrbitOrderbookStorage::new(vec![call.clone(), put.clone()]);
+    storage.process(snapshot(&call, dec!(0.05))).unwrap();
+    storage.process(snapshot(&put, dec!(0.02))).unwrap();
+
+    let delisted = OrbitListingDiff {
+        delisted: vec![put.clone()],
+        ..Default::default()
+    };
+    for event in delisted.removed_events() {
+        let update = storage.process(event).unwrap();
+        assert_eq!(update.key, put_key);
+        assert!(update.changes.is_empty());
+    }
+    // the call of the strike keeps its book
+    assert!(!storage.is_listed(&put_key));
+    assert_eq!(storage.best(&put_key, OrbitBookSide::Bid), None);
+    assert_eq!(
+        storage.best(&call_key, OrbitBookSide::Bid),
+        Some((dec!(0.05), dec!(1)))
+    );
+    assert!(storage.process(snapshot(&put, dec!(0.02))).is_ok());
+
+    let expired = OrbitListingDiff {
+        expired: vec![call.clone()],
+        ..Default::default()
+    };
+    for event in expired.removed_events() {
+        storage.process(event).unwrap();
+    }
+    // the last book of the currency takes the whole entry along
+    assert!(storage.book(&call_key).is_none());
+    assert!(storage.book(&put_key).is_none());
+    assert!(storage.storage.is_empty());
+    assert!(storage.process(snapshot(&call, dec!(0.05))).is_err());
+
+    let expiration: DateTime<Utc> = "2022-12-30T08:00:00Z".parse().unwrap();
+    let future_key = OrbitBookKey::future(
+        OrbitExchange::Deribit,
+        OrbitCurrency::Btc,
+        OrbitSettlement::Inverse,
+        expiration_key(expiration),
+    );
+    let future = OrbitInstrument::new(
+        OrbitExchange::Deribit,
+        "BTC-30DEC22".to_string(),
+        OrbitCurrency::Btc,
+        OrbitCurrency::Usd,
+        OrbitContractType::Future,
+    )
+    .with_settlement(OrbitSettlement::Inverse)
+    .with_expiration(expiration);
+    let listed = OrbitListingDiff {
+        listed: vec![future],
+        ..Default::default()
+    };
+    for event in listed.listed_events() {
+        storage.process(event).unwrap();
+    }
+    assert!(storage.is_listed(&future_key));
+    assert!(storage.book(&future_key).is_some());
+    // listing it again keeps what's there
+    assert!(storage.add_instrument(&call));
+    assert!(!storage.add_instrument(&call));
+    assert!(storage.remove_instrument(&call));
+    assert!(!storage.remove_instrument(&call));
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use common::{delta_option, delta_perpetual, deribit_option, deribit_perpetual};
+use data_streamer::matching::{
+    CanonicalContractKey, OrbitMatchReport, OrbitMatchRules, OrbitMismatch,
+};
+use data_streamer::{OrbitExchange, OrbitSettlement};
+use rust_decimal_macros::dec;
+
+// 2022-12-30 and 2022-12-31 08:00 UTC
+const EXPIRATION: i64 = 1672387200000;
+const NEXT_EXPIRATION: i64 = 1672473600000;
+
+const EXCHANGES: [OrbitExchange; 2] = [OrbitExchange::Deribit, OrbitExchange::Delta];
+
+fn symbols(report: &OrbitMatchReport) -> Vec<String> {
+    let mut symbols: Vec<String> = report
+        .instruments()
+        .iter()
+        .map(|x| x.symbol().to_string())
+        .collect();
+    symbols.sort();
+    symbols
+}
+
+#[test]
+fn keys_listings_by_day_of_expiry() {
+    let deribit = deribit_option("BTC-30DEC22-20000-P", 20000.0, EXPIRATION);
+    let delta = delta_option("P-BTC-20000-301222", "20000", "2022-12-30T12:00:00Z");
+    let deribit_key = CanonicalContractKey::from_instrument(&deribit);
+    let delta_key = CanonicalContractKey::from_instrument(&delta);
+    assert_eq!(deribit_key.expiry, delta_key.expiry);
+    assert_eq!(deribit_key.strike, Some(dec!(20000)));
+    assert_eq!(deribit_key.settlement, Some(OrbitSettlement::Inverse));
+    assert_eq!(delta_key.settlement, Some(OrbitSettlement::Linear));
+
+    let rules = OrbitMatchRules::default();
+    assert_eq!(rules.identity(&deribit_key), rules.identity(&delta_key));
+    // perps are keyed without the year 3000 expiration deribit gives them
+    assert_eq!(
+        rules.identity(&CanonicalContractKey::from_instrument(&deribit_perpetual(
+            "BTC-PERPETUAL"
+        ))),
+        rules.identity(&CanonicalContractKey::from_instrument(&delta_perpetual(
+            "BTCUSDT"
+        )))
+    );
+}
+
+#[test]
+fn matches_listings_of_every_exchange_and_reports_near_misses() {
+    let instruments = vec![
+        deribit_option("BTC-30DEC22-20000-P", 20000.0, EXPIRATION),
+        deribit_option("BTC-30DEC22-25000-P", 25000.0, EXPIRATION),
+        deribit_perpetual("BTC-PERPETUAL"),
+        delta_option("P-BTC-20000-301222", "20000", "2022-12-30T12:00:00Z"),
+        // a day later than deribit's
+        delta_option("P-BTC-25000-311222", "25000", "2022-12-31T12:00:00Z"),
+        delta_perpetual("BTCUSDT"),
+    ];
+    let report = OrbitMatchReport::new(&instruments, &EXCHANGES, &OrbitMatchRules::default());
+    assert_eq!(
+        symbols(&report),
+        vec![
+            "BTC-30DEC22-20000-P",
+            "BTC-PERPETUAL",
+            "BTCUSDT",
+            "P-BTC-20000-301222"
+        ]
+    );
+    assert_eq!(report.near_misses.len(), 1);
+    let near_miss = &report.near_misses[0];
+    assert_eq!(near_miss.first.symbol(), "BTC-30DEC22-25000-P");
+    assert_eq!(near_miss.second.symbol(), "P-BTC-25000-311222");
+    assert_eq!(near_miss.mismatch, OrbitMismatch::Expiry(1));
+
+    // matched ones aren't reported, nor those further apart than the rules allow
+    let instruments = vec![
+        deribit_option("BTC-30DEC22-20000-P", 20000.0, EXPIRATION),
+        deribit_option("BTC-31DEC22-20000-P", 20000.0, NEXT_EXPIRATION),
+        delta_option("P-BTC-20000-301222", "20000", "2022-12-30T12:00:00Z"),
+        delta_option("P-BTC-20000-020123", "20000", "2023-01-02T12:00:00Z"),
+    ];
+    let report = OrbitMatchReport::new(&instruments, &EXCHANGES, &OrbitMatchRules::default());
+    assert_eq!(report.matched.len(), 1);
+    assert_eq!(
+        report
+            .near_misses
+            .iter()
+            .map(|x| (x.first.symbol(), x.second.symbol(), x.mismatch))
+            .collect::<Vec<_>>(),
+        vec![(
+            "BTC-31DEC22-20000-P",
+            "P-BTC-20000-301222",
+            OrbitMismatch::Expiry(-1)
+        )]
+    );
+}
+
+#[test]
+fn keeps_settlements_apart_when_the_rules_say_so() {
+    let instruments = vec![
+        deribit_option("BTC-30DEC22-20000-P", 20000.0, EXPIRATION),
+        delta_option("P-BTC-20000-301222", "20000", "2022-12-30T12:00:00Z"),
+    ];
+    let rules = OrbitMatchRules {
+        same_settlement: true,
+        ..Default::default()
+    };
+    let report = OrbitMatchReport::new(&instruments, &EXCHANGES, &rules);
+    assert!(report.matched.is_empty());
+    assert_eq!(report.near_misses.len(), 1);
+    assert_eq!(report.near_misses[0].mismatch, OrbitMismatch::Settlement);
+    assert_eq!(
+        report.near_misses[0].to_string(),
+        "Deribit BTC-30DEC22-20000-P and Delta P-BTC-20000-301222: settled differently"
+    );
+}
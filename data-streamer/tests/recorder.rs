@@ -0,0 +1,152 @@
+mod common;
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use common::{delta_option, next_events, MockExchange, Step};
+use data_streamer::exchanges::delta::model::DeltaClient;
+use data_streamer::recorder::{
+    partition_dir, read_frames, recording_files, OrbitRawFrame, OrbitRecorder, OrbitRecorderConfig,
+};
+use data_streamer::{OrbitExchange, OrbitExchangeConnector};
+use serde_json::json;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> Self {
+        Self(std::env::temp_dir().join(format!("orbit-recorder-{}", Uuid::new_v4())))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn read_all(root: &Path) -> Vec<OrbitRawFrame> {
+    recording_files(root)
+        .unwrap()
+        .iter()
+        .flat_map(|path| read_frames(path).unwrap().map(Result::unwrap))
+        .collect()
+}
+
+#[test]
+fn partitions_by_day_and_exchange() {
+    let dir = TempDir::new();
+    let recorder = OrbitRecorder::start(OrbitRecorderConfig::new(&dir.0)).unwrap();
+    let connection_id = Uuid::new_v4();
+    let before = Utc::now().timestamp_nanos();
+    recorder.record(
+        OrbitExchange::Delta,
+        connection_id,
+        r#"{"type":"heartbeat"}"#,
+    );
+    recorder.record(
+        OrbitExchange::Deribit,
+        connection_id,
+        r#"{"method":"heartbeat"}"#,
+    );
+    recorder.record(OrbitExchange::Delta, connection_id, "not even json");
+    recorder.flush().unwrap();
+
+    let today = Utc::now().date_naive();
+    let files = recording_files(&dir.0).unwrap();
+    assert_eq!(
+        files,
+        vec![
+            partition_dir(&dir.0, today, &OrbitExchange::Delta).join("00000.ndjson.gz"),
+            partition_dir(&dir.0, today, &OrbitExchange::Deribit).join("00000.ndjson.gz"),
+        ]
+    );
+
+    let frames = read_all(&dir.0);
+    let raw: Vec<_> = frames.iter().map(|f| f.raw.as_str()).collect();
+    assert_eq!(
+        raw,
+        vec![
+            r#"{"type":"heartbeat"}"#,
+            "not even json",
+            r#"{"method":"heartbeat"}"#
+        ]
+    );
+    assert!(frames.iter().all(|f| f.connection_id == connection_id));
+    assert!(frames[0].received_at >= before);
+    assert!(frames[1].received_at >= frames[0].received_at);
+}
+
+#[test]
+fn rotates_on_size_and_keeps_numbering_across_restarts() {
+    let dir = TempDir::new();
+    let mut config = OrbitRecorderConfig::new(&dir.0);
+    config.max_file_bytes = 1;
+    let recorder = OrbitRecorder::start(config.clone()).unwrap();
+    for i in 0..3 {
+        recorder.record(OrbitExchange::Deribit, Uuid::nil(), &i.to_string());
+    }
+    recorder.flush().unwrap();
+    assert_eq!(recording_files(&dir.0).unwrap().len(), 3);
+
+    let restarted = OrbitRecorder::start(config).unwrap();
+    restarted.record(OrbitExchange::Deribit, Uuid::nil(), "3");
+    restarted.flush().unwrap();
+    let files = recording_files(&dir.0).unwrap();
+    assert_eq!(files.len(), 4);
+    assert!(files[3].ends_with("00003.ndjson.gz"));
+    let raw: Vec<_> = read_all(&dir.0).into_iter().map(|f| f.raw).collect();
+    assert_eq!(raw, vec!["0", "1", "2", "3"]);
+}
+
+#[tokio::test]
+async fn records_every_frame_of_a_stream() {
+    let frames = vec![
+        json!({ "type": "subscriptions", "channels": [] }).to_string(),
+        json!({
+            "buy": [{ "depth": "1", "limit_price": "100", "size": 1 }],
+            "sell": [],
+            "symbol": "C-BTC-20000-301222",
+            "type": "l2_orderbook",
+            "timestamp": 1
+        })
+        .to_string(),
+        "garbage".to_string(),
+    ];
+    let mut script = vec![Step::Receive, Step::Receive];
+    script.extend(frames.iter().cloned().map(Step::Send));
+    script.push(Step::Hold);
+    let mock = MockExchange::start(vec![script]).await;
+
+    let dir = TempDir::new();
+    let recorder = OrbitRecorder::start(OrbitRecorderConfig::new(&dir.0)).unwrap();
+    let client = DeltaClient::new()
+        .with_ws_url(&mock.url)
+        .with_recorder(recorder.clone());
+    let (sender, mut rx) = broadcast::channel(100);
+    client
+        .consume(
+            sender,
+            vec![delta_option(
+                "C-BTC-20000-301222",
+                "20000",
+                "2022-12-30T12:00:00Z",
+            )],
+        )
+        .await
+        .unwrap();
+    next_events(&mut rx, 1).await;
+    // the garbage frame comes after the book, give the stream a moment to see it
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    recorder.flush().unwrap();
+
+    let recorded = read_all(&dir.0);
+    let raw: Vec<_> = recorded.iter().map(|f| f.raw.clone()).collect();
+    assert_eq!(raw, frames);
+    assert!(recorded.iter().all(|f| f.exchange == OrbitExchange::Delta));
+    let connections: HashSet<_> = recorded.iter().map(|f| f.connection_id).collect();
+    assert_eq!(connections.len(), 1);
+}
@@ -0,0 +1,318 @@
+mod common;
+
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use chrono::NaiveDate;
+use common::{delta_option, deribit_option};
+use data_streamer::recorder::{partition_dir, OrbitRawFrame};
+use data_streamer::replay::{OrbitReplay, OrbitReplaySpeed};
+use data_streamer::{
+    OrbitBookKey, OrbitEventPayload, OrbitExchange, OrbitInstrument, OrbitOrderbookStorage,
+};
+use flate2::{write::GzEncoder, Compression};
+use rust_decimal_macros::dec;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+const DELTA_SYMBOL: &str = "C-BTC-20000-301222";
+const DERIBIT_NAME: &str = "BTC-30DEC22-20000-C";
+const MS: i64 = 1_000_000;
+
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> Self {
+        Self(std::env::temp_dir().join(format!("orbit-replay-{}", Uuid::new_v4())))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn write_recording(
+    root: &Path,
+    exchange: OrbitExchange,
+    file: &str,
+    frames: &[(i64, Uuid, String)],
+) {
+    let date = NaiveDate::from_ymd_opt(2022, 12, 1).unwrap();
+    let dir = partition_dir(root, date, &exchange);
+    fs::create_dir_all(&dir).unwrap();
+    let mut encoder = GzEncoder::new(File::create(dir.join(file)).unwrap(), Compression::fast());
+    for (received_at, connection_id, raw) in frames {
+        let frame = OrbitRawFrame {
+            received_at: *received_at,
+            exchange: exchange.clone(),
+            connection_id: *connection_id,
+            raw: raw.clone(),
+        };
+        serde_json::to_writer(&mut encoder, &frame).unwrap();
+        encoder.write_all(b"\n").unwrap();
+    }
+    encoder.finish().unwrap();
+}
+
+fn delta_book(timestamp: u64, bid: &str, ask: &str) -> String {
+    json!({
+        "buy": [{ "depth": "1", "limit_price": bid, "size": 1 }],
+        "sell": [{ "depth": "1", "limit_price": ask, "size": 1 }],
+        "symbol": DELTA_SYMBOL,
+        "type": "l2_orderbook",
+        "timestamp": timestamp
+    })
+    .to_string()
+}
+
+fn deribit_book(kind: &str, change_id: i64, prev_change_id: Option<i64>, bids: Value) -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "subscription",
+        "params": {
+            "channel": format!("book.{}.100ms", DERIBIT_NAME),
+            "data": {
+                "type": kind,
+                "timestamp": change_id,
+                "instrument_name": DERIBIT_NAME,
+                "change_id": change_id,
+                "prev_change_id": prev_change_id,
+                "bids": bids,
+                "asks": [["new", 0.1, 1.0]]
+            }
+        }
+    })
+    .to_string()
+}
+
+fn instruments() -> Vec<OrbitInstrument> {
+    vec![
+        delta_option(DELTA_SYMBOL, "20000", "2022-12-30T08:00:00Z"),
+        deribit_option(DERIBIT_NAME, 20000.0, 1672387200000),
+    ]
+}
+
+// deribit: snapshot, change, a gap, a change that must wait, then a reconnect snapshot
+// delta: two snapshots around a frame that doesn't parse, split over two files
+fn record_session(root: &Path) {
+    let first = Uuid::new_v4();
+    let second = Uuid::new_v4();
+    write_recording(
+        root,
+        OrbitExchange::Deribit,
+        "00000.ndjson.gz",
+        &[
+            (
+                10 * MS,
+                first,
+                json!({ "jsonrpc": "2.0", "id": 42, "result": [] }).to_string(),
+            ),
+            (
+                20 * MS,
+                first,
+                deribit_book("snapshot", 1, None, json!([["new", 0.05, 10.0]])),
+            ),
+            (
+                40 * MS,
+                first,
+                deribit_book("change", 2, Some(1), json!([["change", 0.05, 4.0]])),
+            ),
+            (
+                60 * MS,
+                first,
+                deribit_book("change", 5, Some(4), json!([["change", 0.05, 3.0]])),
+            ),
+            (
+                70 * MS,
+                first,
+                deribit_book("change", 6, Some(5), json!([["change", 0.05, 2.0]])),
+            ),
+            (
+                90 * MS,
+                second,
+                deribit_book("snapshot", 7, None, json!([["new", 0.06, 1.0]])),
+            ),
+        ],
+    );
+    let connection = Uuid::new_v4();
+    write_recording(
+        root,
+        OrbitExchange::Delta,
+        "00000.ndjson.gz",
+        &[
+            (30 * MS, connection, delta_book(1, "100", "110")),
+            (50 * MS, connection, "{ broken".to_string()),
+        ],
+    );
+    write_recording(
+        root,
+        OrbitExchange::Delta,
+        "00001.ndjson.gz",
+        &[(80 * MS, connection, delta_book(2, "101", "109"))],
+    );
+}
+
+#[test]
+fn replays_recorded_frames_in_receive_order() {
+    let dir = TempDir::new();
+    record_session(&dir.0);
+    let replay = OrbitReplay::from_dir(&dir.0, instruments()).unwrap();
+
+    let events: Vec<_> = replay.events().collect();
+    let summary: Vec<_> = events
+        .iter()
+        .map(|event| {
+            let kind = match &event.payload {
+                Some(OrbitEventPayload::OrderbookUpdate(update)) => {
+                    format!(
+                        "update {} snapshot={}",
+                        update.timestamp, update.is_snapshot
+                    )
+                }
+                Some(OrbitEventPayload::OrderbookResync) => "resync".to_string(),
+                Some(other) => format!("{other:?}"),
+                None => "none".to_string(),
+            };
+            (event.exchange.clone(), kind)
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (OrbitExchange::Deribit, "update 1 snapshot=true".to_string()),
+            (OrbitExchange::Delta, "update 1 snapshot=true".to_string()),
+            (
+                OrbitExchange::Deribit,
+                "update 2 snapshot=false".to_string()
+            ),
+            (OrbitExchange::Deribit, "resync".to_string()),
+            (OrbitExchange::Delta, "update 2 snapshot=true".to_string()),
+            (OrbitExchange::Deribit, "update 7 snapshot=true".to_string()),
+        ]
+    );
+    // contract details come from the instruments, like on the live feed
+    assert!(events.iter().all(|event| event.strike == Some(dec!(20000))));
+
+    // replaying twice gives the same thing
+    assert_eq!(replay.events().collect::<Vec<_>>(), events);
+}
+
+#[test]
+fn feeds_the_storage_like_the_live_feed() {
+    let dir = TempDir::new();
+    record_session(&dir.0);
+    let instruments = instruments();
+    let mut storage = OrbitOrderbookStorage::new(instruments.clone());
+    for event in OrbitReplay::from_dir(&dir.0, instruments.clone())
+        .unwrap()
+        .events()
+    {
+        storage.process(event).unwrap();
+    }
+
+    let delta = OrbitBookKey::from_instrument(&instruments[0]);
+    let top = storage.top_of_book(&delta).unwrap();
+    assert_eq!(
+        (top.bid, top.ask),
+        (Some((dec!(101), dec!(1))), Some((dec!(109), dec!(1))))
+    );
+    let deribit = OrbitBookKey::from_instrument(&instruments[1]);
+    let top = storage.top_of_book(&deribit).unwrap();
+    assert_eq!(
+        (top.bid, top.ask),
+        (Some((dec!(0.06), dec!(1))), Some((dec!(0.1), dec!(1))))
+    );
+}
+
+#[tokio::test]
+async fn broadcasts_until_the_recording_ends() {
+    let dir = TempDir::new();
+    record_session(&dir.0);
+    let expected: Vec<_> = OrbitReplay::from_dir(&dir.0, instruments())
+        .unwrap()
+        .events()
+        .collect();
+
+    let mut rx = OrbitReplay::from_dir(&dir.0, instruments())
+        .unwrap()
+        .start();
+    let mut events = vec![];
+    while let Ok(event) = rx.recv().await {
+        events.push(event);
+    }
+    assert_eq!(events, expected);
+}
+
+#[tokio::test]
+async fn paces_frames_by_receive_time() {
+    let dir = TempDir::new();
+    record_session(&dir.0);
+    // 80ms between the first and last frame, replayed at half speed
+    let started = Instant::now();
+    let mut rx = OrbitReplay::from_dir(&dir.0, instruments())
+        .unwrap()
+        .with_speed(OrbitReplaySpeed::WallClock(0.5))
+        .unwrap()
+        .start();
+    let mut count = 0;
+    while rx.recv().await.is_ok() {
+        count += 1;
+    }
+    assert_eq!(count, 6);
+    assert!(started.elapsed() >= Duration::from_millis(140));
+}
+
+#[test]
+fn rejects_rates_that_are_not_positive() {
+    let replay = || OrbitReplay::new(vec![], instruments());
+    for rate in [0.0, -1.0, f64::NAN] {
+        assert!(replay()
+            .with_speed(OrbitReplaySpeed::WallClock(rate))
+            .is_err());
+    }
+    assert!(replay()
+        .with_speed(OrbitReplaySpeed::WallClock(2.0))
+        .is_ok());
+    assert!(replay().with_speed(OrbitReplaySpeed::Max).is_ok());
+}
+
+#[test]
+fn sequences_interleaved_connections_separately() {
+    let dir = TempDir::new();
+    // the old connection keeps delivering while its replacement starts up
+    let (old, new) = (Uuid::new_v4(), Uuid::new_v4());
+    let snapshot = || deribit_book("snapshot", 1, None, json!([["new", 0.05, 10.0]]));
+    let change = || deribit_book("change", 2, Some(1), json!([["change", 0.05, 4.0]]));
+    write_recording(
+        &dir.0,
+        OrbitExchange::Deribit,
+        "00000.ndjson.gz",
+        &[
+            (10 * MS, old, snapshot()),
+            (20 * MS, new, snapshot()),
+            (30 * MS, old, change()),
+            (40 * MS, new, change()),
+        ],
+    );
+    let instruments = instruments();
+    let events: Vec<_> = OrbitReplay::from_dir(&dir.0, instruments.clone())
+        .unwrap()
+        .events()
+        .collect();
+    assert_eq!(events.len(), 4);
+    assert!(events
+        .iter()
+        .all(|event| matches!(event.payload, Some(OrbitEventPayload::OrderbookUpdate(_)))));
+
+    let mut storage = OrbitOrderbookStorage::new(instruments.clone());
+    for event in events {
+        storage.process(event).unwrap();
+    }
+    let deribit = OrbitBookKey::from_instrument(&instruments[1]);
+    let top = storage.top_of_book(&deribit).unwrap();
+    assert_eq!(top.bid, Some((dec!(0.05), dec!(4))));
+}
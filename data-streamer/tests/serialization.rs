@@ -0,0 +1,191 @@
+mod common;
+
+use chrono::{DateTime, Duration, Utc};
+use common::{delta_option, delta_perpetual, deribit_option, deribit_perpetual};
+use data_streamer::{
+    OrbitBookKey, OrbitBookSide, OrbitContractSpecs, OrbitContractType, OrbitCurrency, OrbitEvent,
+    OrbitEventPayload, OrbitExchange, OrbitFunding, OrbitIndexPrice, OrbitInstrument,
+    OrbitLifecycle, OrbitOrderbookStorage, OrbitTicker, OrbitTrade, OrbitTradeSide,
+    OrderbookUpdate, OrderbookUpdateLevel, OrderbookUpdateType,
+};
+use rust_decimal_macros::dec;
+use serde_json::Value;
+
+// instruments as listed in delta-deribit-instruments.txt
+const INSTRUMENTS: &str = include_str!("fixtures/instruments.json");
+
+fn instruments() -> Vec<OrbitInstrument> {
+    vec![
+        delta_option("P-BTC-20000-301222", "20000", "2022-12-30T12:00:00Z"),
+        deribit_option("BTC-30DEC22-20000-P", 20000.0, 1672387200000),
+        delta_perpetual("BTCUSDT"),
+        deribit_perpetual("BTC-PERPETUAL"),
+    ]
+}
+
+fn snapshot(instrument: &OrbitInstrument, bid: &str, ask: &str) -> OrbitEvent {
+    let level = |price: &str| {
+        OrderbookUpdateLevel(OrderbookUpdateType::New, price.parse().unwrap(), dec!(1.5))
+    };
+    OrbitEvent::for_instrument(
+        instrument.exchange().clone(),
+        instrument.symbol().to_string(),
+        Some(instrument),
+        OrbitEventPayload::OrderbookUpdate(OrderbookUpdate {
+            is_snapshot: true,
+            timestamp: 1,
+            bids: vec![level(bid)],
+            asks: vec![level(ask)],
+        }),
+    )
+}
+
+fn trade(instrument: &OrbitInstrument) -> OrbitEvent {
+    OrbitEvent::for_instrument(
+        instrument.exchange().clone(),
+        instrument.symbol().to_string(),
+        Some(instrument),
+        OrbitEventPayload::Trade(OrbitTrade {
+            trade_id: Some("1".to_string()),
+            timestamp: 2,
+            price: dec!(0.0125),
+            size: dec!(0.5),
+            side: OrbitTradeSide::Buy,
+            iv: Some(0.62),
+        }),
+    )
+}
+
+fn index_price() -> OrbitEvent {
+    OrbitEvent::index_price(
+        OrbitExchange::Deribit,
+        "btc_usd".to_string(),
+        OrbitCurrency::Btc,
+        OrbitIndexPrice {
+            price: dec!(16842.5),
+            timestamp: Some(3),
+        },
+    )
+}
+
+#[test]
+fn round_trips_instruments_through_the_fixture() {
+    let fixture: Vec<OrbitInstrument> = serde_json::from_str(INSTRUMENTS).unwrap();
+    assert_eq!(fixture, instruments());
+    assert_eq!(
+        serde_json::to_value(&fixture).unwrap(),
+        serde_json::from_str::<Value>(INSTRUMENTS).unwrap()
+    );
+}
+
+#[test]
+fn builds_instruments_like_the_exchanges_list_them() {
+    let expiration: DateTime<Utc> = "2022-12-30T08:00:00Z".parse().unwrap();
+    let built = OrbitInstrument::new(
+        OrbitExchange::Deribit,
+        "BTC-30DEC22-20000-P".to_string(),
+        OrbitCurrency::Btc,
+        OrbitCurrency::Btc,
+        OrbitContractType::PutOption,
+    )
+    .with_strike(dec!(20000.0))
+    .with_expiration(expiration)
+    .with_price_index("btc_usd".to_string())
+    .with_specs(OrbitContractSpecs {
+        premium_fee_cap: Some(0.125),
+        ..Default::default()
+    });
+    assert_eq!(built, instruments()[1]);
+    assert_eq!(
+        built.expiration_date(),
+        Some("2022-12-30T00:00:00Z".parse().unwrap())
+    );
+}
+
+#[test]
+fn round_trips_events() {
+    let instruments = instruments();
+    let option = &instruments[1];
+    let events = vec![
+        snapshot(option, "0.05", "0.06"),
+        trade(option),
+        index_price(),
+        OrbitEvent::for_instrument(
+            OrbitExchange::Deribit,
+            option.symbol().to_string(),
+            Some(option),
+            OrbitEventPayload::Ticker(OrbitTicker {
+                timestamp: 4,
+                mark_price: dec!(0.0125),
+                mark_iv: Some(0.61),
+                bid_iv: None,
+                ask_iv: None,
+                greeks: None,
+                open_interest: Some(dec!(120.5)),
+                underlying_price: Some(dec!(16850)),
+                index_price: None,
+            }),
+        ),
+        OrbitEvent::for_instrument(
+            OrbitExchange::Delta,
+            "BTCUSDT".to_string(),
+            Some(&instruments[2]),
+            OrbitEventPayload::Funding(OrbitFunding {
+                timestamp: 5,
+                rate_8h: 0.0001,
+                next_funding_at: Some("2022-12-30T16:00:00Z".parse().unwrap()),
+            }),
+        ),
+        OrbitEvent::for_instrument(
+            OrbitExchange::Deribit,
+            option.symbol().to_string(),
+            Some(option),
+            OrbitEventPayload::Lifecycle(OrbitLifecycle::Expired, Box::new(option.clone())),
+        ),
+    ];
+    let json = serde_json::to_string(&events).unwrap();
+    assert_eq!(
+        serde_json::from_str::<Vec<OrbitEvent>>(&json).unwrap(),
+        events
+    );
+}
+
+#[test]
+fn round_trips_storage_with_its_books() {
+    let instruments = instruments();
+    let mut storage = OrbitOrderbookStorage::new(instruments.clone())
+        .with_trade_tape_len(10)
+        .with_reference_max_age(Duration::seconds(30));
+    storage
+        .process(snapshot(&instruments[1], "0.05", "0.06"))
+        .unwrap();
+    storage.process(trade(&instruments[1])).unwrap();
+    storage
+        .process(snapshot(&instruments[3], "16840", "16840.5"))
+        .unwrap();
+    storage.process(index_price()).unwrap();
+
+    let json = serde_json::to_string(&storage).unwrap();
+    let restored: OrbitOrderbookStorage = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.id, storage.id);
+    assert_eq!(restored.trade_tape_len(), 10);
+    assert_eq!(restored.reference_max_age(), Duration::seconds(30));
+    for instrument in instruments.iter() {
+        let key = OrbitBookKey::from_instrument(instrument);
+        assert!(restored.is_listed(&key));
+        assert_eq!(
+            serde_json::to_value(restored.book(&key)).unwrap(),
+            serde_json::to_value(storage.book(&key)).unwrap()
+        );
+    }
+    let option = OrbitBookKey::from_instrument(&instruments[1]);
+    assert_eq!(
+        restored.best(&option, OrbitBookSide::Bid),
+        Some((dec!(0.05), dec!(1.5)))
+    );
+    assert_eq!(restored.last_trade(&option), storage.last_trade(&option));
+    assert_eq!(
+        restored.index_price(&OrbitExchange::Deribit, &OrbitCurrency::Btc),
+        storage.index_price(&OrbitExchange::Deribit, &OrbitCurrency::Btc)
+    );
+}
@@ -0,0 +1,414 @@
+mod common;
+
+use chrono::{DateTime, Utc};
+use common::{deribit_option, deribit_perpetual};
+use data_streamer::{
+    expiration_key, Expiration, OrbitBookKey, OrbitBookSide, OrbitCurrency, OrbitEvent,
+    OrbitEventPayload, OrbitExchange, OrbitInstrument, OrbitLevelChange, OrbitOrderbookStorage,
+    OrbitSettlement, OrbitTenor, OrbitVwap, OrderbookUpdate, OrderbookUpdateLevel,
+    OrderbookUpdateType,
+};
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+
+// 2022-12-30 08:00 UTC
+const EXPIRATION: i64 = 1672387200000;
+// the following weeklies, 2023-01-27 is the January monthly
+const WEEKLIES: [i64; 3] = [1672992000000, 1673596800000, 1674806400000];
+
+fn levels(levels: &[(Decimal, Decimal)]) -> Vec<OrderbookUpdateLevel> {
+    levels
+        .iter()
+        .map(|(price, size)| OrderbookUpdateLevel(OrderbookUpdateType::New, *price, *size))
+        .collect()
+}
+
+fn update(
+    instrument: &OrbitInstrument,
+    is_snapshot: bool,
+    bids: Vec<OrderbookUpdateLevel>,
+    asks: Vec<OrderbookUpdateLevel>,
+) -> OrbitEvent {
+    OrbitEvent::for_instrument(
+        OrbitExchange::Deribit,
+        instrument.symbol().to_string(),
+        Some(instrument),
+        OrbitEventPayload::OrderbookUpdate(OrderbookUpdate {
+            is_snapshot,
+            timestamp: 1,
+            bids,
+            asks,
+        }),
+    )
+}
+
+// three levels a side, 1, 2 and 3 deep
+fn stacked_book() -> (OrbitOrderbookStorage, OrbitBookKey) {
+    let call = deribit_option("BTC-30DEC22-20000-C", 20000.0, EXPIRATION);
+    let key = OrbitBookKey::from_instrument(&call);
+    let mut storage = OrbitOrderbookStorage::new(vec![call.clone()]);
+    storage
+        .process(update(
+            &call,
+            true,
+            levels(&[
+                (dec!(0.05), dec!(1)),
+                (dec!(0.045), dec!(2)),
+                (dec!(0.04), dec!(3)),
+            ]),
+            levels(&[
+                (dec!(0.055), dec!(1)),
+                (dec!(0.06), dec!(2)),
+                (dec!(0.065), dec!(3)),
+            ]),
+        ))
+        .unwrap();
+    (storage, key)
+}
+
+#[test]
+fn lists_depth_best_first() {
+    let (storage, key) = stacked_book();
+    assert_eq!(
+        storage.depth(&key, OrbitBookSide::Bid, 2),
+        Some(vec![(dec!(0.05), dec!(1)), (dec!(0.045), dec!(2))])
+    );
+    assert_eq!(
+        storage.depth(&key, OrbitBookSide::Ask, 5),
+        Some(vec![
+            (dec!(0.055), dec!(1)),
+            (dec!(0.06), dec!(2)),
+            (dec!(0.065), dec!(3))
+        ])
+    );
+}
+
+#[test]
+fn sums_size_at_prices_at_least_as_good() {
+    let (storage, key) = stacked_book();
+    let cumulative = |side, price| storage.cumulative_size(&key, side, price).unwrap();
+    assert_eq!(cumulative(OrbitBookSide::Bid, dec!(0.045)), dec!(3));
+    assert_eq!(cumulative(OrbitBookSide::Bid, dec!(0.01)), dec!(6));
+    assert_eq!(cumulative(OrbitBookSide::Bid, dec!(0.051)), dec!(0));
+    assert_eq!(cumulative(OrbitBookSide::Ask, dec!(0.06)), dec!(3));
+    assert_eq!(cumulative(OrbitBookSide::Ask, dec!(0.054)), dec!(0));
+}
+
+#[test]
+fn sweeps_the_book_for_a_vwap() {
+    let (storage, key) = stacked_book();
+    assert_eq!(
+        storage.vwap(&key, OrbitBookSide::Ask, dec!(2)),
+        Some(OrbitVwap {
+            price: dec!(0.0575),
+            filled: dec!(2)
+        })
+    );
+    assert_eq!(
+        storage.vwap(&key, OrbitBookSide::Bid, dec!(4)),
+        Some(OrbitVwap {
+            price: dec!(0.045),
+            filled: dec!(4)
+        })
+    );
+    // more than the book holds fills what there is
+    assert_eq!(
+        storage.vwap(&key, OrbitBookSide::Ask, dec!(10)),
+        Some(OrbitVwap {
+            price: dec!(0.37) / dec!(6),
+            filled: dec!(6)
+        })
+    );
+}
+
+#[test]
+fn has_nothing_to_offer_on_an_empty_book() {
+    let put = deribit_option("BTC-30DEC22-20000-P", 20000.0, EXPIRATION);
+    let key = OrbitBookKey::from_instrument(&put);
+    let storage = OrbitOrderbookStorage::new(vec![put]);
+    assert_eq!(storage.depth(&key, OrbitBookSide::Bid, 3), Some(vec![]));
+    assert_eq!(
+        storage.cumulative_size(&key, OrbitBookSide::Ask, dec!(1)),
+        Some(dec!(0))
+    );
+    assert_eq!(storage.vwap(&key, OrbitBookSide::Ask, dec!(1)), None);
+
+    // and on a book that isn't there
+    let (storage, _) = stacked_book();
+    let key =
+        OrbitBookKey::from_instrument(&deribit_option("BTC-30DEC22-25000-P", 25000.0, EXPIRATION));
+    assert_eq!(storage.depth(&key, OrbitBookSide::Bid, 3), None);
+    assert_eq!(storage.vwap(&key, OrbitBookSide::Bid, dec!(1)), None);
+}
+
+#[test]
+fn books_inverse_and_linear_listings_of_a_currency_apart() {
+    let inverse = deribit_perpetual("BTC-PERPETUAL");
+    let linear = deribit_perpetual("BTC_USDC-PERPETUAL").with_settlement(OrbitSettlement::Linear);
+    let mut storage = OrbitOrderbookStorage::new(vec![inverse.clone(), linear.clone()]);
+    for (perpetual, bid) in [(&inverse, dec!(20000)), (&linear, dec!(20010))] {
+        storage
+            .process(update(
+                perpetual,
+                true,
+                levels(&[(bid, dec!(1))]),
+                levels(&[(bid + dec!(10), dec!(1))]),
+            ))
+            .unwrap();
+    }
+    assert_eq!(storage.storage.len(), 2);
+    let inverse_key = OrbitBookKey::from_instrument(&inverse);
+    let linear_key = OrbitBookKey::from_instrument(&linear);
+    assert_eq!(storage.mid(&inverse_key), Some(dec!(20005)));
+    assert_eq!(storage.mid(&linear_key), Some(dec!(20015)));
+
+    // dropping one leaves the other
+    assert!(storage.remove_instrument(&linear));
+    assert_eq!(storage.mid(&linear_key), None);
+    assert_eq!(storage.mid(&inverse_key), Some(dec!(20005)));
+}
+
+fn time(time: &str) -> DateTime<Utc> {
+    time.parse().unwrap()
+}
+
+fn day(date: &str) -> Expiration {
+    expiration_key(time(&format!("{date}T08:00:00Z")))
+}
+
+// strikes 18000 to 22000 on the first expiry, a single strike on the others
+fn option_chain() -> OrbitOrderbookStorage {
+    let mut instruments: Vec<OrbitInstrument> = [18000.0, 19000.0, 20000.0, 21000.0, 22000.0]
+        .into_iter()
+        .map(|strike| deribit_option(&format!("BTC-30DEC22-{strike}-C"), strike, EXPIRATION))
+        .collect();
+    for (i, expiration) in WEEKLIES.into_iter().enumerate() {
+        instruments.push(deribit_option(
+            &format!("BTC-{i}-20000-C"),
+            20000.0,
+            expiration,
+        ));
+    }
+    OrbitOrderbookStorage::new(instruments)
+}
+
+#[test]
+fn finds_the_nearest_expiry_still_to_come() {
+    let storage = option_chain();
+    let nearest = |now| {
+        storage.nearest_expiration(
+            &OrbitExchange::Deribit,
+            &OrbitCurrency::Btc,
+            OrbitSettlement::Inverse,
+            time(now),
+        )
+    };
+    // settled an hour ago, but keyed by the day
+    assert_eq!(nearest("2022-12-30T09:00:00Z"), Some(day("2022-12-30")));
+    assert_eq!(nearest("2022-12-31T00:00:00Z"), Some(day("2023-01-06")));
+    assert_eq!(nearest("2023-01-28T00:00:00Z"), None);
+    assert_eq!(
+        storage.nearest_expiration(
+            &OrbitExchange::Deribit,
+            &OrbitCurrency::Eth,
+            OrbitSettlement::Inverse,
+            time("2022-12-30T00:00:00Z")
+        ),
+        None
+    );
+    // the chain is inverse, there are no linear BTC options
+    assert_eq!(
+        storage.nearest_expiration(
+            &OrbitExchange::Deribit,
+            &OrbitCurrency::Btc,
+            OrbitSettlement::Linear,
+            time("2022-12-30T00:00:00Z")
+        ),
+        None
+    );
+}
+
+#[test]
+fn picks_expiries_by_tenor() {
+    let storage = option_chain();
+    let now = time("2022-12-30T00:00:00Z");
+    let by_tenor = |tenor| {
+        storage.expiration_by_tenor(
+            &OrbitExchange::Deribit,
+            &OrbitCurrency::Btc,
+            OrbitSettlement::Inverse,
+            tenor,
+            now,
+        )
+    };
+    assert_eq!(
+        by_tenor(OrbitTenor::Month {
+            year: 2023,
+            month: 1
+        }),
+        Some(day("2023-01-27"))
+    );
+    assert_eq!(
+        by_tenor(OrbitTenor::Month {
+            year: 2022,
+            month: 12
+        }),
+        Some(day("2022-12-30"))
+    );
+    assert_eq!(
+        by_tenor(OrbitTenor::Month {
+            year: 2023,
+            month: 2
+        }),
+        None
+    );
+    assert_eq!(by_tenor(OrbitTenor::Days(7)), Some(day("2023-01-06")));
+    // three days past the weekly is closer than four days before the next
+    assert_eq!(by_tenor(OrbitTenor::Days(10)), Some(day("2023-01-06")));
+    assert_eq!(by_tenor(OrbitTenor::Days(12)), Some(day("2023-01-13")));
+    assert_eq!(by_tenor(OrbitTenor::Days(365)), Some(day("2023-01-27")));
+}
+
+#[test]
+fn lists_the_strikes_closest_to_the_money() {
+    let storage = option_chain();
+    let around = |expiration, count| {
+        storage.strikes_around_atm(
+            &OrbitExchange::Deribit,
+            &OrbitCurrency::Btc,
+            OrbitSettlement::Inverse,
+            expiration,
+            dec!(20400),
+            count,
+        )
+    };
+    assert_eq!(
+        around(day("2022-12-30"), 3),
+        vec![dec!(19000), dec!(20000), dec!(21000)]
+    );
+    assert_eq!(around(day("2022-12-30"), 1), vec![dec!(20000)]);
+    assert_eq!(around(day("2022-12-30"), 10).len(), 5);
+    assert_eq!(around(day("2023-01-06"), 3), vec![dec!(20000)]);
+    assert_eq!(around(day("2023-01-07"), 3), vec![]);
+    assert_eq!(
+        storage.atm_strike(
+            &OrbitExchange::Deribit,
+            &OrbitCurrency::Btc,
+            OrbitSettlement::Inverse,
+            day("2022-12-30"),
+            dec!(21600)
+        ),
+        Some(dec!(22000))
+    );
+}
+
+#[test]
+fn reports_the_levels_an_update_changed() {
+    let (mut storage, key) = stacked_book();
+    let call = deribit_option("BTC-30DEC22-20000-C", 20000.0, EXPIRATION);
+    let change = |side, price, amount| OrbitLevelChange {
+        side,
+        price,
+        amount,
+    };
+
+    let changed = storage
+        .process(update(
+            &call,
+            false,
+            vec![OrderbookUpdateLevel(
+                OrderbookUpdateType::Change,
+                dec!(0.045),
+                dec!(5),
+            )],
+            vec![OrderbookUpdateLevel(
+                OrderbookUpdateType::New,
+                dec!(0.0525),
+                dec!(1.5),
+            )],
+        ))
+        .unwrap();
+    assert_eq!(changed.key, key);
+    assert!(!changed.is_snapshot);
+    assert_eq!(
+        changed.changes,
+        vec![
+            change(OrbitBookSide::Bid, dec!(0.045), dec!(5)),
+            change(OrbitBookSide::Ask, dec!(0.0525), dec!(1.5)),
+        ]
+    );
+    assert_eq!(changed.top_of_book.ask, Some((dec!(0.0525), dec!(1.5))));
+
+    // a deleted level is reported at size 0
+    let changed = storage
+        .process(update(
+            &call,
+            false,
+            vec![OrderbookUpdateLevel(
+                OrderbookUpdateType::Delete,
+                dec!(0.05),
+                dec!(0),
+            )],
+            vec![],
+        ))
+        .unwrap();
+    assert_eq!(
+        changed.changes,
+        vec![change(OrbitBookSide::Bid, dec!(0.05), dec!(0))]
+    );
+    assert_eq!(changed.top_of_book.bid, Some((dec!(0.045), dec!(5))));
+    assert_eq!(
+        storage.depth(&key, OrbitBookSide::Bid, 5),
+        Some(vec![(dec!(0.045), dec!(5)), (dec!(0.04), dec!(3))])
+    );
+}
+
+#[test]
+fn reports_the_levels_a_snapshot_removed() {
+    let (mut storage, key) = stacked_book();
+    let call = deribit_option("BTC-30DEC22-20000-C", 20000.0, EXPIRATION);
+    let change = |side, price, amount| OrbitLevelChange {
+        side,
+        price,
+        amount,
+    };
+    storage
+        .process(update(
+            &call,
+            false,
+            levels(&[(dec!(0.035), dec!(4))]),
+            vec![],
+        ))
+        .unwrap();
+
+    // the snapshot leaves out the 0.04 bid and the delta's 0.035 bid and keeps one ask
+    let changed = storage
+        .process(update(
+            &call,
+            true,
+            levels(&[(dec!(0.05), dec!(1)), (dec!(0.045), dec!(2))]),
+            levels(&[(dec!(0.055), dec!(1))]),
+        ))
+        .unwrap();
+    assert!(changed.is_snapshot);
+    assert_eq!(
+        changed.changes,
+        vec![
+            change(OrbitBookSide::Bid, dec!(0.05), dec!(1)),
+            change(OrbitBookSide::Bid, dec!(0.045), dec!(2)),
+            change(OrbitBookSide::Ask, dec!(0.055), dec!(1)),
+            change(OrbitBookSide::Bid, dec!(0.035), dec!(0)),
+            change(OrbitBookSide::Bid, dec!(0.04), dec!(0)),
+            change(OrbitBookSide::Ask, dec!(0.06), dec!(0)),
+            change(OrbitBookSide::Ask, dec!(0.065), dec!(0)),
+        ]
+    );
+    assert_eq!(
+        storage.depth(&key, OrbitBookSide::Bid, 5),
+        Some(vec![(dec!(0.05), dec!(1)), (dec!(0.045), dec!(2))])
+    );
+    assert_eq!(
+        storage.depth(&key, OrbitBookSide::Ask, 5),
+        Some(vec![(dec!(0.055), dec!(1))])
+    );
+}
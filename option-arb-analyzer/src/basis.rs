@@ -0,0 +1,381 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use data_streamer::{
+    Expiration, OrbitBookKey, OrbitContractTypeOrderbook, OrbitCurrency, OrbitExchange,
+    OrbitFutureOrderbook, OrbitOrderbookStorage, OrbitSettlement, OrbitStorageOrderbook,
+};
+
+use crate::units::{float, float_level};
+
+const DAYS_PER_YEAR: f64 = 365.0;
+
+// Basis of a dated future over a perpetual, annualized on the time left to expiry:
+//   annualized = (F - P) / P / years
+// Holding the future against the perp locks in the basis while the perp leg pays or
+// receives funding, so the carry of the pair is the annualized basis net of funding:
+//   cash and carry:         sell future, buy perp  -> carry = basis(F_bid, P_ask) - funding
+//   reverse cash and carry: buy future, sell perp  -> carry = funding - basis(F_ask, P_bid)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CarryTrade {
+    CashAndCarry,
+    ReverseCashAndCarry,
+}
+
+// mid against mid, one point of a venue's term structure in one settlement
+#[derive(Clone, Debug)]
+pub struct BasisQuote {
+    pub exchange: OrbitExchange,
+    pub currency: OrbitCurrency,
+    pub settlement: OrbitSettlement,
+    pub expiration: Expiration,
+    pub future: f64,
+    pub perpetual: f64,
+    pub years: f64,
+    // USD per unit of underlying
+    pub basis: f64,
+    pub annualized: f64,
+}
+
+impl fmt::Display for BasisQuote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} {:?} {:?} {}: future {:.2} perp {:.2}, basis {:.2} USD, {:.2}% annualized",
+            self.exchange,
+            self.currency,
+            self.settlement,
+            self.expiration.date_naive(),
+            self.future,
+            self.perpetual,
+            self.basis,
+            self.annualized * 100.0,
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CarryOpportunity {
+    pub trade: CarryTrade,
+    pub currency: OrbitCurrency,
+    pub expiration: Expiration,
+    pub future_exchange: OrbitExchange,
+    pub future_settlement: OrbitSettlement,
+    pub future_price: f64,
+    pub perpetual_exchange: OrbitExchange,
+    pub perpetual_settlement: OrbitSettlement,
+    pub perpetual_price: f64,
+    pub size: f64,
+    pub years: f64,
+    // annualized fractions
+    pub basis: f64,
+    pub funding: f64,
+    pub carry: f64,
+}
+
+impl fmt::Display for CarryOpportunity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (future_side, perpetual_side) = match self.trade {
+            CarryTrade::CashAndCarry => ("sell", "buy"),
+            CarryTrade::ReverseCashAndCarry => ("buy", "sell"),
+        };
+        write!(
+            f,
+            "{:?} {:?} {}: {} future {:?} {:?}@{:.2} {} perp {:?} {:?}@{:.2} x {}, basis {:.2}% funding {:.2}% carry {:.2}% annualized",
+            self.trade,
+            self.currency,
+            self.expiration.date_naive(),
+            future_side,
+            self.future_exchange,
+            self.future_settlement,
+            self.future_price,
+            perpetual_side,
+            self.perpetual_exchange,
+            self.perpetual_settlement,
+            self.perpetual_price,
+            self.size,
+            self.basis * 100.0,
+            self.funding * 100.0,
+            self.carry * 100.0,
+        )
+    }
+}
+
+// Compares every dated future with every perpetual of the same currency, on the same
+// venue and across venues, inverse and linear alike as both are priced in USD. Fees
+// aren't modelled, the threshold has to cover them.
+#[derive(Clone, Debug)]
+pub struct BasisMonitor {
+    exchanges: Vec<OrbitExchange>,
+    // minimum annualized carry (0.05 is 5%) before an opportunity is reported
+    pub threshold: f64,
+    // the last days of a future blow the annualized figures up, they're left out
+    pub min_days: f64,
+}
+
+impl BasisMonitor {
+    pub fn new(exchanges: Vec<OrbitExchange>, threshold: f64) -> Self {
+        Self {
+            exchanges,
+            threshold,
+            min_days: 1.0,
+        }
+    }
+
+    // every venue's term structure, by exchange, settlement and expiry
+    pub fn quotes(
+        &self,
+        storage: &OrbitOrderbookStorage,
+        currency: &OrbitCurrency,
+        now: DateTime<Utc>,
+    ) -> Vec<BasisQuote> {
+        let mut quotes = vec![];
+        for (exchange, settlement) in self.venues() {
+            let Some(perpetual) = perpetual_book(storage, exchange, currency, settlement)
+                .and_then(|b| b.mid())
+                .map(float)
+            else {
+                continue;
+            };
+            let Some(futures) = futures(storage, exchange, currency, settlement) else {
+                continue;
+            };
+            for (expiration, book) in futures.iter() {
+                let (Some(years), Some(future)) =
+                    (self.years(*expiration, now), book.mid().map(float))
+                else {
+                    continue;
+                };
+                quotes.push(BasisQuote {
+                    exchange: exchange.clone(),
+                    currency: currency.clone(),
+                    settlement,
+                    expiration: *expiration,
+                    future,
+                    perpetual,
+                    years,
+                    basis: future - perpetual,
+                    annualized: annualized_basis(future, perpetual, years),
+                });
+            }
+        }
+        quotes
+    }
+
+    // carry trades on executable prices, perps without a funding rate yet are skipped
+    pub fn scan(
+        &self,
+        storage: &OrbitOrderbookStorage,
+        currency: &OrbitCurrency,
+        now: DateTime<Utc>,
+    ) -> Vec<CarryOpportunity> {
+        let perpetuals: Vec<_> = self
+            .venues()
+            .filter_map(|(exchange, settlement)| {
+                let book = perpetual_book(storage, exchange, currency, settlement)?;
+                let funding = storage
+                    .funding(exchange, currency, settlement)?
+                    .annualized();
+                Some((exchange, settlement, book, funding))
+            })
+            .collect();
+
+        let mut opportunities = vec![];
+        for (future_exchange, future_settlement) in self.venues() {
+            let Some(futures) = futures(storage, future_exchange, currency, future_settlement)
+            else {
+                continue;
+            };
+            for (expiration, future) in futures.iter() {
+                let Some(years) = self.years(*expiration, now) else {
+                    continue;
+                };
+                for (perpetual_exchange, perpetual_settlement, perpetual, funding) in
+                    perpetuals.iter()
+                {
+                    let trades = [
+                        (
+                            CarryTrade::CashAndCarry,
+                            future.best_bid().map(float_level),
+                            perpetual.best_ask().map(float_level),
+                        ),
+                        (
+                            CarryTrade::ReverseCashAndCarry,
+                            future.best_ask().map(float_level),
+                            perpetual.best_bid().map(float_level),
+                        ),
+                    ];
+                    for (trade, future_quote, perpetual_quote) in trades {
+                        let (
+                            Some((future_price, future_size)),
+                            Some((perpetual_price, perpetual_size)),
+                        ) = (future_quote, perpetual_quote)
+                        else {
+                            continue;
+                        };
+                        let basis = annualized_basis(future_price, perpetual_price, years);
+                        let carry = match trade {
+                            CarryTrade::CashAndCarry => basis - funding,
+                            CarryTrade::ReverseCashAndCarry => funding - basis,
+                        };
+                        if carry > self.threshold {
+                            opportunities.push(CarryOpportunity {
+                                trade,
+                                currency: currency.clone(),
+                                expiration: *expiration,
+                                future_exchange: future_exchange.clone(),
+                                future_settlement,
+                                future_price,
+                                perpetual_exchange: (*perpetual_exchange).clone(),
+                                perpetual_settlement: *perpetual_settlement,
+                                perpetual_price,
+                                size: future_size.min(perpetual_size),
+                                years,
+                                basis,
+                                funding: *funding,
+                                carry,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        opportunities
+    }
+
+    fn venues(&self) -> impl Iterator<Item = (&OrbitExchange, OrbitSettlement)> {
+        self.exchanges.iter().flat_map(|exchange| {
+            OrbitSettlement::ALL
+                .into_iter()
+                .map(move |settlement| (exchange, settlement))
+        })
+    }
+
+    // books are keyed by the day of expiry, the settlement hour is ignored
+    fn years(&self, expiration: Expiration, now: DateTime<Utc>) -> Option<f64> {
+        let days = (expiration - now).num_seconds() as f64 / 86_400.0;
+        (days >= self.min_days).then_some(days / DAYS_PER_YEAR)
+    }
+}
+
+pub fn annualized_basis(future: f64, perpetual: f64, years: f64) -> f64 {
+    (future - perpetual) / perpetual / years
+}
+
+fn perpetual_book<'a>(
+    storage: &'a OrbitOrderbookStorage,
+    exchange: &OrbitExchange,
+    currency: &OrbitCurrency,
+    settlement: OrbitSettlement,
+) -> Option<&'a OrbitStorageOrderbook> {
+    storage.book(&OrbitBookKey::perpetual(
+        exchange.clone(),
+        currency.clone(),
+        settlement,
+    ))
+}
+
+fn futures<'a>(
+    storage: &'a OrbitOrderbookStorage,
+    exchange: &OrbitExchange,
+    currency: &OrbitCurrency,
+    settlement: OrbitSettlement,
+) -> Option<&'a OrbitFutureOrderbook> {
+    match &storage
+        .storage
+        .get(&(exchange.clone(), currency.clone(), settlement))?[0]
+    {
+        Some(OrbitContractTypeOrderbook::Future(futures)) => Some(futures),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use data_streamer::{
+        expiration_key, OrbitContractType, OrbitEventPayload, OrbitFunding, OrbitInstrument,
+    };
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::testing::{expiration, instrument, quote, send, storage};
+
+    // a Deribit future against its perp, 73 days out so a year is five times the period,
+    // funding is per 8h
+    fn scan(future: (Decimal, Decimal), rate_8h: f64, days_left: i64) -> Vec<CarryOpportunity> {
+        let instruments: Vec<OrbitInstrument> = [
+            OrbitContractType::Future,
+            OrbitContractType::PerpetualFuture,
+        ]
+        .into_iter()
+        .map(|contract_type| {
+            instrument(
+                OrbitExchange::Deribit,
+                OrbitCurrency::Usd,
+                contract_type,
+                None,
+            )
+        })
+        .collect();
+        let mut storage = storage(&instruments);
+        quote(&mut storage, &instruments[0], future.0, future.1);
+        quote(&mut storage, &instruments[1], dec!(19990), dec!(20000));
+        let funding = OrbitFunding {
+            timestamp: 1,
+            rate_8h,
+            next_funding_at: None,
+        };
+        send(
+            &mut storage,
+            &instruments[1],
+            OrbitEventPayload::Funding(funding),
+        );
+        // books are keyed by the day of expiry
+        let now = expiration_key(expiration()) - Duration::days(days_left);
+        BasisMonitor::new(vec![OrbitExchange::Deribit], 0.05).scan(
+            &storage,
+            &OrbitCurrency::Btc,
+            now,
+        )
+    }
+
+    #[test]
+    fn sells_a_rich_future_against_the_perp() {
+        // 10% annualized basis against 2.19% funding
+        let opportunities = scan((dec!(20400), dec!(20410)), 0.00002, 73);
+        assert_eq!(opportunities.len(), 1);
+        let opportunity = &opportunities[0];
+        assert_eq!(opportunity.trade, CarryTrade::CashAndCarry);
+        assert_eq!(
+            (opportunity.future_price, opportunity.perpetual_price),
+            (20400.0, 20000.0)
+        );
+        assert!((opportunity.basis - 0.1).abs() < 1e-9);
+        assert!((opportunity.carry - (0.1 - 0.0219)).abs() < 1e-9);
+
+        // funding eats the basis
+        assert!(scan((dec!(20400), dec!(20410)), 0.0001, 73).is_empty());
+    }
+
+    #[test]
+    fn buys_a_cheap_future_against_the_perp() {
+        // -9.75% annualized basis while longs pay 10.95% funding
+        let opportunities = scan((dec!(19590), dec!(19600)), 0.0001, 73);
+        assert_eq!(opportunities.len(), 1);
+        let opportunity = &opportunities[0];
+        assert_eq!(opportunity.trade, CarryTrade::ReverseCashAndCarry);
+        assert_eq!(
+            (opportunity.future_price, opportunity.perpetual_price),
+            (19600.0, 19990.0)
+        );
+        assert!((opportunity.carry - (0.1095 + 390.0 / 19990.0 * 5.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn leaves_out_futures_about_to_expire() {
+        assert_eq!(scan((dec!(20400), dec!(20410)), 0.00002, 1).len(), 1);
+        assert!(scan((dec!(20400), dec!(20410)), 0.00002, 0).is_empty());
+    }
+}
@@ -0,0 +1,464 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use log::warn;
+
+use data_streamer::matching::{CanonicalContractKey, OrbitMatchRules};
+use data_streamer::{
+    OrbitBookKey, OrbitContractType, OrbitExchange, OrbitInstrument, OrbitOrderbookStorage,
+};
+
+use crate::units::{float, float_level, forward_price, UsdConverter};
+
+// taker fees as the venue lists them: a rate on the underlying notional, capped at a
+// fraction of the premium for options where the venue caps them
+#[derive(Clone, Copy, Debug)]
+pub struct FeeSchedule {
+    pub taker_rate: f64,
+    pub premium_cap: Option<f64>,
+}
+
+impl FeeSchedule {
+    // fee in USD for one unit of underlying
+    pub fn fee(&self, premium_usd: f64, underlying: f64) -> f64 {
+        let fee = self.taker_rate * underlying;
+        match self.premium_cap {
+            Some(cap) => fee.min(cap * premium_usd),
+            None => fee,
+        }
+    }
+
+    // None when the contract specs don't list a taker fee
+    pub fn for_instrument(instrument: &OrbitInstrument) -> Option<Self> {
+        let specs = instrument.specs();
+        Some(Self {
+            taker_rate: specs.taker_fee?,
+            premium_cap: specs.premium_fee_cap,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CrossExchangeOpportunity {
+    pub key: CanonicalContractKey,
+    pub buy_exchange: OrbitExchange,
+    pub buy_price: f64,
+    pub sell_exchange: OrbitExchange,
+    pub sell_price: f64,
+    pub size: f64,
+    // USD per unit of underlying
+    pub gross_edge: f64,
+    pub fees: f64,
+    pub net_profit: f64,
+}
+
+impl fmt::Display for CrossExchangeOpportunity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let key = &self.key;
+        write!(
+            f,
+            "{:?} {:?} {:?} K={:?}{}: buy {:?}@{:.4} sell {:?}@{:.4} x {}, gross {:.4} fees {:.4} net {:.4} USD",
+            key.underlying,
+            key.kind,
+            key.expiry.map(|e| e.date_naive()),
+            key.strike,
+            key.settlement
+                .map(|settlement| format!(" {settlement:?}"))
+                .unwrap_or_default(),
+            self.buy_exchange,
+            self.buy_price,
+            self.sell_exchange,
+            self.sell_price,
+            self.size,
+            self.gross_edge,
+            self.fees,
+            self.net_profit,
+        )
+    }
+}
+
+// Watches every contract listed on more than one venue and flags crossed markets,
+// one venue's bid above another venue's ask once both are in USD. Listings are the same
+// contract when their CanonicalContractKey is, under the match rules.
+#[derive(Clone, Debug)]
+pub struct CrossExchangeScanner {
+    // the books of each contract, a venue may list it inverse and linear, only contracts
+    // on more than one venue are scanned
+    contracts: HashMap<CanonicalContractKey, HashSet<OrbitBookKey>>,
+    fees: HashMap<OrbitBookKey, FeeSchedule>,
+    converter: UsdConverter,
+    rules: OrbitMatchRules,
+    // minimum net profit in USD per unit of underlying before an opportunity is reported
+    pub threshold: f64,
+}
+
+impl CrossExchangeScanner {
+    pub fn new(instruments: &[OrbitInstrument], rules: OrbitMatchRules, threshold: f64) -> Self {
+        let mut scanner = Self {
+            contracts: HashMap::new(),
+            fees: HashMap::new(),
+            converter: UsdConverter::default(),
+            rules,
+            threshold,
+        };
+        for instrument in instruments.iter() {
+            scanner.add_listing(instrument);
+        }
+        scanner
+    }
+
+    // follows the listings of the instrument refresher, a contract is scanned once a
+    // second venue lists it, listings without a taker fee are left out rather than
+    // priced at a guess
+    pub fn add_listing(&mut self, instrument: &OrbitInstrument) {
+        let Some(fees) = FeeSchedule::for_instrument(instrument) else {
+            warn!(
+                "{:?} {} lists no taker fee, not scanning it for crossed books",
+                instrument.exchange(),
+                instrument.symbol()
+            );
+            return;
+        };
+        let key = OrbitBookKey::from_instrument(instrument);
+        self.converter.add(instrument);
+        self.fees.insert(key.clone(), fees);
+        self.contracts
+            .entry(self.contract_key(&key))
+            .or_default()
+            .insert(key);
+    }
+
+    pub fn remove_listing(&mut self, key: &OrbitBookKey) {
+        self.converter.remove(key);
+        self.fees.remove(key);
+        let contract_key = self.contract_key(key);
+        if let Some(books) = self.contracts.get_mut(&contract_key) {
+            books.remove(key);
+            if books.is_empty() {
+                self.contracts.remove(&contract_key);
+            }
+        }
+    }
+
+    pub fn key(&self, instrument: &OrbitInstrument) -> CanonicalContractKey {
+        self.contract_key(&OrbitBookKey::from_instrument(instrument))
+    }
+
+    // the contract a book is matched on across venues
+    pub fn contract_key(&self, key: &OrbitBookKey) -> CanonicalContractKey {
+        self.rules
+            .identity(&CanonicalContractKey::from_book_key(key))
+    }
+
+    pub fn contracts(&self) -> usize {
+        self.contracts
+            .values()
+            .filter(|books| venues(books) > 1)
+            .count()
+    }
+
+    pub fn scan(
+        &self,
+        storage: &OrbitOrderbookStorage,
+        key: &CanonicalContractKey,
+    ) -> Vec<CrossExchangeOpportunity> {
+        let Some(books) = self.contracts.get(key).filter(|x| venues(x) > 1) else {
+            return vec![];
+        };
+        let contract_type = &key.kind;
+
+        // (exchange, best bid, best ask, underlying, fees) with prices in USD
+        let quotes: Vec<_> = books
+            .iter()
+            .filter_map(|book_key| {
+                let book = storage.book(book_key)?;
+                let fees = self.fees.get(book_key)?;
+                let underlying = forward_price(storage, book_key)?;
+                // coin premiums at the venue's reference price, the forward until there's one
+                let reference = storage
+                    .reference_price(&book_key.exchange, &book_key.currency)
+                    .map(float)
+                    .unwrap_or(underlying);
+                let to_usd = |level| {
+                    let (price, size) = float_level(level);
+                    Some((self.converter.to_usd(book_key, price, reference)?, size))
+                };
+                Some((
+                    &book_key.exchange,
+                    book.best_bid().and_then(to_usd),
+                    book.best_ask().and_then(to_usd),
+                    underlying,
+                    fees,
+                ))
+            })
+            .collect();
+
+        let mut opportunities = vec![];
+        for (sell_exchange, bid, _, sell_underlying, sell_fees) in quotes.iter() {
+            for (buy_exchange, _, ask, buy_underlying, buy_fees) in quotes.iter() {
+                if sell_exchange == buy_exchange {
+                    continue;
+                }
+                let (Some((bid, bid_size)), Some((ask, ask_size))) = (bid, ask) else {
+                    continue;
+                };
+                if bid <= ask {
+                    continue;
+                }
+                let gross_edge = bid - ask;
+                let fees = fee(sell_fees, contract_type, *bid, *sell_underlying)
+                    + fee(buy_fees, contract_type, *ask, *buy_underlying);
+                let net_profit = gross_edge - fees;
+                if net_profit > self.threshold {
+                    opportunities.push(CrossExchangeOpportunity {
+                        key: key.clone(),
+                        buy_exchange: (*buy_exchange).clone(),
+                        buy_price: *ask,
+                        sell_exchange: (*sell_exchange).clone(),
+                        sell_price: *bid,
+                        size: bid_size.min(*ask_size),
+                        gross_edge,
+                        fees,
+                        net_profit,
+                    });
+                }
+            }
+        }
+        opportunities
+    }
+}
+
+// options pay the rate on the underlying up to the premium cap, futures on their price
+fn fee(fees: &FeeSchedule, contract_type: &OrbitContractType, price: f64, underlying: f64) -> f64 {
+    match contract_type {
+        OrbitContractType::CallOption | OrbitContractType::PutOption => fees.fee(price, underlying),
+        _ => fees.taker_rate * price,
+    }
+}
+
+// distinct exchanges among the books of a contract
+fn venues(books: &HashSet<OrbitBookKey>) -> usize {
+    books
+        .iter()
+        .map(|book| &book.exchange)
+        .collect::<HashSet<_>>()
+        .len()
+}
+
+#[cfg(test)]
+mod tests {
+    use data_streamer::{OrbitContractType, OrbitCurrency, OrbitExchange, OrbitInstrument};
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::testing::{instrument, quote, storage, with_fees};
+
+    // a call at the strike with the taker fee and premium cap the venue lists
+    fn call(
+        exchange: OrbitExchange,
+        quote: OrbitCurrency,
+        strike: Decimal,
+        taker_fee: f64,
+        premium_fee_cap: f64,
+    ) -> OrbitInstrument {
+        let call = instrument(exchange, quote, OrbitContractType::CallOption, Some(strike));
+        with_fees(call, taker_fee, Some(premium_fee_cap))
+    }
+
+    fn future(exchange: OrbitExchange, quote: OrbitCurrency) -> OrbitInstrument {
+        let future = instrument(exchange, quote, OrbitContractType::Future, None);
+        with_fees(future, 0.0005, None)
+    }
+
+    // the same call on both venues with futures at 20000, Deribit quotes it in BTC
+    fn scan(
+        deribit: (Decimal, Decimal),
+        delta: (Decimal, Decimal),
+        threshold: f64,
+    ) -> Vec<CrossExchangeOpportunity> {
+        let instruments: Vec<OrbitInstrument> = vec![
+            call(
+                OrbitExchange::Deribit,
+                OrbitCurrency::Btc,
+                dec!(20000),
+                0.0003,
+                0.125,
+            ),
+            call(
+                OrbitExchange::Delta,
+                OrbitCurrency::Usdt,
+                dec!(20000),
+                0.0005,
+                0.1,
+            ),
+            future(OrbitExchange::Deribit, OrbitCurrency::Btc),
+            future(OrbitExchange::Delta, OrbitCurrency::Usdt),
+        ];
+        let mut storage = storage(&instruments);
+        quote(&mut storage, &instruments[0], deribit.0, deribit.1);
+        quote(&mut storage, &instruments[1], delta.0, delta.1);
+        quote(&mut storage, &instruments[2], dec!(20000), dec!(20000));
+        quote(&mut storage, &instruments[3], dec!(20000), dec!(20000));
+        let scanner =
+            CrossExchangeScanner::new(&instruments, OrbitMatchRules::default(), threshold);
+        assert_eq!(scanner.contracts(), 2);
+        scanner.scan(&storage, &scanner.key(&instruments[0]))
+    }
+
+    #[test]
+    fn reports_crossed_books_net_of_fees() {
+        // Deribit bids 620 USD over Delta's 600 ask, taker fees are 6 and 10
+        let opportunities = scan((dec!(0.031), dec!(0.032)), (dec!(590), dec!(600)), 0.0);
+        assert_eq!(opportunities.len(), 1);
+        let opportunity = &opportunities[0];
+        assert_eq!(opportunity.sell_exchange, OrbitExchange::Deribit);
+        assert_eq!(opportunity.buy_exchange, OrbitExchange::Delta);
+        assert!((opportunity.gross_edge - 20.0).abs() < 1e-9);
+        assert!((opportunity.fees - 16.0).abs() < 1e-9);
+        assert!((opportunity.net_profit - 4.0).abs() < 1e-9);
+
+        // crossed, but not by enough to pay the fees and the threshold
+        let opportunities = scan((dec!(0.031), dec!(0.032)), (dec!(590), dec!(600)), 5.0);
+        assert!(opportunities.is_empty());
+    }
+
+    #[test]
+    fn ignores_books_that_are_not_crossed() {
+        let opportunities = scan((dec!(0.029), dec!(0.031)), (dec!(590), dec!(600)), 0.0);
+        assert!(opportunities.is_empty());
+    }
+
+    #[test]
+    fn charges_each_listing_its_own_fee() {
+        // Delta lists the 21000 call at twice the taker fee of the 20000 one
+        let instruments: Vec<OrbitInstrument> = vec![
+            call(
+                OrbitExchange::Deribit,
+                OrbitCurrency::Btc,
+                dec!(20000),
+                0.0003,
+                0.125,
+            ),
+            call(
+                OrbitExchange::Delta,
+                OrbitCurrency::Usdt,
+                dec!(20000),
+                0.0005,
+                0.1,
+            ),
+            call(
+                OrbitExchange::Deribit,
+                OrbitCurrency::Btc,
+                dec!(21000),
+                0.0003,
+                0.125,
+            ),
+            call(
+                OrbitExchange::Delta,
+                OrbitCurrency::Usdt,
+                dec!(21000),
+                0.001,
+                0.1,
+            ),
+            future(OrbitExchange::Deribit, OrbitCurrency::Btc),
+            future(OrbitExchange::Delta, OrbitCurrency::Usdt),
+        ];
+        let mut storage = storage(&instruments);
+        for pair in instruments[..4].chunks(2) {
+            quote(&mut storage, &pair[0], dec!(0.031), dec!(0.032));
+            quote(&mut storage, &pair[1], dec!(590), dec!(600));
+        }
+        quote(&mut storage, &instruments[4], dec!(20000), dec!(20000));
+        quote(&mut storage, &instruments[5], dec!(20000), dec!(20000));
+        let scanner = CrossExchangeScanner::new(&instruments, OrbitMatchRules::default(), f64::MIN);
+
+        let fees = |instrument| {
+            let opportunities = scanner.scan(&storage, &scanner.key(instrument));
+            assert_eq!(opportunities.len(), 1);
+            opportunities[0].fees
+        };
+        // 6 on Deribit and 10 or 20 on Delta
+        assert!((fees(&instruments[0]) - 16.0).abs() < 1e-9);
+        assert!((fees(&instruments[2]) - 26.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn skips_listings_without_a_taker_fee() {
+        let deribit = call(
+            OrbitExchange::Deribit,
+            OrbitCurrency::Btc,
+            dec!(20000),
+            0.0003,
+            0.125,
+        );
+        let delta = instrument(
+            OrbitExchange::Delta,
+            OrbitCurrency::Usdt,
+            OrbitContractType::CallOption,
+            Some(dec!(20000)),
+        );
+        let scanner = CrossExchangeScanner::new(&[deribit, delta], OrbitMatchRules::default(), 0.0);
+        assert_eq!(scanner.contracts(), 0);
+    }
+
+    #[test]
+    fn matches_inverse_and_linear_listings_unless_settlement_must_agree() {
+        // Deribit's call settles in BTC, Delta's in USDT
+        let instruments = vec![
+            call(
+                OrbitExchange::Deribit,
+                OrbitCurrency::Btc,
+                dec!(20000),
+                0.0003,
+                0.125,
+            ),
+            call(
+                OrbitExchange::Delta,
+                OrbitCurrency::Usdt,
+                dec!(20000),
+                0.0005,
+                0.1,
+            ),
+        ];
+        let scanner = CrossExchangeScanner::new(&instruments, OrbitMatchRules::default(), 0.0);
+        assert_eq!(scanner.contracts(), 1);
+        assert_eq!(scanner.key(&instruments[0]), scanner.key(&instruments[1]));
+
+        let rules = OrbitMatchRules {
+            same_settlement: true,
+            ..Default::default()
+        };
+        let scanner = CrossExchangeScanner::new(&instruments, rules, 0.0);
+        assert_eq!(scanner.contracts(), 0);
+        assert_ne!(scanner.key(&instruments[0]), scanner.key(&instruments[1]));
+    }
+
+    #[test]
+    fn follows_listings_coming_and_going() {
+        let deribit = call(
+            OrbitExchange::Deribit,
+            OrbitCurrency::Btc,
+            dec!(20000),
+            0.0003,
+            0.125,
+        );
+        let delta = call(
+            OrbitExchange::Delta,
+            OrbitCurrency::Usdt,
+            dec!(20000),
+            0.0005,
+            0.1,
+        );
+        let mut scanner = CrossExchangeScanner::new(
+            std::slice::from_ref(&deribit),
+            OrbitMatchRules::default(),
+            0.0,
+        );
+        assert_eq!(scanner.contracts(), 0);
+        scanner.add_listing(&delta);
+        assert_eq!(scanner.contracts(), 1);
+        scanner.remove_listing(&OrbitBookKey::from_instrument(&deribit));
+        assert_eq!(scanner.contracts(), 0);
+    }
+}
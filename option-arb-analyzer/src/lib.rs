@@ -0,0 +1,8 @@
+pub mod basis;
+pub mod cross;
+pub mod parity;
+pub mod pricing;
+pub mod units;
+
+#[cfg(test)]
+mod testing;
@@ -1 +1,168 @@
-fn main() {}
+use std::env;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Error;
+use chrono::Utc;
+use data_streamer::config::OrbitConfig;
+use data_streamer::matching::OrbitMatchRules;
+use data_streamer::replay::OrbitReplay;
+use data_streamer::{
+    OrbitContractType, OrbitCurrency, OrbitData, OrbitEventPayload, OrbitExchange, OrbitLifecycle,
+    OrbitOrderbookStorage, DEFAULT_INSTRUMENT_REFRESH_SECS,
+};
+use log::*;
+use tokio::sync::broadcast::error::RecvError;
+
+use option_arb_analyzer::basis::BasisMonitor;
+use option_arb_analyzer::cross::CrossExchangeScanner;
+use option_arb_analyzer::parity::ParityScanner;
+
+// USD per unit of underlying, override with PARITY_THRESHOLD / CROSS_THRESHOLD
+const DEFAULT_PARITY_THRESHOLD: f64 = 5.0;
+const DEFAULT_CROSS_THRESHOLD: f64 = 0.0;
+// annualized carry, override with CARRY_THRESHOLD
+const DEFAULT_CARRY_THRESHOLD: f64 = 0.05;
+
+fn threshold_from_env(name: &str, default: f64) -> f64 {
+    env::var(name)
+        .ok()
+        .and_then(|threshold| threshold.parse::<f64>().ok())
+        .unwrap_or(default)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    dotenv::dotenv().ok();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let threshold = threshold_from_env("PARITY_THRESHOLD", DEFAULT_PARITY_THRESHOLD);
+
+    let exchanges = vec![OrbitExchange::Delta, OrbitExchange::Deribit];
+    let currencies = vec![OrbitCurrency::Btc, OrbitCurrency::Eth, OrbitCurrency::Sol];
+    let basis_monitor = BasisMonitor::new(
+        exchanges.clone(),
+        threshold_from_env("CARRY_THRESHOLD", DEFAULT_CARRY_THRESHOLD),
+    );
+    let config = OrbitConfig::load()?;
+    let orbit_data = Arc::new(OrbitData::from_config(&config, exchanges, currencies));
+
+    let instruments = orbit_data.get_all_instruments().await?;
+    info!(
+        "scanning {} instruments, threshold {threshold} USD",
+        instruments.len()
+    );
+    let mut scanner = ParityScanner::new(&instruments, threshold);
+    // contracts listed on every exchange, SAME_SETTLEMENT=true keeps inverse and linear
+    // listings apart
+    let rules = OrbitMatchRules {
+        same_settlement: env::var("SAME_SETTLEMENT")
+            .ok()
+            .and_then(|same| same.parse::<bool>().ok())
+            .unwrap_or(false),
+        ..Default::default()
+    };
+    let report = orbit_data.match_instruments(&rules).await?;
+    for near_miss in report.near_misses.iter() {
+        debug!("near miss {near_miss}");
+    }
+    let mut cross_scanner = CrossExchangeScanner::new(
+        &report.instruments(),
+        rules,
+        threshold_from_env("CROSS_THRESHOLD", DEFAULT_CROSS_THRESHOLD),
+    );
+    info!(
+        "watching {} cross exchange contracts",
+        cross_scanner.contracts()
+    );
+    let mut orbit_storage = OrbitOrderbookStorage::new(instruments.clone());
+    // ORBIT_REPLAY_DIR backtests on a recording instead of the live feed, the instrument
+    // list above still comes from the exchanges
+    let mut orbit_rx = match env::var("ORBIT_REPLAY_DIR") {
+        Ok(dir) => {
+            info!("replaying {dir}");
+            OrbitReplay::from_dir(Path::new(&dir), instruments)?.start()
+        }
+        Err(_) => {
+            let orbit_rx = orbit_data.consume_instruments(instruments.clone()).await?;
+            // new listings get subscribed and booked, expired ones dropped, override the
+            // interval with INSTRUMENT_REFRESH_SECS
+            let refresh_secs = env::var("INSTRUMENT_REFRESH_SECS")
+                .ok()
+                .and_then(|secs| secs.parse::<u64>().ok())
+                .unwrap_or(DEFAULT_INSTRUMENT_REFRESH_SECS);
+            orbit_data.spawn_instrument_refresher(instruments, Duration::from_secs(refresh_secs));
+            orbit_rx
+        }
+    };
+
+    loop {
+        let event = match orbit_rx.recv().await {
+            Ok(event) => event,
+            // skipped updates can leave books stale until their next snapshot, keep scanning
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("analyzer fell behind, skipped {skipped} events");
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let listing = match &event.payload {
+            Some(OrbitEventPayload::Lifecycle(lifecycle, instrument)) => {
+                Some((*lifecycle, instrument.clone()))
+            }
+            _ => None,
+        };
+        let update = match orbit_storage.process(event) {
+            Ok(update) => update,
+            Err(err) => {
+                warn!("dropping event: {err}");
+                continue;
+            }
+        };
+        // listings come and go with the instrument refresher
+        match listing {
+            Some((OrbitLifecycle::Listed, instrument)) => {
+                scanner.add_listing(&instrument);
+                cross_scanner.add_listing(&instrument);
+            }
+            Some((OrbitLifecycle::Delisted | OrbitLifecycle::Expired, _)) => {
+                scanner.remove_listing(&update.key);
+                cross_scanner.remove_listing(&update.key);
+            }
+            None => {}
+        }
+        // tickers, trades, funding and listing changes leave the levels alone, nothing to rescan
+        if update.changes.is_empty() && !update.is_snapshot {
+            continue;
+        }
+        let key = &update.key;
+        // an option only moves its own expiry, a forward move reprices the whole chain
+        let expiration = match key.contract_type {
+            OrbitContractType::CallOption | OrbitContractType::PutOption => key.expiration,
+            _ => None,
+        };
+        for violation in scanner.scan(
+            &orbit_storage,
+            &key.exchange,
+            &key.currency,
+            key.settlement,
+            expiration,
+        ) {
+            info!("{violation}");
+        }
+        let contract_key = cross_scanner.contract_key(key);
+        for opportunity in cross_scanner.scan(&orbit_storage, &contract_key) {
+            info!("{opportunity}");
+        }
+        if matches!(
+            key.contract_type,
+            OrbitContractType::Future | OrbitContractType::PerpetualFuture
+        ) {
+            for opportunity in basis_monitor.scan(&orbit_storage, &key.currency, Utc::now()) {
+                info!("{opportunity}");
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,436 @@
+use std::fmt;
+
+use data_streamer::{
+    Expiration, OrbitBookKey, OrbitContractType, OrbitContractTypeOrderbook, OrbitCurrency,
+    OrbitExchange, OrbitInstrument, OrbitOrderbookStorage, OrbitSettlement, OrbitStorageOrderbook,
+    Strike,
+};
+
+use crate::pricing::PremiumConvention;
+use crate::units::{float, float_level, UsdConverter};
+
+// Put-call parity with the forward leg taken from the same venue:
+//   C - P = F - K          (all legs in USD per unit of underlying)
+// conversion: sell call, buy put, buy forward -> edge = (C_bid - P_ask) - (F_ask - K)
+// reversal:   buy call, sell put, sell forward -> edge = (F_bid - K) - (C_ask - P_bid)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParityTrade {
+    Conversion,
+    Reversal,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForwardSource {
+    Future,
+    Perpetual,
+}
+
+#[derive(Clone, Debug)]
+pub struct Leg {
+    pub side: Side,
+    // USD per unit of underlying, the raw book price for coin quoted premiums is in native
+    pub price: f64,
+    pub native_price: f64,
+    pub size: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct ParityViolation {
+    pub exchange: OrbitExchange,
+    pub currency: OrbitCurrency,
+    pub settlement: OrbitSettlement,
+    pub expiration: Expiration,
+    pub strike: Strike,
+    pub trade: ParityTrade,
+    pub forward_source: ForwardSource,
+    pub call: Leg,
+    pub put: Leg,
+    pub forward: Leg,
+    pub edge: f64,
+    pub size: f64,
+}
+
+impl fmt::Display for ParityViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} {:?} {:?} {:?} {} K={} {:?}: call {:?} {}@{} ({}), put {:?} {}@{} ({}), {:?} {:?} {}@{}, edge {:.4} USD x {}",
+            self.trade,
+            self.exchange,
+            self.currency,
+            self.settlement,
+            self.expiration.date_naive(),
+            self.strike,
+            self.forward_source,
+            self.call.side,
+            self.call.size,
+            self.call.price,
+            self.call.native_price,
+            self.put.side,
+            self.put.size,
+            self.put.price,
+            self.put.native_price,
+            self.forward.side,
+            self.forward_source,
+            self.forward.size,
+            self.forward.price,
+            self.edge,
+            self.size,
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ParityScanner {
+    converter: UsdConverter,
+    // minimum edge in USD per unit of underlying before a violation is reported
+    pub threshold: f64,
+}
+
+impl ParityScanner {
+    pub fn new(instruments: &[OrbitInstrument], threshold: f64) -> Self {
+        Self {
+            converter: UsdConverter::new(instruments),
+            threshold,
+        }
+    }
+
+    // follows the listings of the instrument refresher, options of a strike are only
+    // checked while both the call and the put are listed
+    pub fn add_listing(&mut self, instrument: &OrbitInstrument) {
+        self.converter.add(instrument);
+    }
+
+    pub fn remove_listing(&mut self, key: &OrbitBookKey) {
+        self.converter.remove(key);
+    }
+
+    // scans the books of one (exchange, currency, settlement), optionally limited to a
+    // single expiration, options are hedged with a forward settled like them
+    pub fn scan(
+        &self,
+        storage: &OrbitOrderbookStorage,
+        exchange: &OrbitExchange,
+        currency: &OrbitCurrency,
+        settlement: OrbitSettlement,
+        expiration: Option<Expiration>,
+    ) -> Vec<ParityViolation> {
+        let mut violations = vec![];
+        let Some(contract_types) =
+            storage
+                .storage
+                .get(&(exchange.clone(), currency.clone(), settlement))
+        else {
+            return violations;
+        };
+        let Some(OrbitContractTypeOrderbook::Option(options)) = &contract_types[1] else {
+            return violations;
+        };
+        let futures = match &contract_types[0] {
+            Some(OrbitContractTypeOrderbook::Future(futures)) => Some(futures),
+            _ => None,
+        };
+        let perpetual = match &contract_types[2] {
+            Some(OrbitContractTypeOrderbook::Perpetual(perpetual)) => Some(perpetual),
+            _ => None,
+        };
+
+        for (option_expiration, strikes) in options.iter() {
+            if expiration.is_some_and(|e| e != *option_expiration) {
+                continue;
+            }
+            // prefer the dated future expiring with the options, the perp is only a proxy
+            let forward = futures
+                .and_then(|f| f.get(option_expiration))
+                .filter(|book| book.best_bid().is_some() && book.best_ask().is_some())
+                .map(|book| (ForwardSource::Future, book))
+                .or_else(|| perpetual.map(|book| (ForwardSource::Perpetual, book)));
+            let Some((forward_source, forward)) = forward else {
+                continue;
+            };
+            for (strike, option) in strikes.iter() {
+                violations.extend(self.check(
+                    exchange,
+                    currency,
+                    settlement,
+                    *option_expiration,
+                    *strike,
+                    option.calls(),
+                    option.puts(),
+                    forward_source,
+                    forward,
+                ));
+            }
+        }
+        violations
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn check(
+        &self,
+        exchange: &OrbitExchange,
+        currency: &OrbitCurrency,
+        settlement: OrbitSettlement,
+        expiration: Expiration,
+        strike: Strike,
+        calls: &OrbitStorageOrderbook,
+        puts: &OrbitStorageOrderbook,
+        forward_source: ForwardSource,
+        forward: &OrbitStorageOrderbook,
+    ) -> Vec<ParityViolation> {
+        let mut violations = vec![];
+        let Some(forward_mid) = forward.mid().map(float) else {
+            return violations;
+        };
+        let convention = |contract_type| {
+            self.converter.convention(&OrbitBookKey::new(
+                exchange.clone(),
+                currency.clone(),
+                settlement,
+                contract_type,
+                Some(expiration),
+                Some(strike),
+            ))
+        };
+        let (Some(call_convention), Some(put_convention)) = (
+            convention(OrbitContractType::CallOption),
+            convention(OrbitContractType::PutOption),
+        ) else {
+            return violations;
+        };
+        let k = float(strike);
+        // coin premiums are valued at the forward of the same venue
+        let leg = |convention: PremiumConvention, side, level| {
+            let (price, size) = float_level(level);
+            Leg {
+                side,
+                price: convention.to_usd(price, forward_mid),
+                native_price: price,
+                size,
+            }
+        };
+
+        if let (Some(call_bid), Some(put_ask), Some(forward_ask)) = (
+            calls.best_bid(),
+            puts.best_ask(),
+            forward.best_ask().map(float_level),
+        ) {
+            let call = leg(call_convention, Side::Sell, call_bid);
+            let put = leg(put_convention, Side::Buy, put_ask);
+            let edge = (call.price - put.price) - (forward_ask.0 - k);
+            if edge > self.threshold {
+                violations.push(ParityViolation {
+                    exchange: exchange.clone(),
+                    currency: currency.clone(),
+                    settlement,
+                    expiration,
+                    strike,
+                    trade: ParityTrade::Conversion,
+                    forward_source,
+                    size: call.size.min(put.size).min(forward_ask.1),
+                    call,
+                    put,
+                    forward: Leg {
+                        side: Side::Buy,
+                        price: forward_ask.0,
+                        native_price: forward_ask.0,
+                        size: forward_ask.1,
+                    },
+                    edge,
+                });
+            }
+        }
+
+        if let (Some(call_ask), Some(put_bid), Some(forward_bid)) = (
+            calls.best_ask(),
+            puts.best_bid(),
+            forward.best_bid().map(float_level),
+        ) {
+            let call = leg(call_convention, Side::Buy, call_ask);
+            let put = leg(put_convention, Side::Sell, put_bid);
+            let edge = (forward_bid.0 - k) - (call.price - put.price);
+            if edge > self.threshold {
+                violations.push(ParityViolation {
+                    exchange: exchange.clone(),
+                    currency: currency.clone(),
+                    settlement,
+                    expiration,
+                    strike,
+                    trade: ParityTrade::Reversal,
+                    forward_source,
+                    size: call.size.min(put.size).min(forward_bid.1),
+                    call,
+                    put,
+                    forward: Leg {
+                        side: Side::Sell,
+                        price: forward_bid.0,
+                        native_price: forward_bid.0,
+                        size: forward_bid.1,
+                    },
+                    edge,
+                });
+            }
+        }
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use data_streamer::{OrbitContractType, OrbitCurrency, OrbitExchange, OrbitInstrument};
+    use rust_decimal::Decimal;
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::testing::{instrument, quote, storage};
+
+    // the call is 100 USD rich against the put with the forward mid on the strike, a
+    // conversion earns that less the 5 paid over the mid for the forward
+    fn scan(
+        exchange: OrbitExchange,
+        option_quote: OrbitCurrency,
+        call: (Decimal, Decimal),
+        put: (Decimal, Decimal),
+        threshold: f64,
+    ) -> Vec<ParityViolation> {
+        let option = |contract_type| {
+            instrument(
+                exchange.clone(),
+                option_quote.clone(),
+                contract_type,
+                Some(dec!(20000)),
+            )
+        };
+        let instruments: Vec<OrbitInstrument> = vec![
+            option(OrbitContractType::CallOption),
+            option(OrbitContractType::PutOption),
+            instrument(
+                exchange.clone(),
+                option_quote.clone(),
+                OrbitContractType::Future,
+                None,
+            ),
+        ];
+        let mut storage = storage(&instruments);
+        quote(&mut storage, &instruments[0], call.0, call.1);
+        quote(&mut storage, &instruments[1], put.0, put.1);
+        quote(&mut storage, &instruments[2], dec!(19995), dec!(20005));
+        let settlement = instruments[0].settlement();
+        ParityScanner::new(&instruments, threshold).scan(
+            &storage,
+            &exchange,
+            &OrbitCurrency::Btc,
+            settlement,
+            None,
+        )
+    }
+
+    #[test]
+    fn reports_violations_above_the_threshold() {
+        let violations = scan(
+            OrbitExchange::Delta,
+            OrbitCurrency::Usdt,
+            (dec!(600), dec!(610)),
+            (dec!(490), dec!(500)),
+            50.0,
+        );
+        assert_eq!(violations.len(), 1);
+        let violation = &violations[0];
+        assert_eq!(violation.trade, ParityTrade::Conversion);
+        assert_eq!(violation.forward_source, ForwardSource::Future);
+        assert_eq!(violation.strike, dec!(20000));
+        assert!((violation.edge - 95.0).abs() < 1e-9);
+        assert_eq!(violation.size, 1.0);
+
+        let violations = scan(
+            OrbitExchange::Delta,
+            OrbitCurrency::Usdt,
+            (dec!(600), dec!(610)),
+            (dec!(490), dec!(500)),
+            100.0,
+        );
+        assert!(violations.is_empty());
+    }
+
+    #[test]
+    fn values_coin_premiums_at_the_forward() {
+        // the same prices in BTC at a forward mid of 20000
+        let violations = scan(
+            OrbitExchange::Deribit,
+            OrbitCurrency::Btc,
+            (dec!(0.03), dec!(0.0305)),
+            (dec!(0.0245), dec!(0.025)),
+            50.0,
+        );
+        assert_eq!(violations.len(), 1);
+        let call = &violations[0].call;
+        assert_eq!((call.native_price, call.price), (0.03, 600.0));
+        assert!((violations[0].edge - 95.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn follows_listings_coming_and_going() {
+        let option = |contract_type| {
+            instrument(
+                OrbitExchange::Delta,
+                OrbitCurrency::Usdt,
+                contract_type,
+                Some(dec!(20000)),
+            )
+        };
+        let (call, put) = (
+            option(OrbitContractType::CallOption),
+            option(OrbitContractType::PutOption),
+        );
+        let future = instrument(
+            OrbitExchange::Delta,
+            OrbitCurrency::Usdt,
+            OrbitContractType::Future,
+            None,
+        );
+        let mut storage = storage(&[call.clone(), put.clone(), future.clone()]);
+        quote(&mut storage, &call, dec!(600), dec!(610));
+        quote(&mut storage, &put, dec!(490), dec!(500));
+        quote(&mut storage, &future, dec!(19995), dec!(20005));
+        let scan = |scanner: &ParityScanner| {
+            scanner.scan(
+                &storage,
+                &OrbitExchange::Delta,
+                &OrbitCurrency::Btc,
+                OrbitSettlement::Linear,
+                None,
+            )
+        };
+
+        // the options were listed after the scanner started
+        let mut scanner = ParityScanner::new(std::slice::from_ref(&future), 50.0);
+        assert!(scan(&scanner).is_empty());
+        scanner.add_listing(&call);
+        assert!(scan(&scanner).is_empty());
+        scanner.add_listing(&put);
+        assert_eq!(scan(&scanner).len(), 1);
+        scanner.remove_listing(&OrbitBookKey::from_instrument(&put));
+        assert!(scan(&scanner).is_empty());
+    }
+
+    #[test]
+    fn reports_reversals_when_the_put_is_rich() {
+        let violations = scan(
+            OrbitExchange::Delta,
+            OrbitCurrency::Usdt,
+            (dec!(490), dec!(500)),
+            (dec!(600), dec!(610)),
+            50.0,
+        );
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].trade, ParityTrade::Reversal);
+        // (19995 - 20000) - (500 - 600)
+        assert!((violations[0].edge - 95.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,457 @@
+use std::f64::consts::PI;
+
+use chrono::{DateTime, Utc};
+use data_streamer::{OrbitCurrency, OrbitStorageOrderbook, OrbitTicker};
+
+use crate::units::float;
+
+const YEAR_SECONDS: f64 = 365.0 * 24.0 * 60.0 * 60.0;
+const MIN_VOL: f64 = 1e-4;
+const MAX_VOL: f64 = 10.0;
+const IV_TOLERANCE: f64 = 1e-10;
+const IV_MAX_ITERATIONS: usize = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OptionKind {
+    Call,
+    Put,
+}
+
+// Linear premiums are paid in USD or a stablecoin (Delta's USDT options, Deribit's USDC
+// ones), inverse premiums in the underlying coin (Deribit's BTC and ETH options), so a
+// coin premium is the USD premium / F.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PremiumConvention {
+    Linear,
+    Inverse,
+}
+
+impl PremiumConvention {
+    // None for premiums in a coin other than the underlying
+    pub fn for_quote(quote: &OrbitCurrency, underlying: &OrbitCurrency) -> Option<Self> {
+        if quote.is_usd() {
+            Some(PremiumConvention::Linear)
+        } else if quote == underlying {
+            Some(PremiumConvention::Inverse)
+        } else {
+            None
+        }
+    }
+
+    pub fn to_usd(&self, premium: f64, forward: f64) -> f64 {
+        match self {
+            PremiumConvention::Linear => premium,
+            PremiumConvention::Inverse => premium * forward,
+        }
+    }
+
+    pub fn from_usd(&self, premium_usd: f64, forward: f64) -> f64 {
+        match self {
+            PremiumConvention::Linear => premium_usd,
+            PremiumConvention::Inverse => premium_usd / forward,
+        }
+    }
+}
+
+// delta and gamma are with respect to the forward, vega per 1.00 of volatility and
+// theta per calendar day, all in USD per unit of underlying
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Greeks {
+    pub delta: f64,
+    pub gamma: f64,
+    pub vega: f64,
+    pub theta: f64,
+}
+
+// Black-76 on the forward. Black-Scholes on spot is the same model once the spot is
+// carried to the forward, see Black76::from_spot.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Black76 {
+    pub forward: f64,
+    pub strike: f64,
+    // years to expiry
+    pub time: f64,
+    // continuously compounded discount rate
+    pub rate: f64,
+}
+
+impl Black76 {
+    pub fn new(forward: f64, strike: f64, time: f64, rate: f64) -> Self {
+        Self {
+            forward,
+            strike,
+            time,
+            rate,
+        }
+    }
+
+    pub fn from_spot(spot: f64, strike: f64, time: f64, rate: f64, dividend: f64) -> Self {
+        Self::new(spot * ((rate - dividend) * time).exp(), strike, time, rate)
+    }
+
+    pub fn discount(&self) -> f64 {
+        (-self.rate * self.time).exp()
+    }
+
+    fn d1_d2(&self, vol: f64) -> (f64, f64) {
+        let std_dev = vol * self.time.sqrt();
+        let d1 = ((self.forward / self.strike).ln() + 0.5 * std_dev * std_dev) / std_dev;
+        (d1, d1 - std_dev)
+    }
+
+    fn intrinsic(&self, kind: OptionKind) -> f64 {
+        let df = self.discount();
+        match kind {
+            OptionKind::Call => df * (self.forward - self.strike).max(0.0),
+            OptionKind::Put => df * (self.strike - self.forward).max(0.0),
+        }
+    }
+
+    // upper no-arbitrage bound, the discounted forward for calls and discounted strike for puts
+    fn upper_bound(&self, kind: OptionKind) -> f64 {
+        let df = self.discount();
+        match kind {
+            OptionKind::Call => df * self.forward,
+            OptionKind::Put => df * self.strike,
+        }
+    }
+
+    // USD price per unit of underlying
+    pub fn price(&self, kind: OptionKind, vol: f64) -> f64 {
+        if self.time <= 0.0 || vol <= 0.0 {
+            return self.intrinsic(kind);
+        }
+        let df = self.discount();
+        let (d1, d2) = self.d1_d2(vol);
+        match kind {
+            OptionKind::Call => df * (self.forward * norm_cdf(d1) - self.strike * norm_cdf(d2)),
+            OptionKind::Put => df * (self.strike * norm_cdf(-d2) - self.forward * norm_cdf(-d1)),
+        }
+    }
+
+    pub fn price_in(&self, kind: OptionKind, vol: f64, convention: PremiumConvention) -> f64 {
+        convention.from_usd(self.price(kind, vol), self.forward)
+    }
+
+    pub fn vega(&self, vol: f64) -> f64 {
+        if self.time <= 0.0 || vol <= 0.0 {
+            return 0.0;
+        }
+        let (d1, _) = self.d1_d2(vol);
+        self.discount() * self.forward * norm_pdf(d1) * self.time.sqrt()
+    }
+
+    // for inverse options the delta is premium adjusted, hedging a coin premium means
+    // part of the exposure is already the premium itself
+    pub fn greeks(&self, kind: OptionKind, vol: f64, convention: PremiumConvention) -> Greeks {
+        if self.time <= 0.0 || vol <= 0.0 {
+            return Greeks::default();
+        }
+        let df = self.discount();
+        let sqrt_t = self.time.sqrt();
+        let (d1, _) = self.d1_d2(vol);
+        let price = self.price(kind, vol);
+        let delta = match kind {
+            OptionKind::Call => df * norm_cdf(d1),
+            OptionKind::Put => -df * norm_cdf(-d1),
+        };
+        let delta = match convention {
+            PremiumConvention::Linear => delta,
+            PremiumConvention::Inverse => delta - price / self.forward,
+        };
+        let gamma = df * norm_pdf(d1) / (self.forward * vol * sqrt_t);
+        let theta_year =
+            -df * self.forward * norm_pdf(d1) * vol / (2.0 * sqrt_t) + self.rate * price;
+        Greeks {
+            delta,
+            gamma,
+            vega: self.vega(vol),
+            theta: theta_year / 365.0,
+        }
+    }
+
+    // Newton on vega inside a bisection bracket, any Newton step that leaves the bracket
+    // or stalls on a flat vega falls back to bisecting. None when the premium is outside
+    // the no-arbitrage bounds of the model.
+    pub fn implied_volatility(&self, kind: OptionKind, premium_usd: f64) -> Option<f64> {
+        if self.time <= 0.0 || !premium_usd.is_finite() {
+            return None;
+        }
+        if premium_usd <= self.intrinsic(kind) || premium_usd >= self.upper_bound(kind) {
+            return None;
+        }
+
+        let (mut lo, mut hi) = (MIN_VOL, MAX_VOL);
+        if self.price(kind, lo) > premium_usd || self.price(kind, hi) < premium_usd {
+            return None;
+        }
+        // Brenner-Subrahmanyam, good near the money and a sane start elsewhere
+        let mut vol = ((2.0 * PI / self.time).sqrt() * premium_usd
+            / (self.discount() * self.forward))
+            .clamp(lo, hi);
+
+        for _ in 0..IV_MAX_ITERATIONS {
+            let diff = self.price(kind, vol) - premium_usd;
+            if diff.abs() < IV_TOLERANCE {
+                return Some(vol);
+            }
+            if diff > 0.0 {
+                hi = vol;
+            } else {
+                lo = vol;
+            }
+            let vega = self.vega(vol);
+            let newton = vol - diff / vega;
+            vol = if vega > f64::EPSILON && newton > lo && newton < hi {
+                newton
+            } else {
+                0.5 * (lo + hi)
+            };
+            if hi - lo < IV_TOLERANCE {
+                return Some(vol);
+            }
+        }
+        Some(vol)
+    }
+
+    pub fn implied_volatility_in(
+        &self,
+        kind: OptionKind,
+        premium: f64,
+        convention: PremiumConvention,
+    ) -> Option<f64> {
+        self.implied_volatility(kind, convention.to_usd(premium, self.forward))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QuoteVols {
+    pub bid: Option<f64>,
+    pub ask: Option<f64>,
+    pub mid: Option<f64>,
+}
+
+impl QuoteVols {
+    // implied vols of the top of book of one side of an option orderbook, prices are taken
+    // in the venue's premium convention
+    pub fn from_book(
+        model: &Black76,
+        kind: OptionKind,
+        book: &OrbitStorageOrderbook,
+        convention: PremiumConvention,
+    ) -> Self {
+        let iv = |premium| model.implied_volatility_in(kind, float(premium), convention);
+        Self {
+            bid: book.best_bid().and_then(|(price, _)| iv(price)),
+            ask: book.best_ask().and_then(|(price, _)| iv(price)),
+            mid: book.mid().and_then(iv),
+        }
+    }
+
+    // the vols the venue quotes itself, its mark iv stands in for the mid
+    pub fn from_ticker(ticker: &OrbitTicker) -> Self {
+        Self {
+            bid: ticker.bid_iv,
+            ask: ticker.ask_iv,
+            mid: ticker.mark_iv,
+        }
+    }
+
+    // field by field self - other, e.g. our solved vols against the venue's
+    pub fn diff(&self, other: &QuoteVols) -> QuoteVols {
+        let diff = |a: Option<f64>, b: Option<f64>| a.zip(b).map(|(a, b)| a - b);
+        Self {
+            bid: diff(self.bid, other.bid),
+            ask: diff(self.ask, other.ask),
+            mid: diff(self.mid, other.mid),
+        }
+    }
+}
+
+pub fn year_fraction(now: DateTime<Utc>, expiration: DateTime<Utc>) -> f64 {
+    (expiration - now).num_milliseconds() as f64 / 1000.0 / YEAR_SECONDS
+}
+
+pub fn norm_pdf(x: f64) -> f64 {
+    (-0.5 * x * x).exp() / (2.0 * PI).sqrt()
+}
+
+// Hart (1968) double precision approximation as given by West, "Better approximations
+// to cumulative normal functions" (2005), accurate to ~1e-14 which the IV solver needs
+pub fn norm_cdf(x: f64) -> f64 {
+    let z = x.abs();
+    let tail = if z > 37.0 {
+        0.0
+    } else {
+        let e = (-0.5 * z * z).exp();
+        if z < 7.071_067_811_865_47 {
+            let num = ((((((0.035_262_496_599_891_1 * z + 0.700_383_064_443_688) * z
+                + 6.373_962_203_531_65)
+                * z
+                + 33.912_866_078_383)
+                * z
+                + 112.079_291_497_871)
+                * z
+                + 221.213_596_169_931)
+                * z
+                + 220.206_867_912_376)
+                * e;
+            let den = ((((((0.088_388_347_648_318_4 * z + 1.755_667_163_182_64) * z
+                + 16.064_177_579_207)
+                * z
+                + 86.780_732_202_946_1)
+                * z
+                + 296.564_248_779_674)
+                * z
+                + 637.333_633_378_831)
+                * z
+                + 793.826_512_519_948)
+                * z
+                + 440.413_735_824_752;
+            num / den
+        } else {
+            let b = z + 1.0 / (z + 2.0 / (z + 3.0 / (z + 4.0 / (z + 0.65))));
+            e / b / (2.0 * PI).sqrt()
+        }
+    };
+    if x > 0.0 {
+        1.0 - tail
+    } else {
+        tail
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VOL: f64 = 0.6;
+
+    #[test]
+    fn prices_the_reference_example() {
+        // Haug, The Complete Guide to Option Pricing Formulas, Black-76 example
+        let model = Black76::new(19.0, 19.0, 0.75, 0.1);
+        assert!((model.price(OptionKind::Call, 0.28) - 1.7011).abs() < 1e-4);
+        assert!((model.price(OptionKind::Put, 0.28) - 1.7011).abs() < 1e-4);
+    }
+
+    #[test]
+    fn has_the_greeks_of_the_reference_example() {
+        // the same example, greeks from the Black-76 closed forms
+        let model = Black76::new(19.0, 19.0, 0.75, 0.1);
+        let call = model.greeks(OptionKind::Call, 0.28, PremiumConvention::Linear);
+        let put = model.greeks(OptionKind::Put, 0.28, PremiumConvention::Linear);
+        assert!((call.delta - 0.508636).abs() < 1e-6);
+        assert!((put.delta + 0.419107).abs() < 1e-6);
+        assert!((call.gamma - 0.079745).abs() < 1e-6);
+        assert!((put.gamma - call.gamma).abs() < 1e-12);
+        assert!((call.vega - 6.045471).abs() < 1e-6);
+        // per calendar day, calls and puts on the forward decay alike at the money
+        assert!((call.theta + 0.0026257).abs() < 1e-7);
+        assert!((put.theta - call.theta).abs() < 1e-12);
+
+        // a coin premium takes premium / F off the delta
+        let call = model.greeks(OptionKind::Call, 0.28, PremiumConvention::Inverse);
+        let put = model.greeks(OptionKind::Put, 0.28, PremiumConvention::Inverse);
+        assert!((call.delta - 0.419107).abs() < 1e-6);
+        assert!((put.delta + 0.508636).abs() < 1e-6);
+    }
+
+    #[test]
+    fn deltas_satisfy_put_call_parity() {
+        for strike in [8000.0, 16000.0, 20000.0, 24000.0, 40000.0] {
+            let model = Black76::new(20000.0, strike, 0.5, 0.05);
+            let delta = |kind, convention| model.greeks(kind, VOL, convention).delta;
+            let linear = delta(OptionKind::Call, PremiumConvention::Linear)
+                - delta(OptionKind::Put, PremiumConvention::Linear);
+            assert!((linear - model.discount()).abs() < 1e-12, "K={strike}");
+            // coin premiums differ by df (F - K) / F, which leaves df K / F
+            let inverse = delta(OptionKind::Call, PremiumConvention::Inverse)
+                - delta(OptionKind::Put, PremiumConvention::Inverse);
+            let expected = model.discount() * strike / 20000.0;
+            assert!((inverse - expected).abs() < 1e-12, "K={strike}");
+        }
+    }
+
+    #[test]
+    fn gamma_is_the_slope_of_delta() {
+        let bump = 1.0;
+        for (kind, strike) in [
+            (OptionKind::Call, 16000.0),
+            (OptionKind::Call, 24000.0),
+            (OptionKind::Put, 20000.0),
+        ] {
+            let delta = |forward| {
+                Black76::new(forward, strike, 0.25, 0.05)
+                    .greeks(kind, VOL, PremiumConvention::Linear)
+                    .delta
+            };
+            let gamma = Black76::new(20000.0, strike, 0.25, 0.05)
+                .greeks(kind, VOL, PremiumConvention::Linear)
+                .gamma;
+            let slope = (delta(20000.0 + bump) - delta(20000.0 - bump)) / (2.0 * bump);
+            assert!(
+                (slope - gamma).abs() < 1e-9,
+                "{kind:?} K={strike}: {slope} {gamma}"
+            );
+        }
+    }
+
+    #[test]
+    fn solves_back_the_vol_it_priced_with() {
+        let forward = 20000.0;
+        let cases = [
+            // in the money, out of the money, deep out of the money, a day to expiry
+            (OptionKind::Call, 16000.0, 0.25),
+            (OptionKind::Put, 24000.0, 0.25),
+            (OptionKind::Call, 24000.0, 0.25),
+            (OptionKind::Put, 16000.0, 0.25),
+            (OptionKind::Call, 40000.0, 0.25),
+            (OptionKind::Put, 8000.0, 0.25),
+            (OptionKind::Call, 20500.0, 1.0 / 365.0),
+            (OptionKind::Put, 19500.0, 1.0 / 365.0),
+        ];
+        for (kind, strike, time) in cases {
+            let model = Black76::new(forward, strike, time, 0.05);
+            for convention in [PremiumConvention::Linear, PremiumConvention::Inverse] {
+                let premium = model.price_in(kind, VOL, convention);
+                let iv = model
+                    .implied_volatility_in(kind, premium, convention)
+                    .unwrap();
+                assert!(
+                    (iv - VOL).abs() < 1e-6,
+                    "{kind:?} K={strike} T={time} {convention:?}: {iv}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn has_no_vol_outside_the_bounds() {
+        let model = Black76::new(20000.0, 16000.0, 0.25, 0.05);
+        let intrinsic = model.discount() * 4000.0;
+        assert_eq!(
+            model.implied_volatility(OptionKind::Call, intrinsic * 0.99),
+            None
+        );
+        assert_eq!(model.implied_volatility(OptionKind::Call, intrinsic), None);
+        let upper = model.discount() * 20000.0;
+        assert_eq!(model.implied_volatility(OptionKind::Call, upper), None);
+        assert_eq!(
+            model.implied_volatility(OptionKind::Call, upper * 1.01),
+            None
+        );
+        assert_eq!(model.implied_volatility(OptionKind::Put, -1.0), None);
+    }
+
+    #[test]
+    fn prices_satisfy_put_call_parity() {
+        for strike in [8000.0, 16000.0, 20000.0, 24000.0, 40000.0] {
+            let model = Black76::new(20000.0, strike, 0.5, 0.05);
+            let call = model.price(OptionKind::Call, VOL);
+            let put = model.price(OptionKind::Put, VOL);
+            let forward_value = model.discount() * (20000.0 - strike);
+            assert!((call - put - forward_value).abs() < 1e-8, "K={strike}");
+        }
+    }
+}
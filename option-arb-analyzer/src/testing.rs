@@ -0,0 +1,101 @@
+// storage fixtures for the scanner tests
+use chrono::{DateTime, TimeZone, Utc};
+use data_streamer::{
+    OrbitContractSpecs, OrbitContractType, OrbitCurrency, OrbitEvent, OrbitEventPayload,
+    OrbitExchange, OrbitInstrument, OrbitOrderbookStorage, OrbitSettlement, OrderbookUpdate,
+    OrderbookUpdateLevel, OrderbookUpdateType, Price,
+};
+use rust_decimal::Decimal;
+
+// 2022-12-30 08:00 UTC
+pub fn expiration() -> DateTime<Utc> {
+    Utc.timestamp_millis_opt(1672387200000).unwrap()
+}
+
+// options and futures expire on expiration(), options are quoted in quote and futures and
+// perps in USD, all of them inverse when quote is the underlying
+pub fn instrument(
+    exchange: OrbitExchange,
+    quote: OrbitCurrency,
+    contract_type: OrbitContractType,
+    strike: Option<Decimal>,
+) -> OrbitInstrument {
+    let settlement = match quote {
+        OrbitCurrency::Btc => OrbitSettlement::Inverse,
+        _ => OrbitSettlement::Linear,
+    };
+    let quote = match contract_type {
+        OrbitContractType::Future | OrbitContractType::PerpetualFuture => OrbitCurrency::Usd,
+        _ => quote,
+    };
+    let symbol = format!("{exchange:?}-{contract_type:?}-{quote:?}-{strike:?}");
+    let instrument = OrbitInstrument::new(
+        exchange,
+        symbol,
+        OrbitCurrency::Btc,
+        quote,
+        contract_type.clone(),
+    )
+    .with_settlement(settlement);
+    let instrument = match strike {
+        Some(strike) => instrument.with_strike(strike),
+        None => instrument,
+    };
+    match contract_type {
+        OrbitContractType::PerpetualFuture => instrument,
+        _ => instrument.with_expiration(expiration()),
+    }
+}
+
+// the taker fee and the option fee cap the venue lists for the contract
+pub fn with_fees(
+    instrument: OrbitInstrument,
+    taker_fee: f64,
+    premium_fee_cap: Option<f64>,
+) -> OrbitInstrument {
+    let specs = OrbitContractSpecs {
+        taker_fee: Some(taker_fee),
+        premium_fee_cap,
+        ..instrument.specs().clone()
+    };
+    instrument.with_specs(specs)
+}
+
+pub fn storage(instruments: &[OrbitInstrument]) -> OrbitOrderbookStorage {
+    OrbitOrderbookStorage::new(instruments.to_vec())
+}
+
+// replaces the book with a single level on each side, the size is 1
+pub fn quote(
+    storage: &mut OrbitOrderbookStorage,
+    instrument: &OrbitInstrument,
+    bid: Price,
+    ask: Price,
+) {
+    let level = |price| OrderbookUpdateLevel(OrderbookUpdateType::New, price, Decimal::ONE);
+    let update = OrderbookUpdate {
+        is_snapshot: true,
+        timestamp: 1,
+        bids: vec![level(bid)],
+        asks: vec![level(ask)],
+    };
+    send(
+        storage,
+        instrument,
+        OrbitEventPayload::OrderbookUpdate(update),
+    );
+}
+
+pub fn send(
+    storage: &mut OrbitOrderbookStorage,
+    instrument: &OrbitInstrument,
+    payload: OrbitEventPayload,
+) {
+    let event = OrbitEvent::for_instrument(
+        instrument.exchange().clone(),
+        instrument.symbol().to_string(),
+        Some(instrument),
+        payload,
+    );
+    storage.process(event).unwrap();
+}
@@ -0,0 +1,102 @@
+use std::collections::HashMap;
+
+use data_streamer::{
+    Amount, OrbitBookKey, OrbitContractType, OrbitCurrency, OrbitInstrument, OrbitOrderbookStorage,
+    Price,
+};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
+
+use crate::pricing::PremiumConvention;
+
+// the storage keeps prices and sizes as exact decimals, the analysis runs on floats
+pub fn float(value: Decimal) -> f64 {
+    value.to_f64().unwrap_or_default()
+}
+
+pub fn float_level((price, size): (Price, Amount)) -> (f64, f64) {
+    (float(price), float(size))
+}
+
+// Book prices in USD per unit of underlying, whatever the venue quotes them in. Prices in
+// USD or a stablecoin are taken at par, prices in the underlying coin (Deribit's inverse
+// options) are valued at a reference price.
+#[derive(Clone, Debug, Default)]
+pub struct UsdConverter {
+    // quote currency of every listed book
+    quotes: HashMap<OrbitBookKey, OrbitCurrency>,
+}
+
+impl UsdConverter {
+    pub fn new(instruments: &[OrbitInstrument]) -> Self {
+        let mut converter = Self::default();
+        for instrument in instruments.iter() {
+            converter.add(instrument);
+        }
+        converter
+    }
+
+    pub fn add(&mut self, instrument: &OrbitInstrument) {
+        self.quotes.insert(
+            OrbitBookKey::from_instrument(instrument),
+            instrument.quote().clone(),
+        );
+    }
+
+    pub fn remove(&mut self, key: &OrbitBookKey) {
+        self.quotes.remove(key);
+    }
+
+    pub fn quote(&self, key: &OrbitBookKey) -> Option<&OrbitCurrency> {
+        self.quotes.get(key)
+    }
+
+    // None when the book's quote currency is unknown or a coin other than its own
+    pub fn convention(&self, key: &OrbitBookKey) -> Option<PremiumConvention> {
+        PremiumConvention::for_quote(self.quote(key)?, &key.currency)
+    }
+
+    pub fn to_usd(&self, key: &OrbitBookKey, price: f64, reference: f64) -> Option<f64> {
+        Some(self.convention(key)?.to_usd(price, reference))
+    }
+
+    // a level of the book valued at the storage's reference price of its currency
+    pub fn level_to_usd(
+        &self,
+        storage: &OrbitOrderbookStorage,
+        key: &OrbitBookKey,
+        level: (Price, Amount),
+    ) -> Option<(f64, f64)> {
+        let reference = float(storage.reference_price(&key.exchange, &key.currency)?);
+        let (price, size) = float_level(level);
+        Some((self.to_usd(key, price, reference)?, size))
+    }
+}
+
+// mid of the future of the book's venue and settlement expiring on its day, falling back
+// to the perpetual
+pub fn forward_price(storage: &OrbitOrderbookStorage, key: &OrbitBookKey) -> Option<f64> {
+    storage
+        .get_orderbook(
+            &key.exchange,
+            &key.currency,
+            key.settlement,
+            &OrbitContractType::Future,
+            key.expiration,
+            None,
+        )
+        .and_then(|book| book.mid())
+        .or_else(|| {
+            storage
+                .get_orderbook(
+                    &key.exchange,
+                    &key.currency,
+                    key.settlement,
+                    &OrbitContractType::PerpetualFuture,
+                    None,
+                    None,
+                )
+                .and_then(|book| book.mid())
+        })
+        .map(float)
+}